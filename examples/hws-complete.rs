@@ -193,7 +193,6 @@ port = 5678
 host = "127.0.0.1"
 http-mode = "http1-only"
 backlog = 256
-scale = 2
 signature = "IPv4"
 reuse-addr = true
 
@@ -202,7 +201,6 @@ port = 5678
 host = "::1"
 http-mode = "http1-only"
 backlog = 256
-scale = 2
 only-v6 = true
 signature = "IPv6"
 max-conn = 20
@@ -212,8 +210,6 @@ reuse-addr = true
 # This one will be rejected on Windows, because it'll turn off the unix domain socket support.
 path = "/tmp/hws.socket"
 http-mode = "http1-only"
-backlog = 256
-scale = 2
 error-sleep = "100ms"
 
 [ui]
@@ -16,7 +16,7 @@ use spirit::prelude::*;
 use spirit_hyper::{BuildServer, HttpServer};
 use spirit_tokio::Runtime;
 
-#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Hash, Serialize)]
 struct Signature {
     signature: Option<String>,
 }
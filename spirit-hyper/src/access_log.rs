@@ -0,0 +1,128 @@
+//! Logging of the handled requests.
+//!
+//! One line is logged per request, once the whole response body has been sent (or abandoned).
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::{Body, Chunk, Method, Request, Response, StatusCode, Uri};
+use log::{log, Level};
+
+use crate::payload;
+use crate::ResponseFuture;
+
+/// What is known about the request before the response is produced.
+pub(crate) struct Entry {
+    level: Level,
+    target: String,
+    method: Method,
+    uri: Uri,
+    peer: Option<SocketAddr>,
+    start: Instant,
+}
+
+impl Entry {
+    pub(crate) fn new(
+        level: Level,
+        target: &str,
+        req: &Request<Body>,
+        peer: Option<SocketAddr>,
+    ) -> Self {
+        Entry {
+            level,
+            target: target.to_owned(),
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            peer,
+            start: Instant::now(),
+        }
+    }
+
+    fn peer(&self) -> String {
+        self.peer
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "-".to_owned())
+    }
+
+    /// Wraps the future of the response so the request gets logged.
+    pub(crate) fn wrap(self, response: ResponseFuture) -> ResponseFuture {
+        let response = response.then(move |result| match result {
+            Ok(response) => {
+                let headers_after = self.start.elapsed();
+                let status = response.status();
+                let (mut parts, body) = response.into_parts();
+                payload::keep_length(&mut parts, &body);
+                let body = LoggedBody {
+                    inner: body,
+                    entry: self,
+                    status,
+                    headers_after,
+                    bytes: 0,
+                    complete: false,
+                };
+                Ok(Response::from_parts(parts, Body::wrap_stream(body)))
+            }
+            Err(e) => {
+                log!(
+                    target: &self.target,
+                    self.level,
+                    "{} \"{} {}\" failed after {:?}: {}",
+                    self.peer(),
+                    self.method,
+                    self.uri,
+                    self.start.elapsed(),
+                    e,
+                );
+                Err(e)
+            }
+        });
+        Box::new(response)
+    }
+}
+
+/// The response body, logging the request when it is done.
+struct LoggedBody {
+    inner: Body,
+    entry: Entry,
+    status: StatusCode,
+    headers_after: Duration,
+    bytes: usize,
+    complete: bool,
+}
+
+impl Stream for LoggedBody {
+    type Item = Chunk;
+    type Error = hyper::Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+        let result = self.inner.poll()?;
+        match result {
+            Async::Ready(Some(ref chunk)) => self.bytes += chunk.len(),
+            Async::Ready(None) => self.complete = true,
+            Async::NotReady => (),
+        }
+        // Hyper doesn't ask for the end if it knows the length
+        self.complete |= self.inner.is_end_stream();
+        Ok(result)
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        let entry = &self.entry;
+        log!(
+            target: &entry.target,
+            entry.level,
+            "{} \"{} {}\" {} {}B, headers after {:?}, body after {:?}{}",
+            entry.peer(),
+            entry.method,
+            entry.uri,
+            self.status.as_u16(),
+            self.bytes,
+            self.headers_after,
+            entry.start.elapsed(),
+            if self.complete { "" } else { " (incomplete)" },
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Limiting the size of request bodies.
+
+use std::error::Error as EError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future;
+use futures::{try_ready, Future, Poll, Stream};
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Body, Chunk, Request, Response, StatusCode};
+use log::debug;
+
+use crate::ResponseFuture;
+
+fn too_large() -> Response<Body> {
+    let mut response = Response::new(Body::from("Request body too large\n"));
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    response
+}
+
+/// A request body that fails once more than the allowed amount of data arrives.
+struct LimitedBody {
+    inner: Body,
+    remaining: usize,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl Stream for LimitedBody {
+    type Item = Chunk;
+    type Error = Box<dyn EError + Send + Sync>;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        let chunk = try_ready!(self.inner.poll());
+        if let Some(ref chunk) = chunk {
+            if chunk.len() > self.remaining {
+                self.exceeded.store(true, Ordering::Relaxed);
+                return Err("Request body too large".into());
+            }
+            self.remaining -= chunk.len();
+        }
+        Ok(chunk.into())
+    }
+}
+
+/// Calls the handler with the body of the request limited to `max` bytes.
+///
+/// Requests announcing a bigger body are refused right away. Streamed bodies are cut when they
+/// grow over the limit and if the handler fails because of that, the client gets the 413 status.
+pub(crate) fn limited<H>(req: Request<Body>, max: usize, handler: H) -> ResponseFuture
+where
+    H: FnOnce(Request<Body>) -> ResponseFuture,
+{
+    let announced = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+    if announced.map(|len| len > max as u64).unwrap_or(false) {
+        debug!("Refusing request with too large body {:?}", announced);
+        return Box::new(future::ok(too_large()));
+    }
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let (parts, body) = req.into_parts();
+    let body = LimitedBody {
+        inner: body,
+        remaining: max,
+        exceeded: Arc::clone(&exceeded),
+    };
+    let req = Request::from_parts(parts, Body::wrap_stream(body));
+    let response = handler(req).or_else(move |e| {
+        if exceeded.load(Ordering::Relaxed) {
+            debug!("Request body grew over the limit: {}", e);
+            Ok(too_large())
+        } else {
+            Err(e)
+        }
+    });
+    Box::new(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    fn consume(req: Request<Body>) -> ResponseFuture {
+        let response = req
+            .into_body()
+            .concat2()
+            .map(|body| Response::new(Body::from(body)))
+            .map_err(Into::into);
+        Box::new(response)
+    }
+
+    fn status(req: Request<Body>) -> StatusCode {
+        limited(req, 4, consume).wait().unwrap().status()
+    }
+
+    #[test]
+    fn announced_length() {
+        let req = Request::builder()
+            .header(CONTENT_LENGTH, "5")
+            .body(Body::from("hello"))
+            .unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, status(req));
+        assert_eq!(StatusCode::OK, status(Request::new(Body::from("hell"))));
+    }
+
+    #[test]
+    fn streamed() {
+        let chunks: Vec<Result<_, hyper::Error>> = vec![Ok("he"), Ok("ll"), Ok("o")];
+        let req = Request::new(Body::wrap_stream(stream::iter_result(chunks)));
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, status(req));
+    }
+}
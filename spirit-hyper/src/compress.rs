@@ -0,0 +1,458 @@
+//! Compression of response bodies.
+//!
+//! The [`Compress`] service wrapper compresses the response bodies with gzip or deflate, depending
+//! on what the client announces in its `Accept-Encoding` header. The body is compressed as it is
+//! streamed, it is not buffered as a whole.
+
+use std::error::Error as EError;
+use std::io::{Result as IoResult, Write};
+use std::mem;
+use std::sync::Arc;
+
+use failure::{ensure, Error};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::{try_ready, Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, VARY,
+};
+use hyper::service::Service;
+use hyper::{Body, Chunk, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cfg-help")]
+use structdoc::StructDoc;
+
+fn default_min_size() -> usize {
+    1024
+}
+
+fn default_level() -> u32 {
+    6
+}
+
+/// Configuration of response compression.
+///
+/// This is the `compression` table of the [`HyperServer`][crate::HyperServer] configuration.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+pub struct CompressionCfg {
+    /// Responses announcing a smaller body (in bytes) are sent uncompressed.
+    ///
+    /// Responses of unknown size are always compressed. Defaults to 1024.
+    #[serde(default = "default_min_size")]
+    min_size: usize,
+
+    /// The compression level, from 0 (fastest) to 9 (smallest).
+    ///
+    /// Defaults to 6.
+    #[serde(default = "default_level")]
+    level: u32,
+}
+
+impl Default for CompressionCfg {
+    fn default() -> Self {
+        CompressionCfg {
+            min_size: default_min_size(),
+            level: default_level(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Picks the encoding to use according to the `Accept-Encoding` header.
+    fn choose(headers: &HeaderMap) -> Option<Self> {
+        // None if not named at all, otherwise if it is accepted
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut any = false;
+        let accepted = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for item in accepted {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let refused = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .filter_map(|q| q.parse::<f32>().ok())
+                .any(|q| q <= 0.0);
+            let coding = match name.as_str() {
+                "gzip" | "x-gzip" => &mut gzip,
+                "deflate" => &mut deflate,
+                "*" => {
+                    any = !refused;
+                    continue;
+                }
+                _ => continue,
+            };
+            *coding = Some(coding.unwrap_or(false) || !refused);
+        }
+        // The wildcard stands only for the codings not named explicitly
+        let gzip = gzip.unwrap_or(any);
+        let deflate = deflate.unwrap_or(any);
+        if gzip {
+            Some(Encoding::Gzip)
+        } else if deflate {
+            Some(Encoding::Deflate)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// Note that the HTTP deflate is really the zlib format, not the raw deflate.
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding, level: Compression) -> Self {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level)),
+        }
+    }
+
+    /// Compresses the data and returns whatever compressed output is available so far.
+    fn compress(&mut self, data: &[u8]) -> IoResult<Vec<u8>> {
+        let output = match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(mem::take(output))
+    }
+
+    fn finish(self) -> IoResult<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// The compressed response body.
+struct CompressedBody {
+    inner: Body,
+    encoder: Option<Encoder>,
+}
+
+impl Stream for CompressedBody {
+    type Item = Chunk;
+    type Error = Box<dyn EError + Send + Sync>;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        loop {
+            let encoder = match self.encoder.as_mut() {
+                Some(encoder) => encoder,
+                None => return Ok(Async::Ready(None)),
+            };
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => {
+                    let output = encoder.compress(&chunk)?;
+                    // The encoder may keep small pieces of data until it has enough of them
+                    if !output.is_empty() {
+                        return Ok(Async::Ready(Some(output.into())));
+                    }
+                }
+                None => {
+                    let output = self.encoder.take().unwrap().finish()?;
+                    return Ok(Async::Ready(Some(output.into())));
+                }
+            }
+        }
+    }
+}
+
+fn precompressed(content_type: &str) -> bool {
+    const TYPES: &[&str] = &[
+        "application/gzip",
+        "application/x-gzip",
+        "application/zip",
+        "application/x-bzip2",
+        "application/x-xz",
+        "application/x-7z-compressed",
+        "application/zstd",
+        "font/woff",
+        "font/woff2",
+    ];
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if content_type == "image/svg+xml" {
+        return false;
+    }
+    content_type.starts_with("image/")
+        || content_type.starts_with("audio/")
+        || content_type.starts_with("video/")
+        || TYPES.contains(&content_type.as_str())
+}
+
+impl CompressionCfg {
+    /// Checks the configuration makes sense.
+    pub(crate) fn validate(&self, name: &str) -> Result<(), Error> {
+        ensure!(
+            self.level <= 9,
+            "The compression level of {} must be from 0 to 9, got {}",
+            name,
+            self.level,
+        );
+        Ok(())
+    }
+
+    fn worth_it(&self, response: &Response<Body>) -> bool {
+        let status = response.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            // The range is of the uncompressed representation, compressing it would break it
+            || status == StatusCode::PARTIAL_CONTENT
+        {
+            return false;
+        }
+        let headers = response.headers();
+        if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+            return false;
+        }
+        let content_type = headers.get(CONTENT_TYPE).and_then(|t| t.to_str().ok());
+        if content_type.map(precompressed).unwrap_or(false) {
+            return false;
+        }
+        let len = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<u64>().ok())
+            .or_else(|| response.body().content_length());
+        len.map(|len| len >= self.min_size as u64).unwrap_or(true)
+    }
+
+    fn compress(&self, response: Response<Body>, encoding: Encoding) -> Response<Body> {
+        if !self.worth_it(&response) {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        let body = CompressedBody {
+            inner: body,
+            encoder: Some(Encoder::new(encoding, Compression::new(self.level))),
+        };
+        Response::from_parts(parts, Body::wrap_stream(body))
+    }
+
+    /// Calls the handler and compresses its response if the client supports it.
+    pub(crate) fn handle<H, E>(
+        &self,
+        req: Request<Body>,
+        handler: H,
+    ) -> Box<dyn Future<Item = Response<Body>, Error = E> + Send>
+    where
+        H: FnOnce(Request<Body>) -> Box<dyn Future<Item = Response<Body>, Error = E> + Send>,
+        E: Send + 'static,
+    {
+        let encoding = if req.method() == Method::HEAD {
+            None
+        } else {
+            Encoding::choose(req.headers())
+        };
+        match encoding {
+            Some(encoding) => {
+                let cfg = self.clone();
+                Box::new(handler(req).map(move |response| cfg.compress(response, encoding)))
+            }
+            None => handler(req),
+        }
+    }
+}
+
+/// A [`Service`] wrapper compressing the responses.
+///
+/// Usually created through [`HyperServer::compress`][crate::HyperServer::compress]. If the
+/// compression is not configured, the responses are passed through unchanged.
+pub struct Compress<S> {
+    cfg: Option<Arc<CompressionCfg>>,
+    inner: S,
+}
+
+impl<S> Compress<S> {
+    /// Wraps the service.
+    pub fn new(cfg: Option<Arc<CompressionCfg>>, inner: S) -> Self {
+        Compress { cfg, inner }
+    }
+}
+
+impl<S> Service for Compress<S>
+where
+    S: Service<ReqBody = Body, ResBody = Body>,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn EError + Send + Sync>> + Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Response<Body>, Error = S::Error> + Send>;
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let inner = &mut self.inner;
+        let mut handler = |req| -> Self::Future { Box::new(inner.call(req)) };
+        match self.cfg.as_ref() {
+            Some(cfg) => cfg.handle(req, handler),
+            None => handler(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error as IoError, Read};
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use futures::{future, stream};
+
+    use super::*;
+
+    fn headers(accept: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(accept).unwrap());
+        headers
+    }
+
+    #[test]
+    fn choose() {
+        assert_eq!(None, Encoding::choose(&HeaderMap::new()));
+        assert_eq!(None, Encoding::choose(&headers("br, identity")));
+        assert_eq!(
+            Some(Encoding::Gzip),
+            Encoding::choose(&headers("deflate, gzip;q=0.5"))
+        );
+        assert_eq!(
+            Some(Encoding::Deflate),
+            Encoding::choose(&headers("gzip;q=0, deflate"))
+        );
+        assert_eq!(Some(Encoding::Gzip), Encoding::choose(&headers("*")));
+        assert_eq!(
+            Some(Encoding::Deflate),
+            Encoding::choose(&headers("gzip;q=0, *"))
+        );
+        assert_eq!(None, Encoding::choose(&headers("gzip;q=0, deflate;q=0, *")));
+        assert_eq!(None, Encoding::choose(&headers("*;q=0")));
+    }
+
+    fn call(accept: &str, response: Response<Body>) -> (Response<Body>, Vec<u8>) {
+        let req = Request::builder()
+            .header(ACCEPT_ENCODING, accept)
+            .body(Body::empty())
+            .unwrap();
+        let cfg = CompressionCfg::default();
+        let response = cfg
+            .handle(req, |_| -> Box<dyn Future<Item = _, Error = ()> + Send> {
+                Box::new(future::ok(response))
+            })
+            .wait()
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.concat2().wait().unwrap().to_vec();
+        (Response::from_parts(parts, Body::empty()), body)
+    }
+
+    #[test]
+    fn compressed() {
+        let data = "Hello world\n".repeat(1000);
+        // Streamed in several chunks
+        let chunks = data
+            .as_bytes()
+            .chunks(100)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let body = Body::wrap_stream(stream::iter_ok::<_, IoError>(chunks));
+        let (response, body) = call("gzip", Response::new(body));
+        assert_eq!("gzip", response.headers()[CONTENT_ENCODING]);
+        assert_eq!("Accept-Encoding", response.headers()[VARY]);
+        assert!(body.len() < data.len());
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(data, decoded);
+
+        let (response, body) = call("deflate", Response::new(Body::from(data.clone())));
+        assert_eq!("deflate", response.headers()[CONTENT_ENCODING]);
+        let mut decoded = String::new();
+        ZlibDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn skipped() {
+        let small = Response::builder()
+            .header(CONTENT_LENGTH, 5)
+            .body(Body::from("Hello"))
+            .unwrap();
+        let (response, body) = call("gzip", small);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(b"Hello", &body[..]);
+
+        let image = Response::builder()
+            .header(CONTENT_TYPE, "image/png")
+            .body(Body::from(vec![0; 4096]))
+            .unwrap();
+        let (response, body) = call("gzip", image);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(4096, body.len());
+
+        let plain = Response::new(Body::from(vec![0; 4096]));
+        let (response, _) = call("br", plain);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+
+        let partial = Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(CONTENT_RANGE, "bytes 0-4095/8192")
+            .body(Body::from(vec![0; 4096]))
+            .unwrap();
+        let (response, body) = call("gzip", partial);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(4096, body.len());
+    }
+
+    #[test]
+    fn level() {
+        let level = |level| CompressionCfg {
+            level,
+            ..CompressionCfg::default()
+        };
+        assert!(level(0).validate("test").is_ok());
+        assert!(level(9).validate("test").is_ok());
+        assert!(level(42).validate("test").is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Cross-origin resource sharing.
+//!
+//! The [`Cors`] service wrapper adds the `Access-Control-Allow-*` headers to responses and answers
+//! the preflight requests, according to the `cors` table in the configuration of the
+//! [`HyperServer`][crate::HyperServer]. The servers created through
+//! [`service_fn`][crate::service_fn] get it automatically.
+
+use std::error::Error as EError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::{ensure, Error};
+use futures::future::{self, Future};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::service::Service;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::debug;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cfg-help")]
+use structdoc::StructDoc;
+
+fn default_methods() -> Vec<String> {
+    vec!["GET".to_owned(), "HEAD".to_owned(), "POST".to_owned()]
+}
+
+/// Configuration of cross-origin resource sharing.
+///
+/// This is the `cors` table of the [`HyperServer`][crate::HyperServer] configuration.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+pub struct CorsCfg {
+    /// The origins allowed to access the server (eg. `https://example.com`).
+    ///
+    /// A `*` allows any origin.
+    origins: Vec<String>,
+
+    /// The methods the clients may use.
+    ///
+    /// Defaults to `GET`, `HEAD` and `POST`.
+    #[serde(default = "default_methods")]
+    methods: Vec<String>,
+
+    /// Request headers the clients may send.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<String>,
+
+    /// Response headers the clients may read.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    expose_headers: Vec<String>,
+
+    /// How long the clients may cache the result of a preflight request.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "spirit::utils::serialize_opt_duration",
+        deserialize_with = "spirit::utils::deserialize_opt_duration",
+        default
+    )]
+    max_age: Option<Duration>,
+
+    /// Allow requests with credentials (cookies, authorization headers).
+    ///
+    /// This can't be combined with the wildcard `*` origin, as that would give every website
+    /// access to the user's data. The origins need to be listed explicitly.
+    #[serde(default)]
+    credentials: bool,
+}
+
+fn list(items: &[String]) -> Option<HeaderValue> {
+    if items.is_empty() {
+        None
+    } else {
+        HeaderValue::from_str(&items.join(", ")).ok()
+    }
+}
+
+// The response depends on the origin, so caches must not mix them up. This holds for the refused
+// origins and requests without any origin too, or a cache could serve their response without the
+// CORS headers to an allowed one.
+fn vary(headers: &mut HeaderMap) {
+    headers.append(VARY, HeaderValue::from_static("Origin"));
+}
+
+impl CorsCfg {
+    /// Checks the configuration makes sense.
+    pub(crate) fn validate(&self, name: &str) -> Result<(), Error> {
+        ensure!(
+            !self.credentials || !self.origins.iter().any(|o| o == "*"),
+            "The CORS configuration of {} can't allow credentials with the wildcard origin",
+            name,
+        );
+        Ok(())
+    }
+
+    /// Decides the value of `Access-Control-Allow-Origin` for the request origin.
+    ///
+    /// Returns `None` if the origin is not allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        let origin_str = origin.to_str().ok()?;
+        if self.origins.iter().any(|o| o == origin_str) {
+            Some(origin.clone())
+        } else if self.origins.iter().any(|o| o == "*") {
+            Some(HeaderValue::from_static("*"))
+        } else {
+            None
+        }
+    }
+
+    fn common(&self, allow_origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    fn preflight(&self, allow_origin: Option<HeaderValue>) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        vary(response.headers_mut());
+        let allow_origin = match allow_origin {
+            Some(allow_origin) => allow_origin,
+            None => {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                return response;
+            }
+        };
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        let headers = response.headers_mut();
+        self.common(allow_origin, headers);
+        if let Some(methods) = list(&self.methods) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if let Some(allowed) = list(&self.headers) {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+        }
+        response
+    }
+
+    /// Handles the CORS part of the request and calls the handler if appropriate.
+    pub(crate) fn handle<H, E>(
+        &self,
+        req: Request<Body>,
+        handler: H,
+    ) -> Box<dyn Future<Item = Response<Body>, Error = E> + Send>
+    where
+        H: FnOnce(Request<Body>) -> Box<dyn Future<Item = Response<Body>, Error = E> + Send>,
+        E: Send + 'static,
+    {
+        let origin = match req.headers().get(ORIGIN) {
+            Some(origin) => origin.clone(),
+            // Not a cross-origin request, but a cache could still serve its response to one
+            None => {
+                return Box::new(handler(req).map(|mut response| {
+                    vary(response.headers_mut());
+                    response
+                }))
+            }
+        };
+        let allow_origin = self.allow_origin(&origin);
+        let is_preflight = req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+        if is_preflight {
+            debug!("Answering CORS preflight from {:?}", origin);
+            return Box::new(future::ok(self.preflight(allow_origin)));
+        }
+        let cfg = self.clone();
+        let response = handler(req).map(move |mut response| {
+            let headers = response.headers_mut();
+            vary(headers);
+            // If not allowed, we let the browser refuse it
+            if let Some(allow_origin) = allow_origin {
+                cfg.common(allow_origin, headers);
+                if let Some(exposed) = list(&cfg.expose_headers) {
+                    headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+                }
+            }
+            response
+        });
+        Box::new(response)
+    }
+}
+
+/// A [`Service`] wrapper adding CORS support.
+///
+/// Usually created through [`HyperServer::cors`][crate::HyperServer::cors]. If CORS is not
+/// configured, the requests are passed to the inner service unchanged.
+pub struct Cors<S> {
+    cfg: Option<Arc<CorsCfg>>,
+    inner: S,
+}
+
+impl<S> Cors<S> {
+    /// Wraps the service.
+    pub fn new(cfg: Option<Arc<CorsCfg>>, inner: S) -> Self {
+        Cors { cfg, inner }
+    }
+}
+
+impl<S> Service for Cors<S>
+where
+    S: Service<ReqBody = Body, ResBody = Body>,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn EError + Send + Sync>> + Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Response<Body>, Error = S::Error> + Send>;
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let inner = &mut self.inner;
+        let mut handler = |req| -> Self::Future { Box::new(inner.call(req)) };
+        match self.cfg.as_ref() {
+            Some(cfg) => cfg.handle(req, handler),
+            None => handler(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(origins: &[&str], credentials: bool) -> CorsCfg {
+        CorsCfg {
+            origins: origins.iter().map(|o| (*o).to_owned()).collect(),
+            methods: default_methods(),
+            headers: vec!["X-Token".to_owned()],
+            expose_headers: Vec::new(),
+            max_age: Some(Duration::from_secs(60)),
+            credentials,
+        }
+    }
+
+    fn call(cfg: &CorsCfg, req: Request<Body>) -> Response<Body> {
+        cfg.handle(req, |_| -> Box<dyn Future<Item = _, Error = ()> + Send> {
+            Box::new(future::ok(Response::new(Body::from("Hello"))))
+        })
+        .wait()
+        .unwrap()
+    }
+
+    fn request(method: Method, origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn preflight() {
+        let cfg = cfg(&["https://example.com"], false);
+        let response = call(&cfg, request(Method::OPTIONS, "https://example.com"));
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        let headers = response.headers();
+        assert_eq!("https://example.com", headers[ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!("GET, HEAD, POST", headers[ACCESS_CONTROL_ALLOW_METHODS]);
+        assert_eq!("X-Token", headers[ACCESS_CONTROL_ALLOW_HEADERS]);
+        assert_eq!("60", headers[ACCESS_CONTROL_MAX_AGE]);
+
+        let response = call(&cfg, request(Method::OPTIONS, "https://evil.com"));
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!("Origin", response.headers()[VARY]);
+    }
+
+    #[test]
+    fn refused() {
+        let cfg = cfg(&["https://example.com"], false);
+        let response = call(&cfg, request(Method::GET, "https://evil.com"));
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!("Origin", response.headers()[VARY]);
+
+        // Not a cross-origin request, but the response must not be cached for one
+        let req = Request::builder()
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let response = call(&cfg, req);
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!("Origin", response.headers()[VARY]);
+    }
+
+    #[test]
+    fn wildcard() {
+        let anyone = cfg(&["*"], false);
+        let response = call(&anyone, request(Method::GET, "https://example.com"));
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("*", response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!("Origin", response.headers()[VARY]);
+        assert!(anyone.validate("test").is_ok());
+
+        // Wildcard can't be used with credentials
+        assert!(cfg(&["*"], true).validate("test").is_err());
+        let credentials = cfg(&["https://example.com"], true);
+        assert!(credentials.validate("test").is_ok());
+        let response = call(&credentials, request(Method::GET, "https://example.com"));
+        let headers = response.headers();
+        assert_eq!("https://example.com", headers[ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!("true", headers[ACCESS_CONTROL_ALLOW_CREDENTIALS]);
+        assert_eq!("Origin", headers[VARY]);
+    }
+}
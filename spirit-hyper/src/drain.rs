@@ -0,0 +1,92 @@
+//! Waiting for the servers to finish their graceful shutdown.
+
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+struct Running {
+    count: Mutex<usize>,
+    finished: Condvar,
+}
+
+static RUNNING: Running = Running {
+    count: Mutex::new(0),
+    finished: Condvar::new(),
+};
+
+// Only a counter is protected, it can't be left inconsistent by a panic.
+fn count() -> MutexGuard<'static, usize> {
+    RUNNING.count.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Marks a server as running (or draining its connections) for as long as it's alive.
+pub(crate) struct RunningGuard(());
+
+impl RunningGuard {
+    pub(crate) fn new() -> Self {
+        *count() += 1;
+        RunningGuard(())
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        *count() -= 1;
+        RUNNING.finished.notify_all();
+    }
+}
+
+/// Waits for all the servers to terminate, including their connections.
+///
+/// When asked to shut down (on termination or when replaced by a new one on configuration reload),
+/// the servers stop accepting new connections, but finish the requests in flight first. The
+/// runtime usually waits for that to happen, but without a time limit. This allows waiting for the
+/// connections to drain from the application's `run` body, after calling
+/// [`terminate`][spirit::Spirit::terminate], so the application can decide what to do if they
+/// don't in time.
+///
+/// Returns `true` if all the servers terminated, `false` if the timeout elapsed first.
+///
+/// Note that this waits for the servers that are still running as well. This must not be called
+/// from within the runtime (it would block it).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use spirit::prelude::*;
+/// Spirit::<Empty, Empty>::new()
+///     .run(|spirit| {
+///         // Start the servers and wait for the signal to terminate here
+///         spirit.terminate();
+///         if !spirit_hyper::wait_drained(Duration::from_secs(30)) {
+///             log::warn!("Some HTTP connections didn't finish in time");
+///         }
+///         Ok(())
+///     });
+/// ```
+pub fn wait_drained(timeout: Duration) -> bool {
+    let (count, _) = RUNNING
+        .finished
+        .wait_timeout_while(count(), timeout, |count| *count > 0)
+        .unwrap_or_else(PoisonError::into_inner);
+    *count == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn wait() {
+        let guard = RunningGuard::new();
+        assert!(!wait_drained(Duration::from_millis(10)));
+        let finished = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+        });
+        assert!(wait_drained(Duration::from_secs(5)));
+        finished.join().unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+//! Serving static files from a directory.
+//!
+//! The [`static_files`] function creates a [`ServiceFn`] serving the files directly. If the
+//! server has other things to do too, the [`StaticFiles`] can be called from inside a custom
+//! handler for some of the requests.
+
+use std::fs::{self, File};
+use std::io::{Error as IoError, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::{future, Async, Future};
+use hyper::header::{
+    HeaderValue, ACCEPT_RANGES, ALLOW, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, error};
+
+use crate::{service_fn, HyperServer, ServiceFn};
+
+/// The future of a response produced by the [`StaticFiles`].
+pub type FileFuture = Box<dyn Future<Item = Response<Body>, Error = IoError> + Send>;
+
+const TYPES: &[(&str, &str)] = &[
+    ("css", "text/css; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html; charset=utf-8"),
+    ("html", "text/html; charset=utf-8"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("mjs", "application/javascript; charset=utf-8"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("wasm", "application/wasm"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+/// Guesses the content type by the file extension.
+fn content_type(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| {
+            TYPES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(ext))
+                .map(|(_, content_type)| *content_type)
+        })
+        .unwrap_or("application/octet-stream")
+}
+
+fn status(status: StatusCode, text: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(text));
+    *response.status_mut() = status;
+    response
+}
+
+fn hex(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Decodes the `%xx` sequences of an URL path segment.
+fn decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut input = segment.bytes();
+    while let Some(b) = input.next() {
+        if b == b'%' {
+            let high = hex(input.next()?)?;
+            let low = hex(input.next()?)?;
+            bytes.push(high << 4 | low);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Turns the URL path into a file path inside the root.
+///
+/// Returns `None` if it would point outside of the root (or is otherwise suspicious).
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let mut result = root.to_owned();
+    for segment in path.split('/') {
+        let segment = decode(segment)?;
+        match segment.as_str() {
+            "" | "." => (),
+            ".." => return None,
+            // Encoded slashes, windows separators and drive letters could get us out as well
+            s if s.contains(&['/', '\\', ':', '\0'][..]) => return None,
+            s => result.push(s),
+        }
+    }
+    Some(result)
+}
+
+/// Parses the `Range` header.
+///
+/// Returns the first and last byte, `Err` if the range can't be satisfied and `None` if the whole
+/// file should be sent (no or unsupported range).
+fn range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // Multiple ranges are not supported, the client gets the whole file (which is allowed).
+    if spec.contains(',') {
+        return None;
+    }
+    let dash = spec.find('-')?;
+    let (start, end) = (spec[..dash].trim(), spec[dash + 1..].trim());
+    let range = if start.is_empty() {
+        // The last N bytes
+        let suffix = end.parse::<u64>().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start = start.parse::<u64>().ok()?;
+        let end = if end.is_empty() {
+            len.saturating_sub(1)
+        } else {
+            end.parse::<u64>().ok()?.min(len.saturating_sub(1))
+        };
+        if start >= len || start > end {
+            return Some(Err(()));
+        }
+        (start, end)
+    };
+    Some(Ok(range))
+}
+
+fn read(file: &mut File, start: u64, len: u64) -> Result<Vec<u8>, IoError> {
+    let mut data = Vec::with_capacity(len as usize);
+    file.seek(SeekFrom::Start(start))?;
+    file.take(len).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn respond(root: &Path, method: &Method, path: &str, range_hdr: Option<&str>) -> Response<Body> {
+    if method != Method::GET && method != Method::HEAD {
+        let mut response = status(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed\n");
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response;
+    }
+    let mut file_path = match resolve(root, path) {
+        Some(file_path) => file_path,
+        None => {
+            debug!("Refusing to serve {} outside of {}", path, root.display());
+            return status(StatusCode::FORBIDDEN, "Forbidden\n");
+        }
+    };
+    let open = |file_path: &Path| -> Result<(File, u64), IoError> {
+        let meta = fs::metadata(file_path)?;
+        if !meta.is_file() {
+            return Err(IoError::new(ErrorKind::NotFound, "Not a file"));
+        }
+        Ok((File::open(file_path)?, meta.len()))
+    };
+    if file_path.is_dir() {
+        file_path.push("index.html");
+    }
+    let result = open(&file_path).and_then(|(mut file, len)| {
+        let mut response = Response::builder();
+        response
+            .header(CONTENT_TYPE, content_type(&file_path))
+            .header(ACCEPT_RANGES, "bytes");
+        let (start, end) = match range_hdr.and_then(|hdr| range(hdr, len)) {
+            None => {
+                response.status(StatusCode::OK);
+                (0, len)
+            }
+            Some(Ok((first, last))) => {
+                response.status(StatusCode::PARTIAL_CONTENT).header(
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", first, last, len).as_str(),
+                );
+                (first, last + 1)
+            }
+            Some(Err(())) => {
+                return Ok(response
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len).as_str())
+                    .body(Body::empty())
+                    .expect("Invalid response"));
+            }
+        };
+        response.header(CONTENT_LENGTH, end - start);
+        let body = if method == Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(read(&mut file, start, end - start)?)
+        };
+        Ok(response.body(body).expect("Invalid response"))
+    });
+    match result {
+        Ok(response) => response,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            status(StatusCode::NOT_FOUND, "Not found\n")
+        }
+        Err(ref e) if e.kind() == ErrorKind::PermissionDenied => {
+            status(StatusCode::FORBIDDEN, "Forbidden\n")
+        }
+        Err(e) => {
+            error!("Failed to serve file {}: {}", file_path.display(), e);
+            status(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error\n")
+        }
+    }
+}
+
+/// Serves the static files from a directory.
+///
+/// The path of the request is looked up inside the root directory (`index.html` is served for a
+/// directory). Requests trying to get outside of it (with `..` and similar) get the 403 status
+/// code, missing files 404. Only the `GET` and `HEAD` methods are supported.
+///
+/// The content type is guessed from the file extension. Single byte ranges (the `Range` header)
+/// are supported, so downloads can be resumed and media seeked.
+///
+/// The files (or the requested ranges) are read into memory as a whole, in the blocking section of
+/// the tokio thread pool. It is meant for the assets of a small service, not for serving huge
+/// files.
+///
+/// # Examples
+///
+/// ```rust
+/// use hyper::{Body, Request, Response, StatusCode};
+/// use spirit::prelude::*;
+/// use spirit_hyper::files::{FileFuture, StaticFiles};
+/// use spirit_hyper::HttpServer;
+///
+/// let files = StaticFiles::new("/srv/www");
+/// let handler = move |_: &HttpServer, req: Request<Body>| -> FileFuture {
+///     if req.uri().path().starts_with("/api/") {
+///         let mut response = Response::new(Body::from("No API yet\n"));
+///         *response.status_mut() = StatusCode::NOT_IMPLEMENTED;
+///         Box::new(futures::future::ok(response))
+///     } else {
+///         files.serve(&req)
+///     }
+/// };
+///
+/// Spirit::<Empty, HttpServer>::new()
+///     .config_defaults("port = 1234")
+///     .with(
+///         Pipeline::new("listen")
+///             .extract_cfg(HttpServer::clone)
+///             .transform(spirit_hyper::service_fn(handler))
+///     )
+/// #   .run(|spirit| {
+/// #       let spirit = std::sync::Arc::clone(spirit);
+/// #       std::thread::spawn(move || spirit.terminate());
+/// #       Ok(())
+/// #   });
+/// ```
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    root: Arc<PathBuf>,
+}
+
+impl StaticFiles {
+    /// Creates the server of files from the given root directory.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        StaticFiles {
+            root: Arc::new(root.into()),
+        }
+    }
+
+    /// Answers the request with the corresponding file.
+    pub fn serve<B>(&self, req: &Request<B>) -> FileFuture {
+        let root = Arc::clone(&self.root);
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let range = req
+            .headers()
+            .get(RANGE)
+            .and_then(|range| range.to_str().ok())
+            .map(str::to_owned);
+        let mut respond = move || respond(&root, &method, &path, range.as_ref().map(|r| r as &str));
+        let response = future::poll_fn(move || match tokio_threadpool::blocking(&mut respond) {
+            Ok(Async::Ready(response)) => Ok(Async::Ready(response)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // Not running inside the thread pool (eg. in a current thread runtime), so do it
+            // right here.
+            Err(_) => Ok(Async::Ready(respond())),
+        });
+        Box::new(response)
+    }
+}
+
+/// Creates a [`ServiceFn`] serving the static files from the root directory.
+///
+/// See [`StaticFiles`] for details.
+///
+/// # Examples
+///
+/// ```rust
+/// use spirit::prelude::*;
+/// use spirit_hyper::HttpServer;
+///
+/// Spirit::<Empty, HttpServer>::new()
+///     .config_defaults("port = 1234")
+///     .with(
+///         Pipeline::new("listen")
+///             .extract_cfg(HttpServer::clone)
+///             .transform(spirit_hyper::files::static_files("/srv/www"))
+///     )
+/// #   .run(|spirit| {
+/// #       let spirit = std::sync::Arc::clone(spirit);
+/// #       std::thread::spawn(move || spirit.terminate());
+/// #       Ok(())
+/// #   });
+/// ```
+pub fn static_files<Transport, P>(
+    root: P,
+) -> ServiceFn<impl Fn(&HyperServer<Transport>, Request<Body>) -> FileFuture + Send + Sync + 'static>
+where
+    P: Into<PathBuf>,
+{
+    let files = StaticFiles::new(root);
+    service_fn(move |_: &HyperServer<Transport>, req: Request<Body>| files.serve(&req))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::Stream;
+
+    use super::*;
+
+    struct Root(PathBuf);
+
+    impl Root {
+        fn new() -> Self {
+            // Each test gets its own directory, they run in parallel
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let root = env::temp_dir().join(format!(
+                "spirit-hyper-files-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(root.join("sub")).unwrap();
+            fs::write(root.join("hello.txt"), "Hello world").unwrap();
+            fs::write(root.join("sub").join("index.html"), "<p>Index</p>").unwrap();
+            Root(root)
+        }
+
+        fn get(&self, path: &str, range: Option<&str>) -> (StatusCode, Response<Body>) {
+            let mut req = Request::builder();
+            req.uri(path);
+            if let Some(range) = range {
+                req.header(RANGE, range);
+            }
+            let req = req.body(Body::empty()).unwrap();
+            let response = StaticFiles::new(&self.0).serve(&req).wait().unwrap();
+            (response.status(), response)
+        }
+
+        fn body(&self, path: &str, range: Option<&str>) -> (StatusCode, String) {
+            let (status, response) = self.get(path, range);
+            let body = response.into_body().concat2().wait().unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn files() {
+        let root = Root::new();
+        let (status, response) = root.get("/hello.txt", None);
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            response.headers()[CONTENT_TYPE].to_str().unwrap()
+        );
+        assert_eq!(
+            (StatusCode::OK, "<p>Index</p>".to_owned()),
+            root.body("/sub/", None)
+        );
+        assert_eq!(StatusCode::NOT_FOUND, root.get("/missing", None).0);
+        assert_eq!(StatusCode::FORBIDDEN, root.get("/../etc/passwd", None).0);
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            root.get("/sub/%2e%2e/%2e%2e/x", None).0
+        );
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            root.get("/sub%2f..%2f..%2fx", None).0
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        let root = Root::new();
+        let (status, body) = root.body("/hello.txt", Some("bytes=6-"));
+        assert_eq!(
+            (StatusCode::PARTIAL_CONTENT, "world"),
+            (status, &body as &str)
+        );
+        let (status, response) = root.get("/hello.txt", Some("bytes=0-4"));
+        assert_eq!(StatusCode::PARTIAL_CONTENT, status);
+        assert_eq!(
+            "bytes 0-4/11",
+            response.headers()[CONTENT_RANGE].to_str().unwrap()
+        );
+        assert_eq!(
+            (StatusCode::PARTIAL_CONTENT, "rld".to_owned()),
+            root.body("/hello.txt", Some("bytes=-3"))
+        );
+        assert_eq!(
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            root.get("/hello.txt", Some("bytes=20-")).0
+        );
+        // Unsupported ones get the whole file
+        assert_eq!(
+            (StatusCode::OK, "Hello world".to_owned()),
+            root.body("/hello.txt", Some("bytes=0-1,3-4"))
+        );
+    }
+}
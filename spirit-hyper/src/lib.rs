@@ -73,6 +73,10 @@
 //! }
 //! ```
 //!
+//! # Features
+//!
+//! * `tls`: Adds the `TlsListen` wrapper and the `HttpsServer` type alias for serving HTTPS.
+//!
 //! Further examples are in the
 //! [git repository](https://github.com/vorner/spirit/tree/master/spirit-hyper/examples).
 //!
@@ -82,15 +86,20 @@
 use std::error::Error as EError;
 use std::fmt::Debug;
 use std::io::Error as IoError;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use failure::{Error, Fail};
+use failure::{ensure, Error, Fail};
+use futures::future::{self, FutureResult};
 use futures::sync::oneshot::{self, Receiver, Sender};
-use futures::{Async, Future, Poll, Stream};
+use futures::{Async, Future, IntoFuture, Poll, Stream};
 use hyper::body::Payload;
 use hyper::server::{Builder, Server};
-use hyper::service::{MakeServiceRef, Service};
-use hyper::Body;
-use log::debug;
+use hyper::service::{MakeService, MakeServiceRef, Service};
+use hyper::{Body, Request, Response};
+use log::{debug, Level};
+use serde::de::{DeserializeOwned, Deserializer};
 use serde::{Deserialize, Serialize};
 use spirit::fragment::driver::{CacheSimilar, Comparable, Comparison};
 use spirit::fragment::{Fragment, Stackable, Transformation};
@@ -103,6 +112,33 @@ use spirit_tokio::TcpListen;
 use structdoc::StructDoc;
 use tokio::io::{AsyncRead, AsyncWrite};
 
+mod access_log;
+mod body_limit;
+pub mod compress;
+pub mod cors;
+mod drain;
+pub mod files;
+pub mod metrics;
+mod observe;
+mod payload;
+pub mod peer;
+mod strict;
+pub mod timeout;
+#[cfg(feature = "tls")]
+pub mod tls;
+
+use crate::access_log::Entry;
+use crate::compress::{Compress, CompressionCfg};
+use crate::cors::{Cors, CorsCfg};
+pub use crate::drain::wait_drained;
+use crate::drain::RunningGuard;
+use crate::metrics::Metrics;
+use crate::observe::ErrorHook;
+use crate::peer::{PeerAddr, RemoteAddr};
+use crate::timeout::Timeout;
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsListen;
+
 fn default_on() -> bool {
     true
 }
@@ -156,8 +192,99 @@ struct HyperCfg {
     #[serde(default = "default_on")]
     http1_half_close: bool,
 
+    /// Maximum size of the buffer for reading from a http1 connection, in bytes.
+    ///
+    /// Bigger buffer allows reading more of the request in one go (which helps with big uploads),
+    /// but it costs memory for each connection. Must be at least 8192.
+    ///
+    /// Defaults to hyper's default (around 400kB).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http1_max_buf_size: Option<usize>,
+
+    /// Which HTTP protocol versions to speak.
+    ///
+    /// With `http2-only`, the server expects HTTP2 with prior knowledge (no upgrade from HTTP1),
+    /// which is what eg. gRPC clients use.
+    ///
+    /// Defaults to both.
     #[serde(default)]
     http_mode: HttpMode,
+
+    /// Log a line for each handled request.
+    ///
+    /// The line contains the client address, method, path, status, the size of the response body
+    /// and the times until the response headers were produced and the whole body was sent.
+    ///
+    /// This applies to servers created through the `service_fn`. Default is off.
+    #[serde(default)]
+    access_log: bool,
+
+    /// The level at which the requests are logged.
+    ///
+    /// Defaults to `info`.
+    #[serde(default = "default_access_log_level")]
+    #[cfg_attr(feature = "cfg-help", structdoc(leaf = "Log level"))]
+    access_log_level: Level,
+
+    /// The log target (which usually is the module name) of the access log lines.
+    ///
+    /// It can be used to filter the access log or direct it to a different destination. Defaults
+    /// to `access`.
+    #[serde(default = "default_access_log_target")]
+    access_log_target: String,
+
+    /// Maximum size of a request body, in bytes.
+    ///
+    /// Requests with bigger bodies are refused with the 413 status code. This applies to servers
+    /// created through the `service_fn`. Bodies of unknown size (chunked) are cut once they grow
+    /// over the limit.
+    ///
+    /// Unlimited by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_body_size: Option<usize>,
+
+    /// Cross-origin resource sharing.
+    ///
+    /// If present, the allowed origins get the `Access-Control-Allow-*` headers and the preflight
+    /// requests are answered. This applies to servers created through the `service_fn`.
+    ///
+    /// Off by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cors: Option<CorsCfg>,
+
+    /// Compression of the response bodies.
+    ///
+    /// If present, the responses are compressed with gzip or deflate if the client supports it.
+    /// This applies to servers created through the `service_fn`.
+    ///
+    /// Off by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<CompressionCfg>,
+
+    /// How long a request may take to be answered.
+    ///
+    /// If the handler doesn't produce the response in time, it is cancelled and the client gets
+    /// the 503 status code. This applies to servers created through the `service_fn`.
+    ///
+    /// Unlimited by default.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "spirit::utils::serialize_opt_duration",
+        deserialize_with = "spirit::utils::deserialize_opt_duration",
+        default
+    )]
+    request_timeout: Option<Duration>,
+}
+
+/// The smallest read buffer hyper accepts.
+const MIN_BUF_SIZE: usize = 8192;
+
+fn default_access_log_level() -> Level {
+    Level::Info
+}
+
+fn default_access_log_target() -> String {
+    "access".to_owned()
 }
 
 /// A [`Fragment`] for hyper servers.
@@ -174,10 +301,62 @@ struct HyperCfg {
 ///
 /// In addition to options already provided by the `Transport`, these options are added:
 ///
-/// * `http1-keepalive`: boolean, default true.
+/// * `http1-keepalive`: boolean, default true. Whether the HTTP1 connections may be reused for
+///   further requests.
 /// * `http1-writev`: boolean, default true.
-/// * `http-mode`: One of `"both"`, `"http1-only"` or `"http2-only"`. Defaults to `"both"`.
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+/// * `http1-half-close`: boolean, default true.
+/// * `http1-max-buf-size`: Maximum size of the read buffer of a http1 connection, in bytes. At
+///   least 8192, defaults to hyper's default (around 400kB).
+/// * `http-mode`: One of `"both"`, `"http1-only"` or `"http2-only"`. Defaults to `"both"`. The
+///   `"http2-only"` mode speaks HTTP2 with prior knowledge.
+/// * `access-log`: boolean, default false. Log a line for each request handled by a server
+///   created through [`service_fn`].
+/// * `access-log-level`: The log level of the access log. Defaults to `"info"`.
+/// * `access-log-target`: The log target of the access log. Defaults to `"access"`.
+/// * `max-body-size`: Maximum size of a request body in bytes, requests with bigger ones get the
+///   413 status code. Applies to servers created through [`service_fn`]. Unlimited by default.
+/// * `cors`: A table with the cross-origin resource sharing settings, off by default. Applies to
+///   servers created through [`service_fn`], other services can be wrapped by the
+///   [`cors`][HyperServer::cors] method. It contains:
+///   - `origins`: Array of allowed origins (eg. `"https://example.com"`), `"*"` allows any.
+///   - `methods`: Array of allowed methods. Defaults to `["GET", "HEAD", "POST"]`.
+///   - `headers`: Array of request headers the client may send.
+///   - `expose-headers`: Array of response headers the client may read.
+///   - `max-age`: How long the client may cache the preflight answer (eg. `"10m"`).
+///   - `credentials`: boolean, default false. Allow requests with cookies and authorization. Can't
+///     be combined with the `"*"` origin.
+/// * `compression`: A table enabling gzip and deflate compression of the response bodies (as
+///   negotiated by the `Accept-Encoding` header), off by default. Already compressed content
+///   types (images, archives...) and partial (range) responses are left alone. Applies to servers
+///   created through [`service_fn`], other services can be wrapped by the
+///   [`compress`][HyperServer::compress] method. It contains:
+///   - `min-size`: Responses announcing a smaller body are not compressed. Defaults to 1024.
+///   - `level`: The compression level, from 0 to 9. Defaults to 6.
+/// * `request-timeout`: How long a request may take until the response is produced (eg. `"30s"`).
+///   The handler is cancelled and the client gets the 503 status code after that. Applies to
+///   servers created through [`service_fn`], other services can be wrapped by the
+///   [`timeout`][HyperServer::timeout] method. Unlimited by default.
+///
+/// When any of these change on configuration reload, the server is replaced by a new one with the
+/// new settings. The listening socket is kept (unless the `Transport` configuration changed as
+/// well).
+///
+/// The TCP-level keepalive of the accepted connections is configured by the `tcp-keepalive` option
+/// of the [`TcpListen`] transport (eg. `"30s"` or `false`). Changing it on reload keeps the
+/// listening socket and applies to the newly accepted connections.
+///
+/// Similarly, the OS buffers of the accepted sockets (`SO_RCVBUF` and `SO_SNDBUF`) are set by the
+/// `tcp-recv-buf-size` and `tcp-send-buf-size` options of the transport. Bigger send buffer may
+/// help with throughput of big responses. Note that the OS takes these only as hints ‒ for
+/// example Linux doubles the value (to leave room for its bookkeeping) and caps it by the
+/// `net.core.rmem_max` and `net.core.wmem_max` sysctls, other systems round it or refuse values
+/// out of their range.
+///
+/// Unknown options (for example misspelled ones) are refused with an error, so a typo doesn't
+/// silently fall back to the default. Therefore, the server must be in its own table (or array of
+/// tables), not flattened into another structure with more fields. If the application needs more
+/// options on the server, it can put them into the `ExtraCfg` of the transport.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
 #[serde(rename_all = "kebab-case")]
 pub struct HyperServer<Transport> {
@@ -192,6 +371,27 @@ pub struct HyperServer<Transport> {
     inner: HyperCfg,
 }
 
+// The derived deserialization, which ignores unknown options.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LenientHyperServer<Transport> {
+    #[serde(flatten)]
+    transport: Transport,
+
+    #[serde(flatten)]
+    inner: HyperCfg,
+}
+
+impl<'de, Transport: DeserializeOwned> Deserialize<'de> for HyperServer<Transport> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let server: LenientHyperServer<Transport> = strict::deserialize(d, "HTTP server")?;
+        Ok(HyperServer {
+            transport: server.transport,
+            inner: server.inner,
+        })
+    }
+}
+
 impl<Transport: Default> Default for HyperServer<Transport> {
     fn default() -> Self {
         HyperServer {
@@ -200,12 +400,47 @@ impl<Transport: Default> Default for HyperServer<Transport> {
                 http1_keepalive: true,
                 http1_writev: true,
                 http1_half_close: true,
+                http1_max_buf_size: None,
                 http_mode: HttpMode::default(),
+                access_log: false,
+                access_log_level: default_access_log_level(),
+                access_log_target: default_access_log_target(),
+                max_body_size: None,
+                cors: None,
+                compression: None,
+                request_timeout: None,
             },
         }
     }
 }
 
+impl<Transport> HyperServer<Transport> {
+    /// Wraps a service to handle cross-origin requests according to the `cors` configuration.
+    ///
+    /// If CORS is not configured, the service is left to handle the requests on its own. This is
+    /// done automatically for servers created through [`service_fn`].
+    pub fn cors<S>(&self, service: S) -> Cors<S> {
+        Cors::new(self.inner.cors.clone().map(Arc::new), service)
+    }
+
+    /// Wraps a service to compress its responses according to the `compression` configuration.
+    ///
+    /// If compression is not configured, the responses are left as they are. This is done
+    /// automatically for servers created through [`service_fn`].
+    pub fn compress<S>(&self, service: S) -> Compress<S> {
+        Compress::new(self.inner.compression.clone().map(Arc::new), service)
+    }
+
+    /// Wraps a service to limit the time to produce its responses by the `request-timeout`
+    /// configuration.
+    ///
+    /// If the timeout is not configured, the service may take as long as it wants. This is done
+    /// automatically for servers created through [`service_fn`].
+    pub fn timeout<S>(&self, service: S) -> Timeout<S> {
+        Timeout::new(self.inner.request_timeout, service)
+    }
+}
+
 impl<Transport: Comparable> Comparable for HyperServer<Transport> {
     fn compare(&self, other: &Self) -> Comparison {
         let transport_cmp = self.transport.compare(&other.transport);
@@ -229,6 +464,9 @@ where
     fn make_seed(&self, name: &'static str) -> Result<Self::Seed, Error> {
         self.transport.make_seed(name)
     }
+    fn identity(&self) -> Option<String> {
+        self.transport.identity()
+    }
     fn make_resource(
         &self,
         seed: &mut Self::Seed,
@@ -240,13 +478,32 @@ where
             HttpMode::Http1Only => (true, false),
             HttpMode::Http2Only => (false, true),
         };
+        if let Some(size) = self.inner.http1_max_buf_size {
+            // Hyper would panic with smaller one
+            ensure!(
+                size >= MIN_BUF_SIZE,
+                "The http1-max-buf-size of {} must be at least {}, got {}",
+                name,
+                MIN_BUF_SIZE,
+                size,
+            );
+        }
+        if let Some(ref cors) = self.inner.cors {
+            cors.validate(name)?;
+        }
+        if let Some(ref compression) = self.inner.compression {
+            compression.validate(name)?;
+        }
         let transport = self.transport.make_resource(seed, name)?;
-        let builder = Server::builder(transport.into_incoming())
+        let mut builder = Server::builder(transport.into_incoming())
             .http1_keepalive(self.inner.http1_keepalive)
             .http1_writev(self.inner.http1_writev)
             .http1_half_close(self.inner.http1_half_close)
             .http1_only(h1_only)
             .http2_only(h2_only);
+        if let Some(size) = self.inner.http1_max_buf_size {
+            builder = builder.http1_max_buf_size(size);
+        }
         Ok(builder)
     }
 }
@@ -254,8 +511,24 @@ where
 impl<Transport> Stackable for HyperServer<Transport> where Transport: Stackable {}
 
 /// A type alias for http (plain TCP) hyper server.
+///
+/// The transport is wrapped in [`WithLimits`], so the number of concurrent connections can be
+/// capped by the `max-conn` option. When the limit is reached, no new connections are accepted
+/// until some of the old ones terminate (they wait in the listen queue of the OS meanwhile). The
+/// number of active connections can be read from the connection through
+/// [`active_connections`][spirit_tokio::net::limits::LimitedConn::active_connections].
+///
+/// Errors when accepting (like running out of file descriptors) make the server back off for a
+/// while (see the `error-sleep`, `error-sleep-max` and `max-errors` options of
+/// [`Limits`][spirit_tokio::net::limits::Limits]) instead of retrying in a busy loop.
 pub type HttpServer<ExtraCfg = Empty> = HyperServer<WithLimits<TcpListen<ExtraCfg>>>;
 
+/// A type alias for https (TLS over TCP) hyper server.
+///
+/// The limits apply to the TCP connections, including the ones still in the TLS handshake.
+#[cfg(feature = "tls")]
+pub type HttpsServer<ExtraCfg = Empty> = HyperServer<TlsListen<WithLimits<TcpListen<ExtraCfg>>>>;
+
 struct ActivateInner<Transport, MS> {
     server: Server<Transport, MS>,
     receiver: Receiver<()>,
@@ -271,6 +544,7 @@ pub struct Activate<Transport, MS> {
     inner: Option<ActivateInner<Transport, MS>>,
     sender: Option<Sender<()>>,
     name: &'static str,
+    on_error: Option<ErrorHook>,
 }
 
 impl<Transport, MS> Drop for Activate<Transport, MS> {
@@ -297,12 +571,21 @@ where
     fn poll(&mut self) -> Poll<(), ()> {
         if let Some(inner) = self.inner.take() {
             let name = self.name;
+            let on_error = self.on_error.clone();
+            let running = RunningGuard::new();
             let server = inner
                 .server
                 .with_graceful_shutdown(inner.receiver)
                 .map_err(move |e| {
+                    if let Some(hook) = on_error {
+                        hook(&e, None);
+                    }
                     let e = e.context(format!("HTTP server {} failed", name));
                     spirit::log_error!(multi Error, e.into());
+                })
+                .then(move |result| {
+                    drop(running);
+                    result
                 });
             tokio::spawn(server);
         }
@@ -320,7 +603,8 @@ where
 /// It shall produce a [`Server`]. This is usually done through the [`serve`][Server::serve]
 /// method. It also pairs the resource with an [`Installer`][spirit::fragment::Installer].
 ///
-/// Note that a graceful shutdown of the [`Server`] is done as part of the automatic plumbing.
+/// Note that a graceful shutdown of the [`Server`] is done as part of the automatic plumbing. It
+/// is possible to wait for it to finish with [`wait_drained`].
 pub struct BuildServer<BS>(pub BS);
 
 impl<Transport, Inst, BS, Incoming, S, B>
@@ -351,6 +635,422 @@ where
             inner: Some(ActivateInner { server, receiver }),
             sender: Some(sender),
             name,
+            on_error: None,
         })
     }
 }
+
+/// The future handed out by the [`HandlerService`].
+pub type ResponseFuture =
+    Box<dyn Future<Item = Response<Body>, Error = Box<dyn EError + Send + Sync>> + Send>;
+
+/// A plumbing helper type.
+///
+/// The [`Service`] produced by the [`ServiceFn`] transformation. The user should not need to
+/// interact directly with this.
+pub struct HandlerService<Transport, F> {
+    handler: Arc<F>,
+    cfg: Arc<HyperServer<Transport>>,
+    peer: Option<SocketAddr>,
+    on_error: Option<ErrorHook>,
+    metrics: Option<Metrics>,
+}
+
+impl<Transport, F, R, B> Service for HandlerService<Transport, F>
+where
+    F: Fn(&HyperServer<Transport>, Request<Body>) -> R,
+    R: IntoFuture<Item = Response<B>>,
+    B: Payload,
+    R::Error: Into<Box<dyn EError + Send + Sync>> + 'static,
+    R::Future: Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = Box<dyn EError + Send + Sync>;
+    type Future = ResponseFuture;
+    fn call(&mut self, mut req: Request<Body>) -> ResponseFuture {
+        if let Some(peer) = self.peer {
+            req.extensions_mut().insert(RemoteAddr(peer));
+        }
+        let cfg = &self.cfg.inner;
+        let entry = if cfg.access_log {
+            Some(Entry::new(
+                cfg.access_log_level,
+                &cfg.access_log_target,
+                &req,
+                self.peer,
+            ))
+        } else {
+            None
+        };
+        let in_flight = self.metrics.as_ref().map(Metrics::start);
+        let handler = &self.handler;
+        let server_cfg = &self.cfg;
+        let handle = move |req| -> ResponseFuture {
+            let response = handler(server_cfg, req).into_future();
+            Box::new(response.map(payload::into_body).map_err(Into::into))
+        };
+        let peer = self.peer;
+        let handle = |req| match self.on_error {
+            Some(ref hook) => observe::observed(req, hook, peer, handle),
+            None => handle(req),
+        };
+        let handle = |req| match cfg.max_body_size {
+            Some(max) => body_limit::limited(req, max, handle),
+            None => handle(req),
+        };
+        let handle = |req| match cfg.request_timeout {
+            Some(timeout) => timeout::limited(req, timeout, handle),
+            None => handle(req),
+        };
+        let handle = |req| match cfg.compression {
+            Some(ref compression) => compression.handle(req, handle),
+            None => handle(req),
+        };
+        // CORS goes first, the preflight requests don't need to get to the handler at all.
+        let response = match cfg.cors {
+            Some(ref cors) => cors.handle(req, handle),
+            None => handle(req),
+        };
+        let response = match in_flight {
+            Some(in_flight) => in_flight.wrap(response),
+            None => response,
+        };
+        match entry {
+            Some(entry) => entry.wrap(response),
+            None => response,
+        }
+    }
+}
+
+/// A plumbing helper type.
+///
+/// Creates a [`HandlerService`] for each new connection. The user should not need to interact
+/// directly with this.
+pub struct MakeHandler<Transport, F> {
+    handler: Arc<F>,
+    cfg: Arc<HyperServer<Transport>>,
+    on_error: Option<ErrorHook>,
+    metrics: Option<Metrics>,
+}
+
+impl<'a, Conn, Transport, F, R, B> MakeService<&'a Conn> for MakeHandler<Transport, F>
+where
+    Conn: PeerAddr,
+    F: Fn(&HyperServer<Transport>, Request<Body>) -> R,
+    R: IntoFuture<Item = Response<B>>,
+    B: Payload,
+    R::Error: Into<Box<dyn EError + Send + Sync>> + 'static,
+    R::Future: Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = Box<dyn EError + Send + Sync>;
+    type Service = HandlerService<Transport, F>;
+    type Future = FutureResult<Self::Service, IoError>;
+    type MakeError = IoError;
+    fn make_service(&mut self, conn: &'a Conn) -> Self::Future {
+        future::ok(HandlerService {
+            handler: Arc::clone(&self.handler),
+            cfg: Arc::clone(&self.cfg),
+            peer: conn.peer(),
+            on_error: self.on_error.clone(),
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+/// A [`Transformation`] to serve requests by an asynchronous handler.
+///
+/// This is an alternative to [`BuildServer`] for the common case when the server only needs to
+/// call a function for each request. Unlike the [`service_fn_ok`][hyper::service::service_fn_ok]
+/// of hyper, the handler doesn't have to produce the [`Response`] right away, it returns a future
+/// of it (or anything convertible into one). This allows doing further IO, like querying a
+/// database, before answering.
+///
+/// The handler gets the configuration fragment ([`HyperServer`]) the server was created from and
+/// the request. If the handler needs access to the spirit itself, it can capture it. The address
+/// of the client is available in the request extensions as [`RemoteAddr`] (unless the transport
+/// has no addresses, like unix domain sockets).
+///
+/// The body of the response can be any [`Payload`], for example a custom type streaming
+/// server-sent events or a big download. Responses with the [`Body`] are passed on as they are,
+/// other payloads are streamed through one (their trailers are lost).
+///
+/// Create it by the [`service_fn`] function. If several servers from the same configuration need
+/// different handlers, the [`service_select`] function picks one for each of them.
+///
+/// The errors of handling the requests (the handler failing, or reading the request body failing,
+/// for example because the client reset the connection) can be observed by a callback set through
+/// the [`on_error`][ServiceFn::on_error] method. So can the failure of the whole server. Errors of
+/// the individual connections that hyper handles on its own before a request is formed (like
+/// clients sending garbage) are not reported ‒ hyper's server doesn't hand them out, it only logs
+/// them at the debug level under the `hyper` target.
+///
+/// The requests and their responses can be counted through the [`metrics`][ServiceFn::metrics]
+/// method.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures::future::{self, FutureResult};
+/// use hyper::{Body, Request, Response};
+/// use spirit::prelude::*;
+/// use spirit_hyper::HttpServer;
+///
+/// fn hello(_cfg: &HttpServer, _req: Request<Body>) -> FutureResult<Response<Body>, hyper::Error> {
+///     future::ok(Response::new(Body::from("Hello world\n")))
+/// }
+///
+/// Spirit::<Empty, HttpServer>::new()
+///     .config_defaults("port = 1234")
+///     .with(
+///         Pipeline::new("listen")
+///             .extract_cfg(HttpServer::clone)
+///             .transform(spirit_hyper::service_fn(hello))
+///     )
+/// #   .run(|spirit| {
+/// #       let spirit = std::sync::Arc::clone(spirit);
+/// #       std::thread::spawn(move || spirit.terminate());
+/// #       Ok(())
+/// #   });
+/// ```
+pub struct ServiceFn<F> {
+    handler: Arc<F>,
+    on_error: Option<ErrorHook>,
+    metrics: Option<Metrics>,
+}
+
+impl<F> ServiceFn<F> {
+    /// Sets a callback to observe errors of handling the requests.
+    ///
+    /// The callback gets the error and the address of the client (if the transport has
+    /// addresses). This can be used for example to count the failures or to detect scans. The
+    /// errors are still propagated as usual.
+    ///
+    /// The failure of the whole server is reported too, without an address. Errors on individual
+    /// connections that don't make it to a request (broken HTTP, for example) are handled inside
+    /// hyper and not reported.
+    pub fn on_error<C>(self, callback: C) -> Self
+    where
+        C: Fn(&(dyn EError + Send + Sync + 'static), Option<SocketAddr>) + Send + Sync + 'static,
+    {
+        ServiceFn {
+            on_error: Some(Arc::new(callback)),
+            ..self
+        }
+    }
+
+    /// Counts the handled requests into the given [`Metrics`].
+    ///
+    /// Without this, nothing is counted (and there's no overhead of the counting).
+    pub fn metrics(self, metrics: Metrics) -> Self {
+        ServiceFn {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+}
+
+/// Creates the [`ServiceFn`] transformation from an asynchronous request handler.
+pub fn service_fn<F>(handler: F) -> ServiceFn<F> {
+    ServiceFn {
+        handler: Arc::new(handler),
+        on_error: None,
+        metrics: None,
+    }
+}
+
+impl<Transport, Inst, F, R, B, Incoming>
+    Transformation<Builder<Incoming>, Inst, HyperServer<Transport>> for ServiceFn<F>
+where
+    Transport: Fragment + Clone + Send + Sync + 'static,
+    Transport::Resource: IntoIncoming<Incoming = Incoming, Connection = Incoming::Item>,
+    Incoming: Stream<Error = IoError> + Send + Sync + 'static,
+    Incoming::Item: AsyncRead + AsyncWrite + PeerAddr + Send + Sync + 'static,
+    F: Fn(&HyperServer<Transport>, Request<Body>) -> R + Send + Sync + 'static,
+    R: IntoFuture<Item = Response<B>>,
+    B: Payload,
+    R::Error: Into<Box<dyn EError + Send + Sync>> + 'static,
+    R::Future: Send + 'static,
+{
+    type OutputResource = Activate<Incoming, MakeHandler<Transport, F>>;
+    type OutputInstaller = FutureInstaller<Self::OutputResource>;
+    fn installer(&mut self, _ii: Inst, _name: &'static str) -> Self::OutputInstaller {
+        FutureInstaller::default()
+    }
+    fn transform(
+        &mut self,
+        builder: Builder<Incoming>,
+        cfg: &HyperServer<Transport>,
+        name: &'static str,
+    ) -> Result<Self::OutputResource, Error> {
+        let handler = &self.handler;
+        let on_error = &self.on_error;
+        let metrics = &self.metrics;
+        let build = |builder: Builder<Incoming>, cfg: &HyperServer<Transport>, _: &'static str| {
+            builder.serve(MakeHandler {
+                handler: Arc::clone(handler),
+                cfg: Arc::new(cfg.clone()),
+                on_error: on_error.clone(),
+                metrics: metrics.clone(),
+            })
+        };
+        let mut activate =
+            Transformation::<_, Inst, _>::transform(&mut BuildServer(build), builder, cfg, name)?;
+        activate.on_error = on_error.clone();
+        Ok(activate)
+    }
+}
+
+/// A request handler with its type erased.
+///
+/// This allows a [`service_select`] selector to pick from handlers of different types. Create it
+/// by the [`boxed_handler`] function.
+pub type BoxedHandler<Transport> =
+    Box<dyn Fn(&HyperServer<Transport>, Request<Body>) -> ResponseFuture + Send + Sync>;
+
+/// Erases the type of an asynchronous request handler.
+///
+/// The handler is the same as the one accepted by [`service_fn`].
+pub fn boxed_handler<Transport, F, R, B>(handler: F) -> BoxedHandler<Transport>
+where
+    F: Fn(&HyperServer<Transport>, Request<Body>) -> R + Send + Sync + 'static,
+    R: IntoFuture<Item = Response<B>>,
+    B: Payload,
+    R::Error: Into<Box<dyn EError + Send + Sync>> + 'static,
+    R::Future: Send + 'static,
+{
+    Box::new(
+        move |cfg: &HyperServer<Transport>, req: Request<Body>| -> ResponseFuture {
+            let response = handler(cfg, req).into_future();
+            Box::new(response.map(payload::into_body).map_err(Into::into))
+        },
+    )
+}
+
+/// A plumbing helper type.
+///
+/// The handler selector inside the [`ServiceFn`] created by [`service_select`]. The user should
+/// not need to interact directly with this.
+pub struct Select<S>(S);
+
+/// Creates the [`ServiceFn`] transformation choosing the request handler for each listener.
+///
+/// This is for the case when several servers come from the same configuration (for example an
+/// array of them), but they need to do different things. The selector is called with the
+/// configuration of each server when it is being created (on the first load or when its
+/// configuration changes) and returns the handler to serve that server's requests. Usually the
+/// decision is done based on the `ExtraCfg` of the listener.
+///
+/// As different handlers are usually of different types, they need to be wrapped by
+/// [`boxed_handler`].
+///
+/// # Examples
+///
+/// ```rust
+/// use std::collections::HashSet;
+///
+/// use futures::future::{self, FutureResult};
+/// use hyper::{Body, Request, Response};
+/// use serde::{Deserialize, Serialize};
+/// use spirit::prelude::*;
+/// use spirit_hyper::HttpServer;
+///
+/// #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Hash, Serialize)]
+/// struct Role {
+///     #[serde(default)]
+///     admin: bool,
+/// }
+///
+/// type Server = HttpServer<Role>;
+///
+/// const DEFAULT_CONFIG: &str = r#"
+/// [[listen]]
+/// port = 1234
+///
+/// [[listen]]
+/// port = 1235
+/// host = "127.0.0.1"
+/// admin = true
+/// "#;
+///
+/// #[derive(Default, Deserialize)]
+/// struct Config {
+///     listen: HashSet<Server>,
+/// }
+///
+/// impl Config {
+///     fn listen(&self) -> HashSet<Server> {
+///         self.listen.clone()
+///     }
+/// }
+///
+/// fn public(_cfg: &Server, _req: Request<Body>) -> FutureResult<Response<Body>, hyper::Error> {
+///     future::ok(Response::new(Body::from("Hello world\n")))
+/// }
+///
+/// fn admin(_cfg: &Server, _req: Request<Body>) -> FutureResult<Response<Body>, hyper::Error> {
+///     future::ok(Response::new(Body::from("Hello admin\n")))
+/// }
+///
+/// Spirit::<Empty, Config>::new()
+///     .config_defaults(DEFAULT_CONFIG)
+///     .with(
+///         Pipeline::new("listen")
+///             .extract_cfg(Config::listen)
+///             .transform(spirit_hyper::service_select(|cfg: &Server| {
+///                 if cfg.transport.listener.extra_cfg.admin {
+///                     spirit_hyper::boxed_handler(admin)
+///                 } else {
+///                     spirit_hyper::boxed_handler(public)
+///                 }
+///             }))
+///     )
+/// #   .run(|spirit| {
+/// #       let spirit = std::sync::Arc::clone(spirit);
+/// #       std::thread::spawn(move || spirit.terminate());
+/// #       Ok(())
+/// #   });
+/// ```
+pub fn service_select<S>(selector: S) -> ServiceFn<Select<S>> {
+    service_fn(Select(selector))
+}
+
+impl<Transport, Inst, S, Incoming> Transformation<Builder<Incoming>, Inst, HyperServer<Transport>>
+    for ServiceFn<Select<S>>
+where
+    Transport: Fragment + Clone + Send + Sync + 'static,
+    Transport::Resource: IntoIncoming<Incoming = Incoming, Connection = Incoming::Item>,
+    Incoming: Stream<Error = IoError> + Send + Sync + 'static,
+    Incoming::Item: AsyncRead + AsyncWrite + PeerAddr + Send + Sync + 'static,
+    S: Fn(&HyperServer<Transport>) -> BoxedHandler<Transport> + Send + Sync + 'static,
+{
+    type OutputResource = Activate<Incoming, MakeHandler<Transport, BoxedHandler<Transport>>>;
+    type OutputInstaller = FutureInstaller<Self::OutputResource>;
+    fn installer(&mut self, _ii: Inst, _name: &'static str) -> Self::OutputInstaller {
+        FutureInstaller::default()
+    }
+    fn transform(
+        &mut self,
+        builder: Builder<Incoming>,
+        cfg: &HyperServer<Transport>,
+        name: &'static str,
+    ) -> Result<Self::OutputResource, Error> {
+        let selector = &self.handler.0;
+        let on_error = &self.on_error;
+        let metrics = &self.metrics;
+        let build = |builder: Builder<Incoming>, cfg: &HyperServer<Transport>, _: &'static str| {
+            builder.serve(MakeHandler {
+                handler: Arc::new(selector(cfg)),
+                cfg: Arc::new(cfg.clone()),
+                on_error: on_error.clone(),
+                metrics: metrics.clone(),
+            })
+        };
+        let mut activate =
+            Transformation::<_, Inst, _>::transform(&mut BuildServer(build), builder, cfg, name)?;
+        activate.on_error = on_error.clone();
+        Ok(activate)
+    }
+}
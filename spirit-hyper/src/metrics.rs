@@ -0,0 +1,216 @@
+//! Counting of the handled requests.
+//!
+//! The [`Metrics`] handle is passed to the [`ServiceFn`][crate::ServiceFn] through its
+//! [`metrics`][crate::ServiceFn::metrics] method. The server then counts the requests it handles
+//! into it and the application can read the counters at any time (for example to expose them on
+//! a `/metrics` endpoint).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::{Future, Poll, Stream};
+use hyper::{Body, Chunk, Response};
+
+use crate::payload;
+use crate::ResponseFuture;
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    in_flight: AtomicU64,
+    responses_2xx: AtomicU64,
+    responses_3xx: AtomicU64,
+    responses_4xx: AtomicU64,
+    responses_5xx: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// A handle to the counters of handled requests.
+///
+/// It can be cloned and shared among multiple servers, in which case they count together.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures::future::{self, FutureResult};
+/// use hyper::{Body, Request, Response};
+/// use spirit::prelude::*;
+/// use spirit_hyper::metrics::Metrics;
+/// use spirit_hyper::HttpServer;
+///
+/// let metrics = Metrics::new();
+/// let handle = metrics.clone();
+/// let hello = move |_: &HttpServer, _: Request<Body>| -> FutureResult<_, hyper::Error> {
+///     let text = format!("Handled {} requests\n", handle.requests());
+///     future::ok(Response::new(Body::from(text)))
+/// };
+///
+/// Spirit::<Empty, HttpServer>::new()
+///     .config_defaults("port = 1234")
+///     .with(
+///         Pipeline::new("listen")
+///             .extract_cfg(HttpServer::clone)
+///             .transform(spirit_hyper::service_fn(hello).metrics(metrics))
+///     )
+/// #   .run(|spirit| {
+/// #       let spirit = std::sync::Arc::clone(spirit);
+/// #       std::thread::spawn(move || spirit.terminate());
+/// #       Ok(())
+/// #   });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Counters>);
+
+impl Metrics {
+    /// Creates a new set of counters, all zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The total number of requests received.
+    pub fn requests(&self) -> u64 {
+        self.0.requests.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests currently being handled.
+    ///
+    /// A request is in flight until its response body is completely sent (or abandoned).
+    pub fn in_flight(&self) -> u64 {
+        self.0.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The number of successful (2xx) responses.
+    pub fn responses_2xx(&self) -> u64 {
+        self.0.responses_2xx.load(Ordering::Relaxed)
+    }
+
+    /// The number of redirection (3xx) responses.
+    pub fn responses_3xx(&self) -> u64 {
+        self.0.responses_3xx.load(Ordering::Relaxed)
+    }
+
+    /// The number of client error (4xx) responses.
+    pub fn responses_4xx(&self) -> u64 {
+        self.0.responses_4xx.load(Ordering::Relaxed)
+    }
+
+    /// The number of server error (5xx) responses.
+    pub fn responses_5xx(&self) -> u64 {
+        self.0.responses_5xx.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests for which the handler failed to produce any response.
+    ///
+    /// Hyper closes the connection in such case.
+    pub fn failed(&self) -> u64 {
+        self.0.failed.load(Ordering::Relaxed)
+    }
+
+    /// Counts a new request, before it is passed to the handler.
+    pub(crate) fn start(&self) -> InFlight {
+        self.0.requests.fetch_add(1, Ordering::Relaxed);
+        self.0.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+}
+
+/// A request being handled.
+///
+/// It leaves the in-flight requests when dropped.
+pub(crate) struct InFlight(Metrics);
+
+impl InFlight {
+    /// Wraps the future of the response, so the response gets counted.
+    pub(crate) fn wrap(self, response: ResponseFuture) -> ResponseFuture {
+        let response = response.then(move |result| {
+            let counters = &(self.0).0;
+            let counter = match result {
+                Ok(ref response) => match response.status().as_u16() / 100 {
+                    2 => Some(&counters.responses_2xx),
+                    3 => Some(&counters.responses_3xx),
+                    4 => Some(&counters.responses_4xx),
+                    5 => Some(&counters.responses_5xx),
+                    _ => None,
+                },
+                Err(_) => Some(&counters.failed),
+            };
+            if let Some(counter) = counter {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            result.map(|response| {
+                let (mut parts, body) = response.into_parts();
+                payload::keep_length(&mut parts, &body);
+                let body = CountedBody {
+                    inner: body,
+                    _in_flight: self,
+                };
+                Response::from_parts(parts, Body::wrap_stream(body))
+            })
+        });
+        Box::new(response)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        (self.0).0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The response body, keeping the request in flight until it is sent.
+struct CountedBody {
+    inner: Body,
+    _in_flight: InFlight,
+}
+
+impl Stream for CountedBody {
+    type Item = Chunk;
+    type Error = hyper::Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+        self.inner.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use hyper::StatusCode;
+
+    use super::*;
+
+    fn respond(metrics: &Metrics, status: StatusCode) -> Response<Body> {
+        let mut response = Response::new(Body::from("Hello"));
+        *response.status_mut() = status;
+        metrics
+            .start()
+            .wrap(Box::new(future::ok(response)))
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn counts() {
+        let metrics = Metrics::new();
+        let ok = respond(&metrics, StatusCode::OK);
+        let not_found = respond(&metrics, StatusCode::NOT_FOUND);
+        drop(not_found);
+        assert_eq!(2, metrics.requests());
+        assert_eq!(1, metrics.in_flight());
+        assert_eq!(1, metrics.responses_2xx());
+        assert_eq!(1, metrics.responses_4xx());
+        // Still in flight until the body is sent
+        let body = ok.into_body().concat2().wait().unwrap();
+        assert_eq!(b"Hello", &body[..]);
+        assert_eq!(0, metrics.in_flight());
+
+        let failed = metrics
+            .start()
+            .wrap(Box::new(future::err("Boom".into())))
+            .wait();
+        assert!(failed.is_err());
+        assert_eq!(1, metrics.failed());
+        assert_eq!(3, metrics.requests());
+        assert_eq!(0, metrics.in_flight());
+        assert_eq!(0, metrics.responses_5xx());
+    }
+}
@@ -0,0 +1,116 @@
+//! Reporting the errors of handling requests to a user callback.
+
+use std::error::Error as EError;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::{Future, Poll, Stream};
+use hyper::{Body, Chunk, Request};
+
+use crate::ResponseFuture;
+
+/// A callback observing the errors of handling requests.
+///
+/// Gets the error and the address of the client, if known.
+pub(crate) type ErrorHook =
+    Arc<dyn Fn(&(dyn EError + Send + Sync + 'static), Option<SocketAddr>) + Send + Sync>;
+
+/// A request body reporting errors of reading it (eg. the client resetting the connection).
+struct ObservedBody {
+    inner: Body,
+    hook: ErrorHook,
+    peer: Option<SocketAddr>,
+    failed: Arc<AtomicBool>,
+}
+
+impl Stream for ObservedBody {
+    type Item = Chunk;
+    type Error = hyper::Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+        self.inner.poll().inspect_err(|e| {
+            self.failed.store(true, Ordering::Relaxed);
+            (self.hook)(e, self.peer);
+        })
+    }
+}
+
+/// Calls the handler and reports the errors of reading the request body or of the handler.
+///
+/// A handler failing because the body failed is reported only once.
+pub(crate) fn observed<H>(
+    req: Request<Body>,
+    hook: &ErrorHook,
+    peer: Option<SocketAddr>,
+    handler: H,
+) -> ResponseFuture
+where
+    H: FnOnce(Request<Body>) -> ResponseFuture,
+{
+    let failed = Arc::new(AtomicBool::new(false));
+    let (parts, body) = req.into_parts();
+    let body = ObservedBody {
+        inner: body,
+        hook: Arc::clone(hook),
+        peer,
+        failed: Arc::clone(&failed),
+    };
+    let req = Request::from_parts(parts, Body::wrap_stream(body));
+    let hook = Arc::clone(hook);
+    let response = handler(req).map_err(move |e| {
+        if !failed.load(Ordering::Relaxed) {
+            hook(&*e, peer);
+        }
+        e
+    });
+    Box::new(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error as IoError, ErrorKind};
+    use std::sync::Mutex;
+
+    use futures::{future, stream};
+    use hyper::Response;
+
+    use super::*;
+
+    fn consume(req: Request<Body>) -> ResponseFuture {
+        let response = req
+            .into_body()
+            .concat2()
+            .map(|body| Response::new(Body::from(body)))
+            .map_err(Into::into);
+        Box::new(response)
+    }
+
+    #[test]
+    fn reported() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let hook: ErrorHook = {
+            let errors = Arc::clone(&errors);
+            Arc::new(move |e, peer| errors.lock().unwrap().push((e.to_string(), peer)))
+        };
+        let peer: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+
+        let ok = Request::new(Body::from("hello"));
+        assert!(observed(ok, &hook, Some(peer), consume).wait().is_ok());
+        assert!(errors.lock().unwrap().is_empty());
+
+        // Reported just once, even though the handler fails too
+        let chunks = vec![
+            Ok("he"),
+            Err(IoError::new(ErrorKind::ConnectionReset, "Reset")),
+        ];
+        let reset = Request::new(Body::wrap_stream(stream::iter_result(chunks)));
+        assert!(observed(reset, &hook, Some(peer), consume).wait().is_err());
+        assert_eq!(1, errors.lock().unwrap().len());
+        assert_eq!(Some(peer), errors.lock().unwrap()[0].1);
+
+        let failing = |_| -> ResponseFuture { Box::new(future::err("Broken".into())) };
+        let req = Request::new(Body::empty());
+        assert!(observed(req, &hook, None, failing).wait().is_err());
+        assert_eq!(("Broken".to_owned(), None), errors.lock().unwrap()[1]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Turning responses with arbitrary bodies into ones with the [`Body`].
+
+use std::any::Any;
+use std::error::Error as EError;
+
+use bytes::{Buf, Bytes};
+use futures::{try_ready, Async, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{HeaderValue, CONTENT_LENGTH};
+use hyper::http::response::Parts;
+use hyper::{Body, Chunk, Response, StatusCode};
+
+/// Puts the length of the body into the headers, if known.
+///
+/// Wrapping a body into a stream loses the information about its length, but it can be kept in
+/// the headers (where it is allowed).
+pub(crate) fn keep_length<B: Payload>(parts: &mut Parts, body: &B) {
+    let status = parts.status;
+    let bodyless = status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED;
+    if let (Some(len), false) = (body.content_length(), bodyless) {
+        parts
+            .headers
+            .entry(CONTENT_LENGTH)
+            .expect("Static header name")
+            .or_insert_with(|| HeaderValue::from(len));
+    }
+}
+
+struct PayloadStream<B>(B);
+
+impl<B: Payload> Stream for PayloadStream<B> {
+    type Item = Chunk;
+    type Error = Box<dyn EError + Send + Sync>;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Self::Error> {
+        let data = try_ready!(self.0.poll_data().map_err(Into::into));
+        Ok(Async::Ready(
+            data.map(|data| Chunk::from(data.collect::<Bytes>())),
+        ))
+    }
+}
+
+/// Converts the response body into the [`Body`].
+///
+/// A [`Body`] is passed through as it is, other payloads are streamed through it (the trailers
+/// are lost in such case).
+pub(crate) fn into_body<B: Payload>(response: Response<B>) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    let body = match (Box::new(body) as Box<dyn Any>).downcast::<Body>() {
+        Ok(body) => return Response::from_parts(parts, *body),
+        Err(body) => *body.downcast::<B>().expect("Type changed under our hands"),
+    };
+    keep_length(&mut parts, &body);
+    Response::from_parts(parts, Body::wrap_stream(PayloadStream(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use futures::Future;
+
+    use super::*;
+
+    struct Chunks(VecDeque<&'static str>);
+
+    impl Payload for Chunks {
+        type Data = Chunk;
+        type Error = hyper::Error;
+        fn poll_data(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+            Ok(Async::Ready(self.0.pop_front().map(Chunk::from)))
+        }
+        fn content_length(&self) -> Option<u64> {
+            Some(self.0.iter().map(|c| c.len() as u64).sum())
+        }
+    }
+
+    #[test]
+    fn custom_payload() {
+        let chunks = Chunks(vec!["Hello", " ", "world"].into());
+        let response = into_body(Response::new(chunks));
+        assert_eq!("11", response.headers()[CONTENT_LENGTH]);
+        let body = response.into_body().concat2().wait().unwrap();
+        assert_eq!(b"Hello world", &body[..]);
+    }
+
+    #[test]
+    fn body_kept() {
+        let response = into_body(Response::new(Body::from("Hello")));
+        // Passed through, not streamed
+        assert_eq!(Some(5), response.body().content_length());
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+    }
+}
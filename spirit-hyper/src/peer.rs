@@ -0,0 +1,82 @@
+//! Finding out who is on the other side of a connection.
+//!
+//! The [`ServiceFn`][crate::ServiceFn] transformation puts the [`RemoteAddr`] into the
+//! [extensions][hyper::Request::extensions] of each request, if the connection has one. When
+//! building the server manually through [`BuildServer`][crate::BuildServer], the [`PeerAddr`]
+//! trait can be used with hyper's [`make_service_fn`][hyper::service::make_service_fn] to get the
+//! same information.
+
+use std::net::SocketAddr;
+
+use spirit_tokio::either::Either;
+use spirit_tokio::net::limits::LimitedConn;
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+/// The address of the remote side of the connection a request came on.
+///
+/// Inserted into the request extensions by the [`ServiceFn`][crate::ServiceFn].
+///
+/// # Examples
+///
+/// ```rust
+/// use hyper::{Body, Request, Response};
+/// use spirit_hyper::peer::RemoteAddr;
+///
+/// fn hello(req: Request<Body>) -> Response<Body> {
+///     let msg = match req.extensions().get::<RemoteAddr>() {
+///         Some(RemoteAddr(addr)) => format!("Hello {}\n", addr.ip()),
+///         None => "Hello stranger\n".to_owned(),
+///     };
+///     Response::new(Body::from(msg))
+/// }
+/// # hello(Request::new(Body::empty()));
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Connections that may know the address of the other side.
+///
+/// Implemented for the connections produced by the transports of this and the
+/// [`spirit-tokio`][spirit_tokio] crate. Connections without a network address (like unix domain
+/// sockets) return `None`.
+pub trait PeerAddr {
+    /// Returns the address of the remote side, if it is known.
+    fn peer(&self) -> Option<SocketAddr>;
+}
+
+impl PeerAddr for TcpStream {
+    fn peer(&self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+}
+
+#[cfg(unix)]
+impl PeerAddr for UnixStream {
+    fn peer(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl<I: PeerAddr> PeerAddr for LimitedConn<I> {
+    fn peer(&self) -> Option<SocketAddr> {
+        (**self).peer()
+    }
+}
+
+impl<A: PeerAddr, B: PeerAddr> PeerAddr for Either<A, B> {
+    fn peer(&self) -> Option<SocketAddr> {
+        match self {
+            Either::A(a) => a.peer(),
+            Either::B(b) => b.peer(),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<I: PeerAddr> PeerAddr for tokio_tls::TlsStream<I> {
+    fn peer(&self) -> Option<SocketAddr> {
+        self.get_ref().get_ref().peer()
+    }
+}
@@ -0,0 +1,112 @@
+//! Refusing unknown configuration options.
+//!
+//! The configuration structures here are composed of several flattened parts, which doesn't work
+//! with serde's `deny_unknown_fields` (and the flattening also hides the ignored keys from tools
+//! like `serde_ignored`). Instead, each key is probed ‒ if replacing its value with something of
+//! a wrong type doesn't break the parsing, nobody reads the key.
+
+use std::collections::BTreeMap;
+
+use serde::de::{DeserializeOwned, Deserializer, Error as DeError};
+use serde::Deserialize;
+use serde_value::Value;
+
+/// Values no real option accepts all of.
+///
+/// A string option refuses the bool, most other options refuse the string.
+fn probes() -> [Value; 2] {
+    [
+        Value::Bool(true),
+        Value::String("\u{0}spirit-unknown-option-probe".to_owned()),
+    ]
+}
+
+fn is_known<T: DeserializeOwned>(raw: &BTreeMap<Value, Value>, key: &Value) -> bool {
+    probes().iter().any(|probe| {
+        let mut probed = raw.clone();
+        probed.insert(key.clone(), probe.clone());
+        Value::Map(probed).deserialize_into::<T>().is_err()
+    })
+}
+
+fn show(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => format!("{:?}", key),
+    }
+}
+
+/// Deserializes the `T`, failing if the input contains keys that `T` doesn't know.
+///
+/// The `what` names the thing in the error message. The error also lists the known options of
+/// the entry, to make it possible to find which one is wrong if there are multiple.
+pub(crate) fn deserialize<'de, D, T>(d: D, what: &str) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let raw = BTreeMap::<Value, Value>::deserialize(d)?;
+    let value = Value::Map(raw.clone())
+        .deserialize_into()
+        .map_err(|e| e.into_error())?;
+    let (unknown, valid): (Vec<_>, Vec<_>) = raw.keys().partition(|key| !is_known::<T>(&raw, key));
+    if unknown.is_empty() {
+        return Ok(value);
+    }
+    let unknown = unknown
+        .iter()
+        .map(|key| format!("`{}`", show(key)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let valid = valid
+        .iter()
+        .map(|key| show(key))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(D::Error::custom(format!(
+        "Unknown option {} in {} {{ {} }}",
+        unknown, what, valid
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HttpServer;
+
+    #[test]
+    fn known_options() {
+        let cfg = r#"
+            port = 1234
+            max-conn = 20
+            http-mode = "http1-only"
+            max-body-size = 1024
+            http1-keepalive = false
+            tcp-keepalive = "30s"
+        "#;
+        let server: HttpServer = toml::from_str(cfg).unwrap();
+        assert_eq!(Some(1024), server.inner.max_body_size);
+        assert!(!server.inner.http1_keepalive);
+    }
+
+    #[test]
+    fn unknown_option() {
+        let cfg = r#"
+            port = 1234
+            bcaklog = 20
+        "#;
+        let err = toml::from_str::<HttpServer>(cfg).unwrap_err().to_string();
+        assert!(err.contains("`bcaklog`"), "{}", err);
+        assert!(err.contains("port"), "{}", err);
+    }
+
+    #[test]
+    fn optional_and_aliased_options() {
+        // Options that are None or not serialized back are still known
+        let cfg = r#"
+            port = 1234
+            host = "127.0.0.1"
+            max-conn = 20
+        "#;
+        toml::from_str::<HttpServer>(cfg).unwrap();
+    }
+}
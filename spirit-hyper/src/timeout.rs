@@ -0,0 +1,127 @@
+//! Limiting the time to handle a request.
+//!
+//! The [`Timeout`] service wrapper answers with the 503 status code if the service doesn't produce
+//! its response in time. The unfinished response future (and whatever the handler is doing in it)
+//! is dropped, so a stuck handler doesn't hold onto its resources forever.
+
+use std::error::Error as EError;
+use std::time::Duration;
+
+use futures::Future;
+use hyper::service::Service;
+use hyper::{Body, Request, Response, StatusCode};
+use log::{debug, error};
+use tokio::timer::Timeout as TimeoutFuture;
+
+fn timed_out() -> Response<Body> {
+    let mut response = Response::new(Body::from("Request timed out\n"));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response
+}
+
+/// Calls the handler, answering with the 503 status code if its response doesn't come in time.
+///
+/// Note that this covers the time until the response (its headers) is produced. Sending of the
+/// response body is not limited.
+pub(crate) fn limited<H, E>(
+    req: Request<Body>,
+    timeout: Duration,
+    handler: H,
+) -> Box<dyn Future<Item = Response<Body>, Error = E> + Send>
+where
+    H: FnOnce(Request<Body>) -> Box<dyn Future<Item = Response<Body>, Error = E> + Send>,
+    E: Send + 'static,
+{
+    let response = TimeoutFuture::new(handler(req), timeout).or_else(move |e| {
+        if e.is_inner() {
+            return Err(e.into_inner().unwrap());
+        }
+        if e.is_elapsed() {
+            debug!("Request not handled in {:?}", timeout);
+        } else {
+            // No timer in here. Refusing every request is better than ignoring the timeout, as
+            // it'll get noticed.
+            error!("Can't limit the request time, no timer available");
+        }
+        Ok(timed_out())
+    });
+    Box::new(response)
+}
+
+/// A [`Service`] wrapper limiting the time to produce the responses.
+///
+/// Usually created through [`HyperServer::timeout`][crate::HyperServer::timeout]. If no timeout
+/// is configured, the responses are passed through unchanged.
+///
+/// The service must run inside a tokio runtime with a timer (which is the case of the runtime
+/// spirit uses).
+pub struct Timeout<S> {
+    timeout: Option<Duration>,
+    inner: S,
+}
+
+impl<S> Timeout<S> {
+    /// Wraps the service.
+    pub fn new(timeout: Option<Duration>, inner: S) -> Self {
+        Timeout { timeout, inner }
+    }
+}
+
+impl<S> Service for Timeout<S>
+where
+    S: Service<ReqBody = Body, ResBody = Body>,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn EError + Send + Sync>> + Send + 'static,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = S::Error;
+    type Future = Box<dyn Future<Item = Response<Body>, Error = S::Error> + Send>;
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let inner = &mut self.inner;
+        let mut handler = |req| -> Self::Future { Box::new(inner.call(req)) };
+        match self.timeout {
+            Some(timeout) => limited(req, timeout, handler),
+            None => handler(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use tokio::runtime::current_thread;
+    use tokio::timer::Delay;
+
+    use super::*;
+
+    type Handled = Box<dyn Future<Item = Response<Body>, Error = ()> + Send>;
+
+    fn status(handler: Handled) -> StatusCode {
+        let response = limited(
+            Request::new(Body::empty()),
+            Duration::from_millis(50),
+            |_| handler,
+        );
+        current_thread::block_on_all(response).unwrap().status()
+    }
+
+    #[test]
+    fn timeout() {
+        assert_eq!(
+            StatusCode::OK,
+            status(Box::new(future::ok(Response::new(Body::empty()))))
+        );
+        let stuck = Delay::new(std::time::Instant::now() + Duration::from_secs(3600))
+            .map_err(|_| ())
+            .map(|()| Response::new(Body::empty()));
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status(Box::new(stuck)));
+        let failed = future::err(());
+        assert!(current_thread::block_on_all(limited(
+            Request::new(Body::empty()),
+            Duration::from_millis(50),
+            |_| -> Handled { Box::new(failed) }
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Serving HTTPS.
+//!
+//! This contains the [`TlsListen`] wrapper, which adds TLS encryption on top of another listener
+//! [`Fragment`]. The [`HttpsServer`][crate::HttpsServer] type alias uses it.
+
+use std::fmt::Debug;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use failure::{ensure, Error, ResultExt};
+use futures::stream::FuturesUnordered;
+use futures::{Async, Poll, Stream};
+use log::{debug, warn};
+use native_tls::{Identity, TlsAcceptor as NativeAcceptor};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use spirit::extension::Extensible;
+use spirit::fragment::driver::{CacheSimilar, Comparable, Comparison};
+use spirit::fragment::{Fragment, Stackable};
+use spirit::utils::serialize_duration;
+use spirit_tokio::net::IntoIncoming;
+#[cfg(feature = "cfg-help")]
+use structdoc::StructDoc;
+use structopt::StructOpt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Timeout;
+use tokio_tls::{Accept, TlsAcceptor, TlsStream};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+struct TlsCfg {
+    /// Path to the PEM file with the server certificate.
+    ///
+    /// It may contain the whole chain of intermediate certificates after the server one.
+    cert: PathBuf,
+
+    /// Path to the PEM file with the private key of the server certificate (in PKCS#8 format).
+    key: PathBuf,
+
+    /// How long a client may take to finish the TLS handshake.
+    ///
+    /// Connections that don't make it in time are closed.
+    #[serde(
+        default = "default_handshake_timeout",
+        deserialize_with = "serde_humantime::deserialize",
+        serialize_with = "serialize_duration"
+    )]
+    handshake_timeout: Duration,
+
+    /// How many TLS handshakes may be in progress at once.
+    ///
+    /// No new connections are accepted while the limit is reached.
+    #[serde(default = "default_max_handshakes")]
+    max_handshakes: usize,
+}
+
+fn default_handshake_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_handshakes() -> usize {
+    100
+}
+
+/// A wrapper around a listening socket [`Fragment`] that adds TLS to the accepted connections.
+///
+/// The connections produced are [`TlsStream`]s, so they can be used by whatever used the inner
+/// connections before (for example the [`HyperServer`][crate::HyperServer]).
+///
+/// Connections that fail the TLS handshake (or don't finish it in time) are logged and dropped,
+/// they don't terminate the listener.
+///
+/// # Configuration options
+///
+/// In addition to options of the inner `Listener`, these are added:
+///
+/// * `cert`: Path to the certificate (or certificate chain) in PEM format.
+/// * `key`: Path to the private key in PKCS#8 PEM format.
+/// * `handshake-timeout`: How long a client may take to finish the handshake, 10s by default.
+/// * `max-handshakes`: How many handshakes may be in progress at once, 100 by default. New
+///   connections are not accepted while the limit is reached.
+///
+/// # Certificate reload
+///
+/// The certificate and key files are read whenever the resource is created. When the
+/// configuration is reloaded (eg. on `SIGHUP`, if the application is set up to do so), the files
+/// are read again even if their paths didn't change, so it is enough to replace the files and
+/// trigger the reload. The listening socket itself is kept, only the server on top of it is
+/// replaced. Already established connections are left to finish with the old certificate.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use hyper::{Body, Request, Response};
+/// use hyper::server::Builder;
+/// use hyper::service::service_fn_ok;
+/// use serde::Deserialize;
+/// use spirit::prelude::*;
+/// use spirit_hyper::{BuildServer, HttpsServer};
+///
+/// const DEFAULT_CONFIG: &str = r#"
+/// [[server]]
+/// port = 1234
+/// cert = "/etc/ssl/server.pem"
+/// key = "/etc/ssl/server.key"
+/// "#;
+///
+/// // There's no sensible default for the certificate, so make the servers a list
+/// #[derive(Default, Deserialize)]
+/// struct Config {
+///     server: Vec<HttpsServer>,
+/// }
+///
+/// impl Config {
+///     fn server(&self) -> Vec<HttpsServer> {
+///         self.server.clone()
+///     }
+/// }
+///
+/// fn request(_req: Request<Body>) -> Response<Body> {
+///     Response::new(Body::from("Hello secret world\n"))
+/// }
+///
+/// Spirit::<Empty, Config>::new()
+///     .config_defaults(DEFAULT_CONFIG)
+///     .with(
+///         Pipeline::new("listen")
+///             .extract_cfg(Config::server)
+///             .transform(BuildServer(|builder: Builder<_>, _cfg: &_, _name: &str| {
+///                 builder.serve(|| service_fn_ok(request))
+///             }))
+///     )
+///     .run(|_| Ok(()));
+/// ```
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+pub struct TlsListen<Listener> {
+    /// The inner listener socket.
+    ///
+    /// This is available publicly to allow reading the extra configuration out of it.
+    #[serde(flatten)]
+    pub listener: Listener,
+
+    #[serde(flatten)]
+    tls: TlsCfg,
+}
+
+impl<Listener> Stackable for TlsListen<Listener> where Listener: Stackable {}
+
+impl<Listener: Comparable> Comparable for TlsListen<Listener> {
+    fn compare(&self, other: &Self) -> Comparison {
+        match self.listener.compare(&other.listener) {
+            // The content of the files could have changed even if the paths didn't, we want to
+            // re-read them.
+            Comparison::Same => Comparison::Similar,
+            cmp => cmp,
+        }
+    }
+}
+
+impl TlsCfg {
+    fn acceptor(&self) -> Result<NativeAcceptor, Error> {
+        let cert = fs::read(&self.cert)
+            .with_context(|_| format!("Failed to read certificate {}", self.cert.display()))?;
+        let key = fs::read(&self.key)
+            .with_context(|_| format!("Failed to read key {}", self.key.display()))?;
+        let identity = Identity::from_pkcs8(&cert, &key).context("Invalid certificate or key")?;
+        let acceptor = NativeAcceptor::new(identity).context("Failed to set up TLS")?;
+        Ok(acceptor)
+    }
+}
+
+impl<Listener> Fragment for TlsListen<Listener>
+where
+    Listener: Clone + Debug + Fragment + Comparable,
+{
+    type Driver = CacheSimilar<Self>;
+    type Installer = ();
+    type Seed = Listener::Seed;
+    type Resource = TlsListener<Listener::Resource>;
+    const RUN_BEFORE_CONFIG: bool = Listener::RUN_BEFORE_CONFIG;
+    fn make_seed(&self, name: &'static str) -> Result<Self::Seed, Error> {
+        self.listener.make_seed(name)
+    }
+    fn make_resource(
+        &self,
+        seed: &mut Self::Seed,
+        name: &'static str,
+    ) -> Result<Self::Resource, Error> {
+        debug!(
+            "Loading TLS certificate {} for {}",
+            self.tls.cert.display(),
+            name
+        );
+        ensure!(
+            self.tls.max_handshakes > 0,
+            "The max-handshakes of {} must be positive",
+            name
+        );
+        let acceptor = self.tls.acceptor()?;
+        let inner = self.listener.make_resource(seed, name)?;
+        Ok(TlsListener {
+            inner,
+            acceptor: acceptor.into(),
+            handshake_timeout: self.tls.handshake_timeout,
+            max_handshakes: self.tls.max_handshakes,
+            name,
+        })
+    }
+    fn init<B: Extensible<Ok = B>>(builder: B, name: &'static str) -> Result<B, Error>
+    where
+        B::Config: DeserializeOwned + Send + Sync + 'static,
+        B::Opts: StructOpt + Send + Sync + 'static,
+    {
+        Listener::init(builder, name)
+    }
+    fn identity(&self) -> Option<String> {
+        self.listener.identity()
+    }
+}
+
+/// Wrapper around a listener instance.
+///
+/// This is a plumbing type the user shouldn't need to come into contact with. It implements the
+/// [`IntoIncoming`] trait, which is the interesting property.
+///
+/// This is created by the [`Fragment`] trait of [`TlsListen`].
+pub struct TlsListener<Inner> {
+    inner: Inner,
+    acceptor: TlsAcceptor,
+    handshake_timeout: Duration,
+    max_handshakes: usize,
+    name: &'static str,
+}
+
+impl<Inner> IntoIncoming for TlsListener<Inner>
+where
+    Inner: IntoIncoming,
+    Inner::Connection: AsyncRead + AsyncWrite,
+{
+    type Connection = TlsStream<Inner::Connection>;
+    type Incoming = TlsIncoming<Inner::Incoming>;
+    fn into_incoming(self) -> Self::Incoming {
+        TlsIncoming {
+            inner: Some(self.inner.into_incoming()),
+            acceptor: self.acceptor,
+            handshakes: FuturesUnordered::new(),
+            handshake_timeout: self.handshake_timeout,
+            max_handshakes: self.max_handshakes,
+            name: self.name,
+        }
+    }
+}
+
+/// The stream of connections that went through the TLS handshake.
+///
+/// Handshakes of multiple connections are performed concurrently, up to the configured limit.
+pub struct TlsIncoming<Inner: Stream> {
+    inner: Option<Inner>,
+    acceptor: TlsAcceptor,
+    handshakes: FuturesUnordered<Timeout<Accept<Inner::Item>>>,
+    handshake_timeout: Duration,
+    max_handshakes: usize,
+    name: &'static str,
+}
+
+impl<Inner> Stream for TlsIncoming<Inner>
+where
+    Inner: Stream<Error = IoError>,
+    Inner::Item: AsyncRead + AsyncWrite,
+{
+    type Item = TlsStream<Inner::Item>;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+        loop {
+            while self.handshakes.len() < self.max_handshakes {
+                let inner = match self.inner.as_mut() {
+                    Some(inner) => inner,
+                    None => break,
+                };
+                match inner.poll()? {
+                    Async::Ready(Some(conn)) => {
+                        let accept = self.acceptor.accept(conn);
+                        let handshake = Timeout::new(accept, self.handshake_timeout);
+                        self.handshakes.push(handshake);
+                    }
+                    Async::Ready(None) => self.inner = None,
+                    Async::NotReady => break,
+                }
+            }
+            match self.handshakes.poll() {
+                Ok(Async::Ready(Some(conn))) => return Ok(Async::Ready(Some(conn))),
+                Ok(Async::Ready(None)) if self.inner.is_none() => return Ok(Async::Ready(None)),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // A broken client shouldn't take the whole server down. The failed handshake
+                // freed a slot, so go accept more.
+                Err(e) => warn!("TLS handshake on {} failed: {}", self.name, e),
+            }
+        }
+    }
+}
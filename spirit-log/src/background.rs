@@ -13,9 +13,10 @@
 //! [`Pipeline`]: spirit::fragment::pipeline::Pipeline
 //! [`Transformation`]: spirit::fragment::Transformation
 
-use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::panic::{self, AssertUnwindSafe};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::thread::{Builder as ThreadBuilder, Thread};
@@ -30,6 +31,8 @@ use parking_lot::{Condvar, Mutex};
 use spirit::extension::{Extensible, Extension};
 use spirit::fragment::Transformation;
 
+use crate::tid;
+
 thread_local! {
     // The thread name injected by the background logging.
     //
@@ -38,6 +41,9 @@ thread_local! {
     // thread and only that one sets this, so we can't pollute other thread).
     static LOG_THREAD_NAME: RefCell<Option<Arc<str>>> = RefCell::new(None);
 
+    // The OS thread ID injected by the background logging, the same way as the name.
+    static LOG_THREAD_ID: Cell<Option<u64>> = Cell::new(None);
+
     // Reusable mine thread name, used as a source when putting the log message into the channel.
     //
     // Because we need to potentially log the thread name into multiple loggers, we don't want to
@@ -45,25 +51,31 @@ thread_local! {
     // it both because of refcell and thread-local. We can, however, clone an Arc. When we already
     // have Arcs around, we can as well cache the name in it for the whole lifetime of the thread.
     static MY_THREAD_NAME: Arc<str> = {
-        Arc::from(thread::current().name().unwrap_or(super::UNKNOWN_THREAD))
+        Arc::from(super::thread_name(&thread::current()))
     };
 }
 
 fn reset_thread_name() {
     LOG_THREAD_NAME.with(|log| *log.borrow_mut() = None);
+    LOG_THREAD_ID.with(|log| log.set(None));
 }
 
 // In case it we are inside the background logging thread, we have the LOG_THREAD_NAME set (unless
 // we log a message ourselves). If not, then we simply take the local thread name.
-pub(crate) fn get_thread_name(thread: &Thread) -> Either<&str, Arc<str>> {
+pub(crate) fn get_thread_name(thread: &Thread) -> Either<Cow<'_, str>, Arc<str>> {
     LOG_THREAD_NAME.with(|n| {
         n.borrow()
             .as_ref()
             .map(|n| Either::Right(Arc::clone(n)))
-            .unwrap_or_else(|| Either::Left(thread.name().unwrap_or(super::UNKNOWN_THREAD)))
+            .unwrap_or_else(|| Either::Left(super::thread_name(thread)))
     })
 }
 
+// Similar to the get_thread_name, but for the OS thread ID.
+pub(crate) fn get_thread_id() -> Option<u64> {
+    LOG_THREAD_ID.with(Cell::get).or_else(tid::current)
+}
+
 struct FlushDone {
     done: Mutex<bool>,
     wakeup: Condvar,
@@ -102,6 +114,7 @@ enum Instruction {
         file: Option<String>,
         line: Option<u32>,
         thread: Arc<str>,
+        tid: Option<u64>,
     },
     Flush(DropNotify),
 }
@@ -117,8 +130,10 @@ impl Instruction {
                 file,
                 line,
                 thread,
+                tid,
             } => {
                 LOG_THREAD_NAME.with(|n| n.replace(Some(thread)));
+                LOG_THREAD_ID.with(|t| t.set(tid));
                 dst.log(
                     &Record::builder()
                         .args(format_args!("{}", msg))
@@ -141,6 +156,19 @@ impl Instruction {
 struct SyncLogger {
     logger: Box<dyn Log>,
     lost_msgs: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns how many messages were dropped by all the [`AsyncLogger`]s so far.
+///
+/// This counts the messages dropped because of a full buffer, in any of the dropping
+/// [`OverflowMode`]s (including the silent one). It is meant for exposing as a metric. The
+/// synchronous loggers never drop messages, therefore this stays at zero if no [`AsyncLogger`]
+/// (or [`Background`] transformation) is used.
+pub fn dropped_messages() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
 }
 
 struct Recv {
@@ -284,6 +312,7 @@ impl AsyncLogger {
         let shared = Arc::new(SyncLogger {
             logger,
             lost_msgs: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
         });
         let (sender, receiver) = crossbeam_channel::bounded(buffer);
         let recv = Recv {
@@ -302,6 +331,21 @@ impl AsyncLogger {
             shared,
         }
     }
+
+    /// Returns how many messages this logger has dropped so far.
+    ///
+    /// See [`dropped_messages`] for a global counter across all the loggers.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    fn count_drop(&self, report: bool) {
+        if report {
+            self.shared.lost_msgs.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl Log for AsyncLogger {
@@ -319,7 +363,7 @@ impl Log for AsyncLogger {
             } = self.mode
             {
                 if record.level() >= from_level && self.ch.len() >= fill_limit {
-                    self.shared.lost_msgs.fetch_add(1, Ordering::Relaxed);
+                    self.count_drop(true);
                     return;
                 }
             }
@@ -331,17 +375,13 @@ impl Log for AsyncLogger {
                 msg: format!("{}", record.args()),
                 target: record.target().to_owned(),
                 thread: MY_THREAD_NAME.with(|n| Arc::clone(&n)),
+                tid: tid::current(),
             };
             if self.mode == OverflowMode::Block {
                 self.ch.send(i).expect("Logging thread disappeared");
             } else if let Err(e) = self.ch.try_send(i) {
                 assert!(e.is_full(), "Logging thread disappeared");
-                match self.mode {
-                    OverflowMode::DropMsg | OverflowMode::AdaptiveDrop { .. } => {
-                        self.shared.lost_msgs.fetch_add(1, Ordering::Relaxed);
-                    }
-                    _ => (),
-                }
+                self.count_drop(self.mode != OverflowMode::DropMsgSilently);
             }
         }
     }
@@ -507,3 +547,61 @@ where
         Ok(builder)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+
+    /// Holds the background thread in the first message until told to continue.
+    struct Stuck {
+        entered: StdMutex<Sender<()>>,
+        release: StdMutex<Receiver<()>>,
+    }
+
+    impl Log for Stuck {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, _: &Record) {
+            let _ = self.entered.lock().unwrap().send(());
+            let _ = self.release.lock().unwrap().recv();
+        }
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn overflow_counted() {
+        log::set_max_level(LevelFilter::Trace);
+        let (entered, wait_entered) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        let stuck = Stuck {
+            entered: StdMutex::new(entered),
+            release: StdMutex::new(released),
+        };
+        let logger = AsyncLogger::new(Box::new(stuck), 1, OverflowMode::DropMsgSilently);
+        let log = || {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("msg"))
+                    .build(),
+            )
+        };
+        log();
+        // The background thread is now stuck in the first message
+        wait_entered.recv().unwrap();
+        let before = dropped_messages();
+        // One fits into the buffer, the rest is dropped
+        for _ in 0..5 {
+            log();
+        }
+        assert_eq!(4, logger.dropped());
+        assert!(dropped_messages() >= before + 4);
+        // Let it finish (including the flush on drop)
+        drop(release);
+        drop(logger);
+    }
+}
@@ -0,0 +1,161 @@
+//! Coalescing log lines into bigger writes.
+
+use std::io::{Result as IoResult, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use failure::Error;
+
+use crate::worker::{report, Ticker};
+
+struct Batch {
+    inner: Box<dyn Write + Send>,
+    buffer: Vec<u8>,
+}
+
+impl Batch {
+    fn send(&mut self) -> IoResult<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let buffer = mem::take(&mut self.buffer);
+        self.inner.write_all(&buffer)?;
+        self.inner.flush()
+    }
+}
+
+/// A [`Write`] adapter that sends whatever was written between two flushes in batches.
+///
+/// Fern flushes the writer after each log line. The lines are accumulated and passed to the inner
+/// writer (followed by its flush) once they take at least `size` bytes. A batch never grows over
+/// `size` by adding a line, unless the line alone is bigger ‒ so a batch sent as an UDP datagram
+/// still fits if the lines do.
+///
+/// A helper thread sends whatever accumulated each `linger` interval, so the lines are not held
+/// back for long if not much is being logged. The thread terminates once the writer is dropped.
+pub(crate) struct BatchWriter {
+    batch: Arc<Mutex<Batch>>,
+    line: Vec<u8>,
+    size: usize,
+    _linger: Ticker,
+}
+
+impl BatchWriter {
+    pub(crate) fn new(
+        inner: Box<dyn Write + Send>,
+        size: usize,
+        linger: Duration,
+    ) -> Result<Self, Error> {
+        let batch = Arc::new(Mutex::new(Batch {
+            inner,
+            buffer: Vec::with_capacity(size),
+        }));
+        let lingering = Arc::clone(&batch);
+        let linger = Ticker::start("spirit-log-batch", linger, move || {
+            if let Err(e) = lingering.lock().unwrap().send() {
+                report(format_args!("Failed to send a batch of logs: {}", e));
+            }
+        })?;
+        Ok(Self {
+            batch,
+            line: Vec::new(),
+            size,
+            _linger: linger,
+        })
+    }
+}
+
+impl Write for BatchWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let mut batch = self.batch.lock().unwrap();
+        let result = if batch.buffer.len() + self.line.len() > self.size {
+            batch.send()
+        } else {
+            Ok(())
+        };
+        batch.buffer.append(&mut self.line);
+        if batch.buffer.len() >= self.size {
+            batch.send()?;
+        }
+        result
+    }
+}
+
+impl Drop for BatchWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        let _ = self.batch.lock().unwrap().send();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Writes(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.lock().unwrap().push(buf.to_owned());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn log(writer: &mut BatchWriter, line: &str) {
+        writeln!(writer, "{}", line).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn by_size() {
+        let writes = Writes::default();
+        let mut writer =
+            BatchWriter::new(Box::new(writes.clone()), 10, Duration::from_secs(3600)).unwrap();
+        log(&mut writer, "abc");
+        log(&mut writer, "def");
+        assert!(writes.0.lock().unwrap().is_empty());
+        // Doesn't fit into the batch, the previous ones are sent
+        log(&mut writer, "ghijk");
+        assert_eq!(vec![b"abc\ndef\n".to_vec()], *writes.0.lock().unwrap());
+        // Too big alone
+        log(&mut writer, "0123456789");
+        drop(writer);
+        assert_eq!(
+            vec![
+                b"abc\ndef\n".to_vec(),
+                b"ghijk\n".to_vec(),
+                b"0123456789\n".to_vec(),
+            ],
+            *writes.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn linger() {
+        let writes = Writes::default();
+        let mut writer =
+            BatchWriter::new(Box::new(writes.clone()), 1000, Duration::from_millis(10)).unwrap();
+        log(&mut writer, "Hello");
+        for _ in 0..100 {
+            if !writes.0.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec![b"Hello\n".to_vec()], *writes.0.lock().unwrap());
+    }
+}
@@ -0,0 +1,117 @@
+//! Buffering of the standard outputs.
+
+use std::io::{BufWriter, Write};
+use std::sync::{Mutex, PoisonError};
+
+use log::{Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cfg-help")]
+use structdoc::StructDoc;
+
+/// When the log records written to `stdout` or `stderr` are flushed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Buffering {
+    /// Each record is written at once, as soon as it is logged.
+    #[default]
+    Line,
+    /// The records are collected and written in bigger blocks.
+    ///
+    /// This is faster under heavy logging, but the records show up with a delay and the last ones
+    /// may be lost if the application crashes. They are written out on a clean shutdown.
+    Block,
+    /// No buffering at all, the records are written in pieces as they are being formatted.
+    None,
+}
+
+impl Buffering {
+    pub(crate) fn is_line(&self) -> bool {
+        *self == Buffering::Line
+    }
+
+    pub(crate) fn wrap<W: Write + Send + 'static>(self, inner: W) -> Box<dyn Log> {
+        let out = match self {
+            Buffering::None => BufWriter::with_capacity(0, inner),
+            Buffering::Line | Buffering::Block => BufWriter::new(inner),
+        };
+        Box::new(Buffered {
+            out: Mutex::new(out),
+            buffering: self,
+        })
+    }
+}
+
+/// The output of the already formatted records into a buffered stream.
+///
+/// Unlike the writers, this one sees the difference between the end of a record and an explicit
+/// flush of the logger, so the block buffering can skip the former.
+struct Buffered<W: Write> {
+    out: Mutex<BufWriter<W>>,
+    buffering: Buffering,
+}
+
+impl<W: Write + Send> Log for Buffered<W> {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &Record) {
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        // There's nowhere to report the errors, we are the logger.
+        let _ = writeln!(out, "{}", record.args());
+        if self.buffering != Buffering::Block {
+            let _ = out.flush();
+        }
+    }
+    fn flush(&self) {
+        let _ = self
+            .out
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// A writer remembering what got through.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn written(buffering: Buffering) -> (Sink, Box<dyn Log>) {
+        let sink = Sink::default();
+        let log = buffering.wrap(sink.clone());
+        log.log(&Record::builder().args(format_args!("hello")).build());
+        (sink, log)
+    }
+
+    #[test]
+    fn line() {
+        let (sink, _log) = written(Buffering::Line);
+        assert_eq!(b"hello\n", &sink.0.lock().unwrap()[..]);
+        let (sink, _log) = written(Buffering::None);
+        assert_eq!(b"hello\n", &sink.0.lock().unwrap()[..]);
+    }
+
+    #[test]
+    fn block() {
+        let (sink, log) = written(Buffering::Block);
+        assert!(sink.0.lock().unwrap().is_empty());
+        log.flush();
+        assert_eq!(b"hello\n", &sink.0.lock().unwrap()[..]);
+    }
+}
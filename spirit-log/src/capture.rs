@@ -0,0 +1,144 @@
+//! Capturing the logs in memory, for tests.
+
+use std::io::{Result as IoResult, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use fern::Dispatch;
+use log::{LevelFilter, Log};
+
+/// Collects formatted log lines in memory.
+///
+/// This is meant for integration tests of applications, to check that a given code path emits
+/// the expected log lines. It is not configured through the configuration, but created
+/// programmatically and plugged in, usually through the [`ExtraLogger`][crate::ExtraLogger]
+/// transformation.
+///
+/// The lines have the form of `LEVEL target: message`. If a different format is needed, the
+/// [`writer`][Captured::writer] can be chained into a custom [`Dispatch`].
+///
+/// All clones share the same captured lines.
+///
+/// # Examples
+///
+/// ```rust
+/// use log::{Level, LevelFilter, Record};
+/// use spirit_log::Captured;
+///
+/// let captured = Captured::new();
+/// // Usually passed to ExtraLogger::new(move || captured.logger(LevelFilter::Warn))
+/// let (logger, _level) = captured.logger(LevelFilter::Warn);
+/// logger.log(
+///     &Record::builder()
+///         .level(Level::Warn)
+///         .target("app")
+///         .args(format_args!("Disk almost full"))
+///         .build(),
+/// );
+///
+/// assert_eq!(vec!["WARN app: Disk almost full"], captured.take());
+/// assert!(captured.lines().is_empty());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Captured {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl Captured {
+    /// Creates a new empty capture.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a logger writing into this capture.
+    ///
+    /// The result is in the form [`ExtraLogger`][crate::ExtraLogger] expects.
+    pub fn logger(&self, level: LevelFilter) -> (Box<dyn Log>, LevelFilter) {
+        let (level, logger) = Dispatch::new()
+            .level(level)
+            .format(|out, message, record| {
+                out.finish(format_args!(
+                    "{} {}: {}",
+                    record.level(),
+                    record.target(),
+                    message
+                ))
+            })
+            .chain(self.writer())
+            .into_log();
+        (logger, level)
+    }
+
+    /// Creates a writer storing whatever is written between two flushes as one line.
+    ///
+    /// Trailing newlines are stripped.
+    pub fn writer(&self) -> Box<dyn Write + Send> {
+        Box::new(CaptureWriter {
+            lines: Arc::clone(&self.lines),
+            line: Vec::new(),
+        })
+    }
+
+    /// Returns a copy of the lines captured so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+
+    /// Returns the lines captured so far and clears the capture.
+    pub fn take(&self) -> Vec<String> {
+        mem::take(&mut *self.lines.lock().unwrap())
+    }
+
+    /// Clears the captured lines.
+    pub fn clear(&self) {
+        self.lines.lock().unwrap().clear();
+    }
+}
+
+struct CaptureWriter {
+    lines: Arc<Mutex<Vec<String>>>,
+    line: Vec<u8>,
+}
+
+impl Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim_end_matches(&['\n', '\r'][..]).to_owned();
+        self.line.clear();
+        self.lines.lock().unwrap().push(line);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use super::*;
+
+    #[test]
+    fn capture() {
+        let captured = Captured::new();
+        let (logger, level) = captured.logger(LevelFilter::Info);
+        assert_eq!(LevelFilter::Info, level);
+        for &(level, msg) in &[(Level::Warn, "Hello"), (Level::Debug, "Hidden")] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target("test")
+                    .args(format_args!("{}", msg))
+                    .build(),
+            );
+        }
+        assert_eq!(vec!["WARN test: Hello"], captured.clone().lines());
+        captured.clear();
+        assert!(captured.take().is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+//! Colored log levels in the text formats.
+
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use log::Level;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cfg-help")]
+use structdoc::StructDoc;
+
+/// When to color the log levels.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    /// Color if logging into a terminal (`stdout` or `stderr`).
+    ///
+    /// The colors are turned off if the `NO_COLOR` environment variable is set to a non-empty
+    /// value or if `TERM` is `dumb`.
+    Auto,
+    /// Always color, even when logging into a file or if the environment asks not to.
+    Always,
+    /// Never color.
+    #[default]
+    Never,
+}
+
+impl ColorMode {
+    /// Decides if the colors are used, based on the environment.
+    pub(crate) fn resolve(self, terminal: bool) -> bool {
+        self.decide(
+            terminal,
+            env::var_os("NO_COLOR").as_deref(),
+            env::var_os("TERM").as_deref(),
+        )
+    }
+
+    fn decide(self, terminal: bool, no_color: Option<&OsStr>, term: Option<&OsStr>) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                // https://no-color.org: set and not empty means no colors, whatever the value.
+                let no_color = no_color.map(|v| !v.is_empty()).unwrap_or(false);
+                let dumb = term == Some(OsStr::new("dumb"));
+                terminal && !no_color && !dumb
+            }
+        }
+    }
+}
+
+/// The name of a log level, wrapped in the ANSI codes of its color if `on`.
+///
+/// The padding is applied to the name only, so the columns stay aligned.
+pub(crate) struct Colored<'a> {
+    pub(crate) level: Level,
+    pub(crate) name: &'a str,
+    pub(crate) on: bool,
+}
+
+impl Display for Colored<'_> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        if !self.on {
+            return fmt.pad(self.name);
+        }
+        let code = match self.level {
+            Level::Error => "31",
+            Level::Warn => "33",
+            Level::Info => "32",
+            Level::Debug => "34",
+            Level::Trace => "35",
+        };
+        write!(fmt, "\x1b[{}m", code)?;
+        fmt.pad(self.name)?;
+        fmt.write_str("\x1b[0m")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decide() {
+        let dumb = Some(OsStr::new("dumb"));
+        let xterm = Some(OsStr::new("xterm"));
+        let set = Some(OsStr::new("1"));
+        let empty = Some(OsStr::new(""));
+        assert!(ColorMode::Auto.decide(true, None, xterm));
+        assert!(ColorMode::Auto.decide(true, empty, None));
+        assert!(!ColorMode::Auto.decide(false, None, xterm));
+        assert!(!ColorMode::Auto.decide(true, set, xterm));
+        assert!(!ColorMode::Auto.decide(true, None, dumb));
+        assert!(ColorMode::Always.decide(false, set, dumb));
+        assert!(!ColorMode::Never.decide(true, None, xterm));
+    }
+
+    #[test]
+    fn colored() {
+        let plain = Colored {
+            level: Level::Warn,
+            name: "WARN",
+            on: false,
+        };
+        assert_eq!("WARN ", format!("{:5}", plain));
+        let colored = Colored {
+            level: Level::Error,
+            name: "ERROR",
+            on: true,
+        };
+        assert_eq!("\x1b[31mERROR\x1b[0m", format!("{:5}", colored));
+    }
+}
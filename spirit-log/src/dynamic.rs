@@ -0,0 +1,183 @@
+//! Loggers added and removed at runtime, next to the installed ones.
+
+use std::cmp;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::max_level::MAX_LEVEL;
+
+struct Dynamic {
+    installed: LevelFilter,
+    next_id: u64,
+    loggers: Vec<(u64, LevelFilter, Box<dyn Log>)>,
+}
+
+impl Dynamic {
+    fn update_level(&self) {
+        let level = self
+            .loggers
+            .iter()
+            .map(|(_, level, _)| *level)
+            .fold(self.installed, cmp::max);
+        log::set_max_level(cmp::min(level, MAX_LEVEL));
+        ACTIVE.store(!self.loggers.is_empty(), Ordering::Relaxed);
+    }
+}
+
+static DYNAMIC: RwLock<Dynamic> = RwLock::new(Dynamic {
+    installed: LevelFilter::Off,
+    next_id: 0,
+    loggers: Vec::new(),
+});
+
+// Allows skipping the lock on the hot path in the common case of no dynamic loggers.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// A panicking logger doesn't leave the list in inconsistent state, so we can ignore poisoning.
+fn read() -> RwLockReadGuard<'static, Dynamic> {
+    DYNAMIC.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write() -> RwLockWriteGuard<'static, Dynamic> {
+    DYNAMIC.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Wraps a logger that is about to be installed, so it also feeds the dynamic loggers.
+///
+/// It also sets the global max level to satisfy both.
+pub(crate) fn wrap(level: LevelFilter, inner: Box<dyn Log>) -> Box<dyn Log> {
+    let mut dynamic = write();
+    dynamic.installed = level;
+    dynamic.update_level();
+    Box::new(WithDynamic { level, inner })
+}
+
+struct WithDynamic {
+    level: LevelFilter,
+    inner: Box<dyn Log>,
+}
+
+impl Log for WithDynamic {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        (metadata.level() <= self.level && self.inner.enabled(metadata))
+            || (ACTIVE.load(Ordering::Relaxed)
+                && read()
+                    .loggers
+                    .iter()
+                    .any(|(_, level, log)| metadata.level() <= *level && log.enabled(metadata)))
+    }
+    fn log(&self, record: &Record) {
+        // The global max level may be raised by the dynamic loggers
+        if record.level() <= self.level {
+            self.inner.log(record);
+        }
+        if ACTIVE.load(Ordering::Relaxed) {
+            for (_, level, log) in &read().loggers {
+                if record.level() <= *level {
+                    log.log(record);
+                }
+            }
+        }
+    }
+    fn flush(&self) {
+        self.inner.flush();
+        if ACTIVE.load(Ordering::Relaxed) {
+            for (_, _, log) in &read().loggers {
+                log.flush();
+            }
+        }
+    }
+}
+
+/// A handle to a logger added by [`add_logger`].
+///
+/// The logger is removed when the handle is dropped (or [`remove`][DynamicLogger::remove]d).
+#[derive(Debug)]
+#[must_use = "The logger is removed once the handle is dropped"]
+pub struct DynamicLogger {
+    id: u64,
+}
+
+impl DynamicLogger {
+    /// Removes the logger.
+    ///
+    /// This is the same as dropping the handle, but more explicit.
+    pub fn remove(self) {}
+}
+
+impl Drop for DynamicLogger {
+    fn drop(&mut self) {
+        let removed = {
+            let mut dynamic = write();
+            let pos = dynamic.loggers.iter().position(|(id, _, _)| *id == self.id);
+            let removed = pos.map(|pos| dynamic.loggers.remove(pos));
+            dynamic.update_level();
+            removed
+        };
+        if let Some((_, _, log)) = removed {
+            log.flush();
+        }
+    }
+}
+
+/// Adds a logger at runtime, in addition to the installed ones.
+///
+/// Unlike the loggers created from configuration, this one survives installation of new loggers
+/// (eg. on configuration reload) and stays until the returned handle is dropped. The global
+/// maximum log level is raised as needed for the logger to get what it wants.
+///
+/// This is useful for example to switch on verbose logging live, in response to an admin command,
+/// without reloading the configuration.
+///
+/// The logger gets the records only while logging is set up by this crate (see [`init`]).
+///
+/// [`init`]: crate::init
+pub fn add_logger(level: LevelFilter, logger: Box<dyn Log>) -> DynamicLogger {
+    let mut dynamic = write();
+    let id = dynamic.next_id;
+    dynamic.next_id += 1;
+    dynamic.loggers.push((id, level, logger));
+    dynamic.update_level();
+    DynamicLogger { id }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+    use crate::Captured;
+
+    #[test]
+    fn add_remove() {
+        let installed = Captured::new();
+        let (logger, _) = installed.logger(LevelFilter::Trace);
+        let logger = wrap(LevelFilter::Warn, logger);
+        let log = |level| {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target("test")
+                    .args(format_args!("Hello"))
+                    .build(),
+            );
+        };
+
+        let added = Captured::new();
+        let (added_logger, _) = added.logger(LevelFilter::Trace);
+        let handle = add_logger(LevelFilter::Debug, added_logger);
+        assert!(log::max_level() >= cmp::min(LevelFilter::Debug, MAX_LEVEL));
+        log(Level::Debug);
+        log(Level::Warn);
+        assert_eq!(vec!["WARN test: Hello"], installed.take());
+        assert_eq!(vec!["DEBUG test: Hello", "WARN test: Hello"], added.take());
+
+        handle.remove();
+        log(Level::Debug);
+        log(Level::Warn);
+        assert_eq!(vec!["WARN test: Hello"], installed.take());
+        assert!(added.take().is_empty());
+    }
+}
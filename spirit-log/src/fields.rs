@@ -0,0 +1,49 @@
+//! Constant fields of the JSON records registered from the code.
+
+use std::collections::BTreeMap;
+use std::sync::{PoisonError, RwLock};
+
+static FIELDS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// Adds a constant field to each record of the `json` and `logstash` formats.
+///
+/// This complements the `static-fields` configuration option for values known only to the
+/// application itself (for example the commit it was built from or a version of the log schema).
+/// The configured fields take precedence if both contain the same name. Registering the same name
+/// again replaces the value.
+///
+/// The fields are picked up when the loggers are created, so they need to be registered before
+/// the configuration is loaded (the [`Cfg::static_field`][crate::Cfg::static_field] extension
+/// takes care of that).
+pub fn add_static_field<N: Into<String>, V: Into<String>>(name: N, value: V) {
+    FIELDS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(name.into(), value.into());
+}
+
+/// The registered fields, overridden by the configured ones.
+pub(crate) fn merged(configured: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut fields = FIELDS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    fields.extend(configured.iter().map(|(k, v)| (k.clone(), v.clone())));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        add_static_field("fields_test_schema", "1");
+        add_static_field("fields_test_commit", "abc");
+        let mut configured = BTreeMap::new();
+        configured.insert("fields_test_commit".to_owned(), "def".to_owned());
+        let fields = merged(&configured);
+        assert_eq!("1", fields["fields_test_schema"]);
+        assert_eq!("def", fields["fields_test_commit"]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Opening the log files with the requested permissions and ownership.
+
+use std::fs::{File, OpenOptions, Permissions};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use failure::{Error, ResultExt};
+use serde::de::{Deserializer, Error as DeError, Unexpected};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+/// Permissions of a file, written as an octal number in a string (`"0640"`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct FileMode(pub(crate) u32);
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<FileMode, D::Error> {
+        let s = String::deserialize(d)?;
+        u32::from_str_radix(&s, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .map(FileMode)
+            .ok_or_else(|| D::Error::invalid_value(Unexpected::Str(&s), &"octal file mode"))
+    }
+}
+
+impl Serialize for FileMode {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("{:04o}", self.0))
+    }
+}
+
+#[cfg(feature = "cfg-help")]
+impl structdoc::StructDoc for FileMode {
+    fn document() -> structdoc::Documentation {
+        structdoc::Documentation::leaf("Octal file mode")
+    }
+}
+
+/// Opens (and possibly creates) the log file for appending.
+///
+/// The mode and ownership are changed only if they differ. This allows reopening a file created
+/// (and chowned) by root before dropping privileges as the unprivileged user, who is not allowed
+/// to change them.
+pub(crate) fn open(
+    filename: &Path,
+    mode: Option<FileMode>,
+    owner: Option<u32>,
+    group: Option<u32>,
+) -> Result<File, Error> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    if let Some(FileMode(mode)) = mode {
+        // Restrict the permissions right from the creation, not only after the chmod below.
+        options.mode(mode);
+    }
+    let file = options.open(filename)?;
+    let meta = file.metadata()?;
+    if let Some(FileMode(mode)) = mode {
+        if meta.mode() & 0o7777 != mode {
+            file.set_permissions(Permissions::from_mode(mode))
+                .with_context(|_| {
+                    format!(
+                        "Failed to set mode {:04o} of log file {}",
+                        mode,
+                        filename.display()
+                    )
+                })?;
+        }
+    }
+    let owner = owner.filter(|owner| *owner != meta.uid());
+    let group = group.filter(|group| *group != meta.gid());
+    if owner.is_some() || group.is_some() {
+        std::os::unix::fs::chown(filename, owner, group).with_context(|_| {
+            format!("Failed to change owner of log file {}", filename.display())
+        })?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn mode() {
+        let mode: FileMode = serde_json::from_str(r#""0640""#).unwrap();
+        assert_eq!(FileMode(0o640), mode);
+        assert_eq!(r#""0640""#, serde_json::to_string(&mode).unwrap());
+        assert!(serde_json::from_str::<FileMode>(r#""0948""#).is_err());
+        assert!(serde_json::from_str::<FileMode>(r#""77777""#).is_err());
+    }
+
+    #[test]
+    fn permissions() {
+        let path = TempFile::new("mode");
+        let path = path.path();
+        let file = open(path, Some(FileMode(0o600)), None, None).unwrap();
+        assert_eq!(0o600, file.metadata().unwrap().mode() & 0o7777);
+        drop(file);
+
+        // Reopened with a different mode, it gets changed. Setting the ownership to what it is
+        // already needs no privileges.
+        let meta = fs::metadata(path).unwrap();
+        let file = open(
+            path,
+            Some(FileMode(0o640)),
+            Some(meta.uid()),
+            Some(meta.gid()),
+        )
+        .unwrap();
+        assert_eq!(0o640, file.metadata().unwrap().mode() & 0o7777);
+    }
+}
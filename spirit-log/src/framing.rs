@@ -0,0 +1,131 @@
+//! Delimiting the log records sent over a stream.
+
+use std::io::{Result as IoResult, Write};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cfg-help")]
+use structdoc::StructDoc;
+
+/// How the log records are delimited in the stream.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Framing {
+    /// Each record is terminated by a newline.
+    #[default]
+    Newline,
+    /// Each record is terminated by a null byte, instead of the newline.
+    ///
+    /// This allows the collector to keep multi-line messages together.
+    Null,
+    /// Each record is preceded by its length in bytes, as 32-bit big endian number.
+    ///
+    /// The newline is not included.
+    LengthPrefixed,
+}
+
+impl Framing {
+    pub(crate) fn is_newline(&self) -> bool {
+        *self == Framing::Newline
+    }
+
+    /// Frames a single record, stripping its trailing newline first.
+    pub(crate) fn frame(self, record: &[u8]) -> Vec<u8> {
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        let mut framed = Vec::with_capacity(record.len() + 4);
+        match self {
+            Framing::Newline => {
+                framed.extend_from_slice(record);
+                framed.push(b'\n');
+            }
+            Framing::Null => {
+                framed.extend_from_slice(record);
+                framed.push(0);
+            }
+            Framing::LengthPrefixed => {
+                framed.extend_from_slice(&(record.len() as u32).to_be_bytes());
+                framed.extend_from_slice(record);
+            }
+        }
+        framed
+    }
+
+    pub(crate) fn wrap(self, inner: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+        match self {
+            // Fern already terminates the lines this way
+            Framing::Newline => inner,
+            _ => Box::new(FramedWriter {
+                inner,
+                framing: self,
+                record: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// A [`Write`] adapter that re-frames the records.
+///
+/// Whatever is written between two flushes (a record, as fern flushes after each one) is stripped
+/// of the trailing newline and passed to the inner writer at once, framed by the chosen method.
+struct FramedWriter {
+    inner: Box<dyn Write + Send>,
+    framing: Framing,
+    record: Vec<u8>,
+}
+
+impl Write for FramedWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.record.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.record.is_empty() {
+            return Ok(());
+        }
+        let framed = self.framing.frame(&self.record);
+        self.record.clear();
+        self.inner.write_all(&framed)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn framed(framing: Framing) -> Vec<u8> {
+        let sink = Sink::default();
+        let mut writer = framing.wrap(Box::new(sink.clone()));
+        for record in &["Hello\n", "multi\nline\n"] {
+            writer.write_all(record.as_bytes()).unwrap();
+            writer.flush().unwrap();
+        }
+        let out = sink.0.lock().unwrap().clone();
+        out
+    }
+
+    #[test]
+    fn framing() {
+        assert_eq!(b"Hello\nmulti\nline\n".to_vec(), framed(Framing::Newline));
+        assert_eq!(b"Hello\0multi\nline\0".to_vec(), framed(Framing::Null));
+        assert_eq!(
+            b"\0\0\0\x05Hello\0\0\0\x0amulti\nline".to_vec(),
+            framed(Framing::LengthPrefixed)
+        );
+    }
+}
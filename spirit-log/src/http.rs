@@ -0,0 +1,265 @@
+//! Sending batches of log records to an HTTP endpoint.
+//!
+//! The records are queued and a background thread POSTs them in batches, as newline-delimited
+//! lines (which is what eg. the Elasticsearch `_bulk` API and many log collectors accept).
+
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::{Error, ResultExt};
+use log::{Log, Metadata, Record};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+
+use crate::worker::report;
+
+const RETRY_SLEEP: Duration = Duration::from_millis(100);
+const RETRY_SLEEP_MAX: Duration = Duration::from_secs(10);
+
+/// Settings of the [`HttpLog`].
+#[derive(Clone, Debug)]
+pub(crate) struct HttpCfg {
+    pub(crate) url: String,
+    pub(crate) batch_size: usize,
+    pub(crate) batch_interval: Duration,
+    pub(crate) queue_size: usize,
+    pub(crate) retries: usize,
+    pub(crate) elasticsearch_index: Option<String>,
+}
+
+enum Msg {
+    Line(String),
+    Flush(Sender<()>),
+}
+
+struct Sink {
+    client: Client,
+    url: Url,
+    cfg: HttpCfg,
+    batch: Vec<String>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Sink {
+    fn body(&self) -> String {
+        let action = self.cfg.elasticsearch_index.as_ref().map(|index| {
+            let action = serde_json::json!({ "index": { "_index": index } });
+            format!("{}\n", action)
+        });
+        let mut body = String::new();
+        for line in &self.batch {
+            if let Some(action) = action.as_ref() {
+                body.push_str(action);
+            }
+            body.push_str(line.trim_end_matches('\n'));
+            body.push('\n');
+        }
+        body
+    }
+
+    fn post(&self, body: &str) -> Result<(), Error> {
+        self.client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(body.to_owned())
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn send(&mut self) {
+        // Reported aside, the body must stay in the format the endpoint expects
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            report(format_args!(
+                "{} log records for {} dropped because the queue was full",
+                dropped, self.url
+            ));
+        }
+        if self.batch.is_empty() {
+            return;
+        }
+        let body = self.body();
+        let mut sleep = RETRY_SLEEP;
+        for attempt in 0..=self.cfg.retries {
+            if attempt > 0 {
+                thread::sleep(sleep);
+                sleep = cmp::min(sleep * 2, RETRY_SLEEP_MAX);
+            }
+            match self.post(&body) {
+                Ok(()) => {
+                    self.batch.clear();
+                    return;
+                }
+                Err(e) => report(format_args!("Failed to send logs to {}: {}", self.url, e)),
+            }
+        }
+        report(format_args!(
+            "Giving up sending {} log records to {}",
+            self.batch.len(),
+            self.url
+        ));
+        self.batch.clear();
+    }
+
+    fn run(mut self, receiver: Receiver<Msg>) {
+        let mut deadline = Instant::now() + self.cfg.batch_interval;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(Msg::Line(line)) => {
+                    self.batch.push(line);
+                    if self.batch.len() >= self.cfg.batch_size {
+                        self.send();
+                        deadline = Instant::now() + self.cfg.batch_interval;
+                    }
+                }
+                Ok(Msg::Flush(done)) => {
+                    self.send();
+                    let _ = done.send(());
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.send();
+                    deadline = Instant::now() + self.cfg.batch_interval;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.send();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// A logger sending the (already formatted) records to an HTTP endpoint.
+///
+/// Logging only puts the record into a queue, the sending happens in a background thread. If the
+/// queue is full, the records are dropped instead of blocking the application (and the number of
+/// dropped records is reported on stderr later on). Flushing waits for the queued records to be sent.
+///
+/// The thread terminates once the logger is dropped, after sending what was left in the queue.
+pub(crate) struct HttpLog {
+    // Mutex only to make it Sync, older senders are not.
+    sender: Mutex<SyncSender<Msg>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl HttpLog {
+    pub(crate) fn new(cfg: HttpCfg) -> Result<Self, Error> {
+        let url = Url::parse(&cfg.url).with_context(|_| format!("Invalid URL {}", cfg.url))?;
+        let client = Client::builder()
+            .build()
+            .context("Failed to create HTTP client")?;
+        let (sender, receiver) = mpsc::sync_channel(cfg.queue_size);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let sink = Sink {
+            client,
+            url,
+            batch: Vec::with_capacity(cfg.batch_size),
+            cfg,
+            dropped: Arc::clone(&dropped),
+        };
+        thread::Builder::new()
+            .name("spirit-log-http".to_owned())
+            .spawn(move || sink.run(receiver))
+            .context("Failed to start the HTTP log thread")?;
+        Ok(Self {
+            sender: Mutex::new(sender),
+            dropped,
+        })
+    }
+}
+
+impl Log for HttpLog {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &Record) {
+        let line = Msg::Line(record.args().to_string());
+        if let Err(TrySendError::Full(_)) = self.sender.lock().unwrap().try_send(line) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        let sent = self.sender.lock().unwrap().send(Msg::Flush(done));
+        if sent.is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use log::Level;
+
+    use super::*;
+
+    /// Accepts one request and returns its body.
+    fn serve_one(listener: &TcpListener) -> String {
+        let (conn, _) = listener.accept().unwrap();
+        let mut conn = BufReader::new(conn);
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            conn.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_ascii_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("content-length:") {
+                len = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        conn.read_exact(&mut body).unwrap();
+        conn.get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn bulk() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = HttpLog::new(HttpCfg {
+            url: format!("http://127.0.0.1:{}/_bulk", port),
+            batch_size: 2,
+            batch_interval: Duration::from_secs(3600),
+            queue_size: 10,
+            retries: 0,
+            elasticsearch_index: Some("logs".to_owned()),
+        })
+        .unwrap();
+        for msg in &["{\"message\":\"hello\"}", "{\"message\":\"world\"}"] {
+            log.log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .args(format_args!("{}\n", msg))
+                    .build(),
+            );
+        }
+        let action = r#"{"index":{"_index":"logs"}}"#;
+        let expected = format!(
+            "{}\n{{\"message\":\"hello\"}}\n{}\n{{\"message\":\"world\"}}\n",
+            action, action
+        );
+        assert_eq!(expected, serve_one(&listener));
+
+        // Flushing sends the incomplete batch right away
+        let log = Arc::new(log);
+        let flushed = Arc::clone(&log);
+        log.log(&Record::builder().args(format_args!("{{}}")).build());
+        let flushing = thread::spawn(move || flushed.flush());
+        assert_eq!(format!("{}\n{{}}\n", action), serve_one(&listener));
+        flushing.join().unwrap();
+    }
+}
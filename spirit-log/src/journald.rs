@@ -0,0 +1,46 @@
+//! Sending logs directly to the systemd journal.
+
+use std::thread;
+
+use libsystemd::logging::{self, Priority};
+use log::{Level, Log, Metadata, Record};
+
+use crate::get_thread_name;
+
+/// A logger sending the records to the local systemd journal.
+///
+/// Apart from the message itself, the location and thread of the record are preserved as
+/// structured fields.
+pub(crate) struct Journald;
+
+fn priority(level: Level) -> Priority {
+    match level {
+        Level::Error => Priority::Error,
+        Level::Warn => Priority::Warning,
+        Level::Info => Priority::Info,
+        Level::Debug | Level::Trace => Priority::Debug,
+    }
+}
+
+impl Log for Journald {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &Record) {
+        let line = record.line().map(|l| l.to_string());
+        let thread = get_thread_name(&thread::current()).to_string();
+        let fields = [
+            ("CODE_FILE", record.file()),
+            ("CODE_LINE", line.as_ref().map(|l| l as &str)),
+            ("TARGET", Some(record.target())),
+            ("THREAD_NAME", Some(&thread as &str)),
+        ];
+        let fields = fields
+            .iter()
+            .filter_map(|(name, value)| value.map(|v| (name, v)));
+        // There's nowhere to report the failure to. The same as with the other destinations, the
+        // messages are lost if the journal is not available.
+        let _ = logging::journal_send(priority(record.level()), &record.args().to_string(), fields);
+    }
+    fn flush(&self) {}
+}
@@ -0,0 +1,98 @@
+//! Output of the structured key-value pairs attached to log records.
+//!
+//! Without the `kv` feature, these output nothing.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use log::Record;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Displays the key-value pairs of a record as ` key=value` suffix for the text formats.
+#[cfg_attr(not(feature = "kv"), allow(dead_code))]
+pub(crate) struct KvText<'a, 'r>(pub(crate) &'a Record<'r>);
+
+impl Display for KvText<'_, '_> {
+    #[cfg(feature = "kv")]
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        use log::kv::{Error, Key, Value, VisitSource};
+
+        struct Visitor<'a, 'f>(&'a mut Formatter<'f>);
+
+        impl<'kvs> VisitSource<'kvs> for Visitor<'_, '_> {
+            fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+                write!(self.0, " {}={}", key, value).map_err(Error::boxed)
+            }
+        }
+
+        self.0
+            .key_values()
+            .visit(&mut Visitor(fmt))
+            .map_err(|_| std::fmt::Error)
+    }
+
+    #[cfg(not(feature = "kv"))]
+    fn fmt(&self, _: &mut Formatter) -> FmtResult {
+        Ok(())
+    }
+}
+
+/// Serializes the key-value pairs of a record as a map.
+///
+/// It is meant to be flattened into the structure of the JSON formats.
+#[cfg_attr(not(feature = "kv"), allow(dead_code))]
+pub(crate) struct KvJson<'a, 'r>(pub(crate) &'a Record<'r>);
+
+impl Serialize for KvJson<'_, '_> {
+    #[cfg(feature = "kv")]
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use log::kv::{Error, Key, Value, VisitSource};
+
+        struct Visitor<M: SerializeMap> {
+            map: M,
+            error: Option<M::Error>,
+        }
+
+        impl<'kvs, M: SerializeMap> VisitSource<'kvs> for Visitor<M> {
+            fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+                self.map.serialize_entry(key.as_str(), &value).map_err(|e| {
+                    self.error = Some(e);
+                    Error::msg("Failed to serialize key-value pair")
+                })
+            }
+        }
+
+        let mut visitor = Visitor {
+            map: s.serialize_map(None)?,
+            error: None,
+        };
+        let _ = self.0.key_values().visit(&mut visitor);
+        match visitor.error {
+            Some(e) => Err(e),
+            None => visitor.map.end(),
+        }
+    }
+
+    #[cfg(not(feature = "kv"))]
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_map(Some(0))?.end()
+    }
+}
+
+#[cfg(all(test, feature = "kv"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kv_output() {
+        let kvs = [("user_id", 42), ("attempt", 2)];
+        let record = Record::builder()
+            .args(format_args!("login"))
+            .key_values(&kvs)
+            .build();
+        assert_eq!(" user_id=42 attempt=2", KvText(&record).to_string());
+        assert_eq!(
+            r#"{"user_id":42,"attempt":2}"#,
+            serde_json::to_string(&KvJson(&record)).unwrap()
+        );
+    }
+}
@@ -15,13 +15,21 @@
 //! reloading (through [`log-reroute`]).
 //!
 //! It assumes the application doesn't set the global logger itself. It also sets the panic hook
-//! through the [`log_panics`] crate. The `with-backtrace` cargo feature is propagated through.
+//! through the [`log_panics`] crate (this can be avoided by using
+//! [`Cfg::init_extension_no_panic_hook`]). The `with-backtrace` cargo feature is propagated
+//! through.
+//!
+//! The panics are logged on the `ERROR` level under the [`PANIC_TARGET`] target (`panic`). They
+//! can therefore be routed like any other module, eg. `per-module = { panic = "OFF" }` keeps them
+//! out of a logger and a logger with `level = "OFF"` and `per-module = { panic = "ERROR" }` gets
+//! nothing but the panics.
 //!
 //! # Startup
 //!
 //! The logging is set in multiple steps:
 //!
 //! * As soon as the pipeline is registered, a logging on the `WARN` level is sent to `stderr`.
+//!   The level can be changed by the `SPIRIT_LOG_BOOTSTRAP` environment variable.
 //! * After command line arguments are parsed the `stderr` logging is updated to reflect that (or
 //!   left on the `WARN` level if nothing is set by the user).
 //! * After configuration is loaded from the files, full logging is configured.
@@ -30,8 +38,38 @@
 //!
 //! If you need something specific (for example [`sentry`](https://crates.io/crates/sentry)), you
 //! can plug in additional loggers through the pipeline ‒ the [`Dispatch`] allows adding arbitrary
-//! loggers. The [`Pipeline::map`][spirit::fragment::pipeline::Pipeline::map] is a good place to do
-//! it.
+//! loggers. The [`ExtraLogger`] transformation does exactly that. For more complex needs, the
+//! [`Pipeline::map`][spirit::fragment::pipeline::Pipeline::map] is a good place to do it.
+//!
+//! Loggers can also be added and removed at runtime, independently of the configuration, with
+//! [`add_logger`].
+//!
+//! Constant fields of the JSON records that are known only to the application (not to the
+//! configuration), like the commit it was built from, can be registered by [`add_static_field`]
+//! or the [`Cfg::static_field`] extension.
+//!
+//! # Testing
+//!
+//! To check in tests of the application what it logs, plug in a [`Captured`] logger (for example
+//! through the [`ExtraLogger`]) and examine the lines it collected.
+//!
+//! On the other hand, tests and benchmarks that don't care about the logs can turn on the
+//! `disabled` feature flag. The configuration is still parsed and validated the same way, but no
+//! loggers are created (files are not opened, nothing connects anywhere) and all the records are
+//! thrown away.
+//!
+//! # Compiling out the verbose levels
+//!
+//! The `max_level_*` and `release_max_level_*` feature flags (eg. `release_max_level_info`) put a
+//! ceiling on the levels, the same way the flags of the [`log`] crate do. The configured levels
+//! are capped by it ‒ a logger with `level = "TRACE"` in a build with `release_max_level_info`
+//! gets only the `INFO` and more severe messages, the rest is refused by a single comparison in
+//! the log macros and never gets formatted. If more than one flag is set, the most restrictive
+//! one wins.
+//!
+//! To remove the log statements from the binary completely, so a `trace!` in a hot loop costs
+//! nothing at all, enable the same flag of the [`log`] crate in the application. The flags of
+//! the [`log`] crate are taken into account here too.
 //!
 //! # Performance warning
 //!
@@ -54,9 +92,7 @@
 //!
 //! These pieces are planned some time in future, but haven't happened yet.
 //!
-//! * Reconnecting to the remote server if a TCP connection is lost.
 //! * Log file rotation.
-//! * Colors on `stdout`/`stderr`.
 //!
 //! # Usage without Pipelines
 //!
@@ -158,39 +194,122 @@
 //! clock = "UTC"
 //! ```
 
+use std::borrow::Cow;
 use std::cmp;
-use std::collections::HashMap;
-use std::fmt::Arguments;
-use std::io::{self, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::{Arguments, Debug, Display, Formatter, Result as FmtResult, Write as FmtWrite};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::iter;
-use std::net::TcpStream;
-use std::path::PathBuf;
+use std::mem;
+use std::num::NonZeroU32;
+#[cfg(feature = "http")]
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread;
+use std::time::Duration;
 
 use chrono::format::{DelayedFormat, StrftimeItems};
-use chrono::{Local, Utc};
-use failure::{Error, Fail};
-use fern::Dispatch;
+use chrono::{FixedOffset, Local, TimeZone, Utc};
+#[cfg(feature = "timezone")]
+use chrono_tz::Tz;
+use failure::{err_msg, Error, Fail, ResultExt};
+use fern::{Dispatch, FormatCallback};
 use itertools::Itertools;
-use log::{debug, trace, LevelFilter, Log, STATIC_MAX_LEVEL};
-use serde::de::{Deserializer, Error as DeError};
+use log::{debug, info, trace, warn, Level, LevelFilter, Log, Metadata, Record};
+#[cfg(feature = "tls")]
+use native_tls::{Certificate, TlsConnector};
+use serde::de::{Deserializer, Error as DeError, Unexpected};
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
 use spirit::extension::{Extensible, Extension};
-use spirit::fragment::driver::Trivial as TrivialDriver;
-use spirit::fragment::{Fragment, Installer};
+use spirit::fragment::driver::{CacheSimilar, Comparable, Comparison};
+use spirit::fragment::{Fragment, Installer, Transformation};
 #[cfg(feature = "cfg-help")]
 use structdoc::StructDoc;
 use structopt::StructOpt;
+use syslog::Facility;
+
+use crate::batch::BatchWriter;
+use crate::buffering::Buffering;
+use crate::color::Colored;
+#[cfg(unix)]
+use crate::file::FileMode;
+use crate::framing::Framing;
+#[cfg(feature = "http")]
+use crate::http::{HttpCfg, HttpLog};
+use crate::kv::{KvJson, KvText};
+use crate::max_level::MAX_LEVEL;
+use crate::periodic_flush::PeriodicFlush;
+#[cfg(unix)]
+use crate::pipe::PipeWriter;
+use crate::rate_limit::RateLimited;
+use crate::reconnect::{Connector, ReconnectWriter};
+use crate::rfc3164::Rfc3164;
+use crate::rfc5424::Rfc5424;
+use crate::sanitize::Sanitized;
+use crate::shared::SharedFiles;
+use crate::tid::TidColumn;
+use crate::truncate::Limited;
+use crate::udp::UdpWriter;
 
 #[cfg(feature = "background")]
 pub mod background;
+mod batch;
+mod buffering;
+mod capture;
+mod color;
+mod dynamic;
+mod fields;
+#[cfg(unix)]
+mod file;
+mod framing;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "journald")]
+mod journald;
+mod kv;
+mod max_level;
+mod periodic_flush;
+#[cfg(unix)]
+mod pipe;
+mod rate_limit;
+mod reconnect;
+mod rfc3164;
+mod rfc5424;
+mod ring;
+mod sanitize;
+mod shared;
+#[cfg(test)]
+mod test_util;
+mod tid;
+mod truncate;
+mod udp;
+mod worker;
 
 #[cfg(feature = "background")]
 pub use background::{Background, FlushGuard, OverflowMode};
+pub use capture::Captured;
+pub use color::ColorMode;
+pub use dynamic::{add_logger, DynamicLogger};
+pub use fields::add_static_field;
+pub use ring::{ring_buffer, RingBuffer};
 
-const UNKNOWN_THREAD: &str = "<unknown>";
+/// The name of the thread, or one made up from the thread ID if it has none (eg. `thread-12`).
+fn thread_name(thread: &thread::Thread) -> Cow<'_, str> {
+    match thread.name() {
+        Some(name) => Cow::Borrowed(name),
+        None => {
+            // The numeric value of the ID is available only through the Debug, as `ThreadId(12)`
+            let id = format!("{:?}", thread.id());
+            let id = id.trim_start_matches("ThreadId(").trim_end_matches(')');
+            Cow::Owned(format!("thread-{}", id))
+        }
+    }
+}
 
 /// A fragment for command line options.
 ///
@@ -199,7 +318,18 @@ const UNKNOWN_THREAD: &str = "<unknown>";
 /// pairs (eg. `-L spirit=TRACE`) specifying levels for specific logging targets.
 ///
 /// If used, the logging will be sent to `stderr`.
-#[derive(Clone, Debug, StructOpt)]
+///
+/// It is also possible to construct it programmatically, without parsing the command line (eg.
+/// for tests or when embedding the application into something else):
+///
+/// ```rust
+/// use log::LevelFilter;
+/// use spirit_log::Opts;
+///
+/// let opts = Opts::from(LevelFilter::Info).with_module("spirit", LevelFilter::Trace);
+/// # drop(opts);
+/// ```
+#[derive(Clone, Debug, Default, StructOpt)]
 pub struct Opts {
     /// Log to stderr with this log level.
     #[structopt(short = "l", long = "log", raw(number_of_values = "1"))]
@@ -216,23 +346,106 @@ pub struct Opts {
 }
 
 impl Opts {
+    /// Sets the log level for `stderr`, as the `-l` option would.
+    pub fn with_level(self, level: LevelFilter) -> Self {
+        Opts {
+            log: Some(level),
+            ..self
+        }
+    }
+
+    /// Adds a level override for a specific module, as the `-L` option would.
+    ///
+    /// Note that, as with the command line, the overrides take effect only if the global level is
+    /// set too.
+    pub fn with_module<M: Into<String>>(mut self, module: M, level: LevelFilter) -> Self {
+        self.log_modules.push((module.into(), level));
+        self
+    }
+
+    /// Creates the `stderr` logger these options describe.
+    ///
+    /// This is the same logger [`CfgAndOpts`] would create from them. Returns `None` if no level
+    /// is set.
+    pub fn create_logger(&self) -> Result<Option<Dispatch>, Error> {
+        self.logger_cfg().map(|logger| logger.create()).transpose()
+    }
+
     fn logger_cfg(&self) -> Option<Logger> {
         self.log.map(|level| Logger {
             level: LevelFilterSerde(level),
-            destination: LogDestination::StdErr,
             per_module: self
                 .log_modules
                 .iter()
                 .map(|(module, lf)| (module.clone(), LevelFilterSerde(*lf)))
                 .collect(),
-            clock: Clock::Local,
-            time_format: cmdline_time_format(),
-            format: Format::Short,
+            ..Logger::default()
         })
     }
 }
 
-// TODO: OptsExt & OptsVerbose and turn the other things into Into<Opts>
+const VERBOSITY_LEVELS: &[LevelFilter] = &[
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// A fragment for verbosity-counting command line options.
+///
+/// This is an alternative to [`Opts`] in the style of many unix tools. It provides the `-v`
+/// (`--verbose`) and `-q` (`--quiet`) options, each can be repeated. Every `-v` raises the level of
+/// logging to `stderr` by one step from a baseline chosen by the application, every `-q` lowers it
+/// by one step (so `-vvv` from `WARN` reaches `TRACE` and `-qq` from `INFO` reaches `ERROR`).
+///
+/// As the baseline is up to the application, it is passed to the [`level`][OptsVerbose::level]
+/// and [`opts`][OptsVerbose::opts] methods. The latter produces [`Opts`], which can be used with
+/// [`CfgAndOpts`] the same way as if it came from the command line directly. Note that the
+/// resulting logger to `stderr` is always present (possibly with the `OFF` level) and therefore
+/// overrides any `stderr` logger from the configuration.
+#[derive(Clone, Debug, Default, StructOpt)]
+pub struct OptsVerbose {
+    /// Increase verbosity of logging to stderr (may be repeated).
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u64,
+
+    /// Decrease verbosity of logging to stderr (may be repeated).
+    #[structopt(short = "q", long = "quiet", parse(from_occurrences))]
+    quiet: u64,
+}
+
+impl OptsVerbose {
+    /// Computes the resulting log level from the given baseline.
+    ///
+    /// The result is saturated at `OFF` and `TRACE`.
+    pub fn level(&self, base: LevelFilter) -> LevelFilter {
+        let base = VERBOSITY_LEVELS
+            .iter()
+            .position(|l| *l == base)
+            .expect("Level missing in the table") as u64;
+        let idx = (base + self.verbose).saturating_sub(self.quiet);
+        let idx = cmp::min(idx, VERBOSITY_LEVELS.len() as u64 - 1);
+        VERBOSITY_LEVELS[idx as usize]
+    }
+
+    /// Converts into the [`Opts`], using the `base` level if no `-v` nor `-q` is present.
+    pub fn opts(&self, base: LevelFilter) -> Opts {
+        Opts {
+            log: Some(self.level(base)),
+            log_modules: Vec::new(),
+        }
+    }
+}
+
+impl From<LevelFilter> for Opts {
+    fn from(level: LevelFilter) -> Self {
+        Opts::default().with_level(level)
+    }
+}
+
+// TODO: OptsExt
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
@@ -243,11 +456,39 @@ enum LogDestination {
         /// The path to the file to store the log into.
         ///
         /// The file will be appended to or created if it doesn't exist. The directory it resides
-        /// in must already exist.
+        /// in must already exist, unless `create-dirs` is set.
         ///
         /// There is no direct support for log rotation. However, as the log file is reopened on
         /// `SIGHUP`, the usual external logrotate setup should work.
         filename: PathBuf,
+
+        /// Create the directory of the file (including its parents) if it doesn't exist.
+        #[serde(default, rename = "create-dirs")]
+        create_dirs: bool,
+
+        /// The permissions of the file, as an octal number in a string (eg. `"0640"`).
+        ///
+        /// Applied both when creating the file and when reopening it. Left to the defaults (and
+        /// umask) if not set. Available only on unix.
+        #[cfg(unix)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mode: Option<FileMode>,
+
+        /// The numeric ID of the user to own the file.
+        ///
+        /// When the application starts as root and drops privileges (for example with
+        /// `spirit-daemonize`), this allows the file created as root to be reopened later on by the
+        /// unprivileged user. Available only on unix.
+        #[cfg(unix)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        owner: Option<u32>,
+
+        /// The numeric ID of the group to own the file.
+        ///
+        /// Available only on unix.
+        #[cfg(unix)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group: Option<u32>,
         // TODO: Truncate
     },
 
@@ -256,36 +497,467 @@ enum LogDestination {
     /// Note that syslog ignores formatting options.
     Syslog {
         /// Overrides the host value in the log messages.
+        ///
+        /// If not set, the hostname of the local machine is detected.
         #[serde(skip_serializing_if = "Option::is_none")]
         host: Option<String>,
+
+        /// The path to the unix socket of the syslog.
+        ///
+        /// If not set, `/dev/log` is used (or `/var/run/syslog` if it doesn't exist).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+
+        /// The syslog facility to log under.
+        ///
+        /// Defaults to `user`.
+        #[serde(default)]
+        facility: FacilitySerde,
+
+        /// The format of the syslog messages.
+        ///
+        /// Defaults to `rfc3164`.
+        #[serde(default, rename = "syslog-format")]
+        syslog_format: SyslogFormat,
+
+        /// The application (process) name to put into the messages.
+        ///
+        /// Defaults to the name of the executable.
+        #[serde(rename = "app-name", skip_serializing_if = "Option::is_none")]
+        app_name: Option<String>,
+
+        /// Structured data to attach to each message.
+        ///
+        /// Maps the IDs of the structured data elements to their parameters. Used only with the
+        /// `rfc5424` format.
+        #[serde(
+            default,
+            rename = "structured-data",
+            skip_serializing_if = "HashMap::is_empty"
+        )]
+        structured_data: HashMap<String, HashMap<String, String>>,
+
+        /// Overrides of the syslog severities of the log levels.
+        ///
+        /// By default, `ERROR` maps to `err`, `WARN` to `warning`, `INFO` to `info` and both
+        /// `DEBUG` and `TRACE` to `debug`.
+        #[serde(default, skip_serializing_if = "Severities::is_empty")]
+        severities: Severities,
+
+        /// Log to stderr instead if the syslog can't be connected to.
+        ///
+        /// Useful in environments without a syslog daemon (like some containers). A warning is
+        /// printed the first time it happens. If not set, failing to connect to syslog is an error.
+        #[serde(default)]
+        fallback: bool,
         // TODO: Remote syslog
     },
 
+    /// Sends the logs directly to the local systemd journal.
+    ///
+    /// Apart from the message, the source file and line, the logging target and the thread name
+    /// are preserved as structured fields. As with syslog, the formatting options are ignored.
+    ///
+    /// Available only with the `journald` feature.
+    #[cfg(feature = "journald")]
+    Journald,
+
     /// Sends the logs over a TCP connection over the network.
+    ///
+    /// The connection is made in the background, so an unreachable collector doesn't block the
+    /// application. It is also re-established if lost. Lines logged while not connected are queued
+    /// (up to 1000 of them).
+    ///
+    /// Like files, the connection is reopened on each configuration reload (eg. on `SIGHUP`). The
+    /// host name is resolved again, so a collector that moved to a different address is picked
+    /// up.
     Network {
         /// Hostname or IP address of the remote machine.
         host: String,
 
         /// Port to connect to on the remote machine.
         port: u16,
+
+        /// Encrypt the connection with TLS.
+        ///
+        /// Available only with the `tls` feature.
+        #[serde(default)]
+        tls: bool,
+
+        /// The domain name to validate the server certificate against.
+        ///
+        /// Defaults to the host.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        domain: Option<String>,
+
+        /// A PEM file with an additional certificate authority to trust.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ca: Option<PathBuf>,
+
+        /// Send the lines in batches instead of one by one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        batch: Option<BatchCfg>,
+
+        /// How to delimit the log records in the stream.
+        ///
+        /// One of `newline` (the default), `null` or `length-prefixed`.
+        #[serde(default, skip_serializing_if = "Framing::is_newline")]
+        framing: Framing,
+    },
+
+    /// Sends each log line as a separate UDP datagram.
+    ///
+    /// There's no guarantee the messages arrive, but a slow collector never blocks the
+    /// application. Note that lines longer than what fits into a datagram (usually a bit less
+    /// than 64kB, possibly less depending on the network) are lost.
+    NetworkUdp {
+        /// Hostname or IP address of the remote machine.
+        host: String,
+
+        /// Port to send the datagrams to on the remote machine.
+        port: u16,
+
+        /// Send the lines in batches instead of one by one.
+        ///
+        /// Each batch is sent as one datagram, so the `size` should stay well below the datagram
+        /// size limit.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        batch: Option<BatchCfg>,
+    },
+
+    /// Writes the logs into a named pipe (FIFO).
+    ///
+    /// The pipe is written to without blocking. If there's no reader or it doesn't keep up, the
+    /// log lines are dropped.
+    ///
+    /// Available only on unix.
+    #[cfg(unix)]
+    Pipe {
+        /// The path to the named pipe.
+        ///
+        /// The pipe must already exist.
+        path: PathBuf,
+    },
+
+    /// Keeps the most recent log lines in memory.
+    ///
+    /// They can be read through the [`RingBuffer`] handle. All the loggers of this type share the
+    /// same buffer.
+    RingBuffer {
+        /// How many lines to keep.
+        capacity: usize,
+    },
+
+    /// Sends batches of the log lines to an HTTP endpoint.
+    ///
+    /// The lines are POSTed by a background thread, separated by newlines. This is meant to be
+    /// used with the `json` or `logstash` formats, making it suitable for the Elasticsearch
+    /// `_bulk` API or other log collectors accepting newline-delimited JSON.
+    ///
+    /// Available only with the `http` feature.
+    #[cfg(feature = "http")]
+    Http {
+        /// The URL to send the logs to.
+        url: String,
+
+        /// Send the logs once this many accumulate.
+        ///
+        /// Defaults to 100.
+        #[serde(rename = "batch-size", default = "default_batch_size")]
+        batch_size: usize,
+
+        /// Send the logs at least this often.
+        ///
+        /// Defaults to 1s.
+        #[serde(
+            rename = "batch-interval",
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "spirit::utils::serialize_opt_duration",
+            deserialize_with = "spirit::utils::deserialize_opt_duration",
+            default
+        )]
+        batch_interval: Option<Duration>,
+
+        /// How many log lines may wait to be sent.
+        ///
+        /// If the endpoint doesn't keep up, more lines are dropped (and the number of dropped
+        /// ones is reported on stderr), so the application doesn't get blocked. Must be positive,
+        /// defaults to 10000.
+        #[serde(rename = "queue-size", default = "default_queue_size")]
+        #[cfg_attr(feature = "cfg-help", structdoc(leaf = "Positive integer"))]
+        queue_size: NonZeroUsize,
+
+        /// How many times to retry sending a batch if it fails.
+        ///
+        /// There's an exponential back off between the attempts. Once the retries are exhausted,
+        /// the batch is dropped. Defaults to 3.
+        #[serde(default = "default_retries")]
+        retries: usize,
+
+        /// Use the Elasticsearch `_bulk` API format, indexing the records into this index.
+        ///
+        /// Each line is preceded by the `index` action line. The `url` should point to the
+        /// `_bulk` endpoint.
+        #[serde(
+            rename = "elasticsearch-index",
+            skip_serializing_if = "Option::is_none"
+        )]
+        elasticsearch_index: Option<String>,
     },
 
     /// Writes logs to standard output.
     #[serde(rename = "stdout")]
-    StdOut, // TODO: Colors
+    StdOut {
+        /// When the records are written out.
+        ///
+        /// One of `line` (the default, each record is written at once), `block` (collected and
+        /// written in bigger chunks, faster but delayed and possibly lost on a crash) or `none`.
+        #[serde(default, skip_serializing_if = "Buffering::is_line")]
+        buffering: Buffering,
+
+        /// Prefix each line with the syslog priority of the record, as `<3>` for errors.
+        ///
+        /// Systemd parses the prefix when the output is connected to the journal
+        /// (`StandardOutput=journal`) and sets the priority of the message accordingly, without
+        /// the need for the `journald` destination. This is on by default in systemd, but can be
+        /// turned off by `SyslogLevelPrefix=false` in the unit file, in which case the prefix
+        /// stays as part of the message. Applies only to the `message-only` and `short` formats.
+        #[serde(default, rename = "sd-prefix")]
+        sd_prefix: bool,
+    },
 
     /// Writes the logs to error output.
     #[serde(rename = "stderr")]
-    StdErr, // TODO: Colors
+    StdErr {
+        /// When the records are written out.
+        ///
+        /// The same as with `stdout`.
+        #[serde(default, skip_serializing_if = "Buffering::is_line")]
+        buffering: Buffering,
+
+        /// Prefix each line with the syslog priority of the record.
+        ///
+        /// The same as with `stdout`.
+        #[serde(default, rename = "sd-prefix")]
+        sd_prefix: bool,
+    },
+
+    /// Uses one of two destinations, as chosen by `select`.
+    ///
+    /// This allows switching the destination (eg. a file in production and stderr during
+    /// development) by overriding a single option.
+    Either {
+        /// Which destination to use, `a` or `b`.
+        select: Select,
+
+        /// The first destination.
+        #[cfg_attr(feature = "cfg-help", structdoc(leaf = "Log destination"))]
+        a: Box<LogDestination>,
+
+        /// The second destination.
+        #[cfg_attr(feature = "cfg-help", structdoc(leaf = "Log destination"))]
+        b: Box<LogDestination>,
+    },
+}
+
+impl LogDestination {
+    /// Resolves the [`Either`][LogDestination::Either] choices to the destination actually used.
+    fn chosen(&self) -> &Self {
+        match self {
+            LogDestination::Either {
+                select: Select::A,
+                a,
+                ..
+            } => a.chosen(),
+            LogDestination::Either {
+                select: Select::B,
+                b,
+                ..
+            } => b.chosen(),
+            other => other,
+        }
+    }
+
+    /// The format used when the logger doesn't set one.
+    ///
+    /// The collectors over the network usually want something machine readable, people reading the
+    /// rest want something short.
+    fn default_format(&self) -> Format {
+        match self.chosen() {
+            LogDestination::Network { .. } | LogDestination::NetworkUdp { .. } => Format::Json,
+            #[cfg(feature = "http")]
+            LogDestination::Http { .. } => Format::Json,
+            _ => Format::Short,
+        }
+    }
+}
+
+/// The choice of the [`Either`][LogDestination::Either] destination.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "lowercase")]
+enum Select {
+    /// The first destination.
+    A,
+    /// The second destination.
+    B,
+}
+
+impl Display for LogDestination {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            LogDestination::File { filename, .. } => write!(fmt, "file {}", filename.display()),
+            LogDestination::Syslog { .. } => write!(fmt, "syslog"),
+            #[cfg(feature = "journald")]
+            LogDestination::Journald => write!(fmt, "journald"),
+            LogDestination::Network {
+                host, port, tls, ..
+            } => {
+                let proto = if *tls { "tls" } else { "tcp" };
+                write!(fmt, "network {}://{}:{}", proto, host, port)
+            }
+            LogDestination::NetworkUdp { host, port, .. } => {
+                write!(fmt, "network udp://{}:{}", host, port)
+            }
+            #[cfg(unix)]
+            LogDestination::Pipe { path } => write!(fmt, "pipe {}", path.display()),
+            LogDestination::RingBuffer { capacity } => write!(fmt, "ring buffer ({})", capacity),
+            #[cfg(feature = "http")]
+            LogDestination::Http { url, .. } => write!(fmt, "http {}", url),
+            LogDestination::StdOut { .. } => write!(fmt, "stdout"),
+            LogDestination::StdErr { .. } => write!(fmt, "stderr"),
+            LogDestination::Either { .. } => Display::fmt(self.chosen(), fmt),
+        }
+    }
+}
+
+/// Batching of the log lines sent over the network.
+///
+/// Instead of one write (or datagram) per log line, the lines are accumulated and sent together,
+/// either once there's enough of them or after a short while.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+struct BatchCfg {
+    /// Send the batch once it has this many bytes.
+    ///
+    /// Defaults to 8192.
+    #[serde(default = "default_batch_bytes")]
+    size: usize,
+
+    /// Send the accumulated lines at least this often.
+    ///
+    /// Defaults to 100ms.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "spirit::utils::serialize_opt_duration",
+        deserialize_with = "spirit::utils::deserialize_opt_duration",
+        default
+    )]
+    linger: Option<Duration>,
+}
+
+impl BatchCfg {
+    fn wrap(&self, inner: Box<dyn Write + Send>) -> Result<Box<dyn Write + Send>, Error> {
+        let linger = self.linger.unwrap_or_else(|| Duration::from_millis(100));
+        Ok(Box::new(BatchWriter::new(inner, self.size, linger)?))
+    }
+}
+
+fn default_batch_bytes() -> usize {
+    8192
+}
+
+#[cfg(feature = "http")]
+fn default_batch_size() -> usize {
+    100
+}
+
+#[cfg(feature = "http")]
+fn default_queue_size() -> NonZeroUsize {
+    NonZeroUsize::new(10_000).unwrap()
+}
+
+#[cfg(feature = "http")]
+fn default_retries() -> usize {
+    3
 }
 
 const LEVEL_FILTERS: &[&str] = &["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
 
-// A newtype to help us with serde, structdoc, default... more convenient inside maps and such.
-//
-// We could get serde support with a feature flag from log itself, but not the rest :-(.
-#[derive(Copy, Clone, Debug)]
-struct LevelFilterSerde(LevelFilter);
+/// An error returned by [`parse_level`] for an unknown level.
+#[derive(Debug)]
+pub struct InvalidLevel(String);
+
+impl Display for InvalidLevel {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "Unknown log level {}, expected one of OFF, ERROR, WARN, INFO, DEBUG or TRACE",
+            self.0
+        )
+    }
+}
+
+impl Fail for InvalidLevel {}
+
+/// Parses a log level the same way as the levels in the logging configuration.
+///
+/// The accepted values are `OFF`, `ERROR`, `WARN`, `INFO`, `DEBUG` and `TRACE` (case
+/// insensitive). This allows the application to accept levels in its own configuration options
+/// with the same vocabulary. See also [`LevelFilterSerde`] for use directly in the configuration
+/// structures.
+///
+/// # Examples
+///
+/// ```rust
+/// use log::LevelFilter;
+///
+/// assert_eq!(LevelFilter::Debug, spirit_log::parse_level("DEBUG").unwrap());
+/// assert!(spirit_log::parse_level("VERBOSE").is_err());
+/// ```
+pub fn parse_level(level: &str) -> Result<LevelFilter, InvalidLevel> {
+    level.parse().map_err(|_| InvalidLevel(level.to_owned()))
+}
+
+/// A log level usable in configuration structures.
+///
+/// The [`LevelFilter`] doesn't implement the traits needed for configuration (it can get serde
+/// support through a feature flag of the [`log`] crate, but not the rest). This is a newtype
+/// around it that does, accepting the same values as the levels of the loggers. It is more
+/// convenient inside maps and such than annotating fields with the `with` attribute.
+///
+/// It defaults to `ERROR`.
+///
+/// # Examples
+///
+/// ```rust
+/// use log::LevelFilter;
+/// use serde::Deserialize;
+/// use spirit_log::LevelFilterSerde;
+///
+/// #[derive(Deserialize)]
+/// struct Cfg {
+///     audit_level: LevelFilterSerde,
+/// }
+///
+/// let cfg: Cfg = serde_json::from_str(r#"{"audit_level": "INFO"}"#).unwrap();
+/// assert_eq!(LevelFilter::Info, cfg.audit_level.0);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LevelFilterSerde(pub LevelFilter);
+
+impl From<LevelFilter> for LevelFilterSerde {
+    fn from(level: LevelFilter) -> Self {
+        LevelFilterSerde(level)
+    }
+}
+
+impl From<LevelFilterSerde> for LevelFilter {
+    fn from(level: LevelFilterSerde) -> Self {
+        level.0
+    }
+}
 
 impl Default for LevelFilterSerde {
     fn default() -> LevelFilterSerde {
@@ -296,7 +968,7 @@ impl Default for LevelFilterSerde {
 impl<'de> Deserialize<'de> for LevelFilterSerde {
     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<LevelFilterSerde, D::Error> {
         let s = String::deserialize(d)?;
-        s.parse()
+        parse_level(&s)
             .map(LevelFilterSerde)
             .map_err(|_| D::Error::unknown_variant(&s, LEVEL_FILTERS))
     }
@@ -320,53 +992,448 @@ impl structdoc::StructDoc for LevelFilterSerde {
     }
 }
 
-/// This error can be returned when initialization of logging to syslog fails.
-#[derive(Debug, Fail)]
-#[fail(display = "{}", _0)]
-pub struct SyslogError(String);
+/// A single log level in the configuration.
+///
+/// Unlike the [`LevelFilterSerde`], this doesn't accept `OFF`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct LevelSerde(Level);
+
+impl<'de> Deserialize<'de> for LevelSerde {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<LevelSerde, D::Error> {
+        let s = String::deserialize(d)?;
+        parse_level(&s)
+            .ok()
+            .and_then(|level| level.to_level())
+            .map(LevelSerde)
+            .ok_or_else(|| D::Error::unknown_variant(&s, &LEVEL_FILTERS[1..]))
+    }
+}
+
+impl Serialize for LevelSerde {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.0.to_string())
+    }
+}
+
+#[cfg(feature = "cfg-help")]
+impl structdoc::StructDoc for LevelSerde {
+    fn document() -> structdoc::Documentation {
+        use structdoc::{Documentation, Field, Tagging};
+
+        let levels = LEVEL_FILTERS[1..]
+            .iter()
+            .map(|name| (*name, Field::new(Documentation::leaf_empty(), "")));
+        Documentation::enum_(levels, Tagging::External)
+    }
+}
+
+/// Is the log target inside the module (or the module itself)?
+fn in_module(target: &str, module: &str) -> bool {
+    target.starts_with(module)
+        && (target.len() == module.len() || target[module.len()..].starts_with("::"))
+}
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// Custom names of the log levels in the output.
+///
+/// The ones not set use the default uppercase names.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-enum Clock {
-    Local,
-    Utc,
+#[serde(rename_all = "UPPERCASE")]
+struct LevelNames {
+    /// The name of the error level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The name of the warn level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warn: Option<String>,
+    /// The name of the info level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<String>,
+    /// The name of the debug level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug: Option<String>,
+    /// The name of the trace level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: Option<String>,
 }
 
-impl Clock {
-    fn now(self, format: &str) -> DelayedFormat<StrftimeItems> {
-        match self {
-            Clock::Local => Local::now().format(format),
-            Clock::Utc => Utc::now().format(format),
-        }
+impl LevelNames {
+    fn name(&self, level: Level) -> &str {
+        let custom = match level {
+            Level::Error => &self.error,
+            Level::Warn => &self.warn,
+            Level::Info => &self.info,
+            Level::Debug => &self.debug,
+            Level::Trace => &self.trace,
+        };
+        custom
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or_else(|| level.as_str())
     }
 }
 
-impl Default for Clock {
-    fn default() -> Self {
-        Clock::Local
+/// The syslog severity (priority) of the messages.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    /// System is unusable.
+    #[serde(alias = "LOG_EMERG")]
+    Emerg,
+    /// Action must be taken immediately.
+    #[serde(alias = "LOG_ALERT")]
+    Alert,
+    /// Critical conditions.
+    #[serde(alias = "LOG_CRIT")]
+    Crit,
+    /// Error conditions.
+    #[serde(alias = "LOG_ERR")]
+    Err,
+    /// Warning conditions.
+    #[serde(alias = "LOG_WARNING")]
+    Warning,
+    /// Normal but significant condition.
+    #[serde(alias = "LOG_NOTICE")]
+    Notice,
+    /// Informational messages.
+    #[serde(alias = "LOG_INFO")]
+    Info,
+    /// Debug-level messages.
+    #[serde(alias = "LOG_DEBUG")]
+    Debug,
+}
+
+/// Mapping of the log levels to the syslog severities.
+///
+/// The ones not set use the default mapping (`TRACE` and `DEBUG` both map to `debug`).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "UPPERCASE")]
+struct Severities {
+    /// The severity of the error level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Severity>,
+    /// The severity of the warn level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warn: Option<Severity>,
+    /// The severity of the info level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<Severity>,
+    /// The severity of the debug level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug: Option<Severity>,
+    /// The severity of the trace level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: Option<Severity>,
+}
+
+impl Severities {
+    fn is_empty(&self) -> bool {
+        *self == Severities::default()
+    }
+
+    fn severity(&self, level: Level) -> Severity {
+        match level {
+            Level::Error => self.error.unwrap_or(Severity::Err),
+            Level::Warn => self.warn.unwrap_or(Severity::Warning),
+            Level::Info => self.info.unwrap_or(Severity::Info),
+            Level::Debug => self.debug.unwrap_or(Severity::Debug),
+            Level::Trace => self.trace.unwrap_or(Severity::Debug),
+        }
     }
 }
 
-fn default_time_format() -> String {
-    "%+".to_owned()
+/// The priority prefix of the lines on the standard outputs understood by systemd, if `on`.
+struct SdPrefix {
+    level: Level,
+    on: bool,
 }
 
-fn cmdline_time_format() -> String {
-    "%F %T%.3f".to_owned()
+impl Display for SdPrefix {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        if self.on {
+            let severity = Severities::default().severity(self.level);
+            write!(fmt, "<{}>", severity as u8)
+        } else {
+            Ok(())
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// The standard the syslog messages follow.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
 #[serde(rename_all = "kebab-case")]
-enum Format {
-    /// Only the message, without any other fields.
-    MessageOnly,
-    /// The time, log level, log target and message in columns.
-    Short,
-    /// The time, log level, thread name, log target and message in columns.
-    Extended,
-    /// The time, log level, thread name, file name and line, log target and message in columns.
+enum SyslogFormat {
+    /// The traditional BSD syslog format.
+    #[default]
+    Rfc3164,
+    /// The modern syslog format, with structured data.
+    Rfc5424,
+}
+
+const FACILITIES: &[&str] = &[
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+// Similar to the LevelFilterSerde, syslog's Facility doesn't implement the traits we need.
+#[derive(Copy, Clone, Debug)]
+struct FacilitySerde(Facility);
+
+impl FacilitySerde {
+    fn name(self) -> &'static str {
+        FACILITIES
+            .iter()
+            .find(|name| name.parse::<Facility>().ok().map(|f| f as u8) == Some(self.0 as u8))
+            .expect("Facility missing in the table")
+    }
+}
+
+impl Default for FacilitySerde {
+    fn default() -> Self {
+        FacilitySerde(Facility::LOG_USER)
+    }
+}
+
+impl PartialEq for FacilitySerde {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 as u8 == other.0 as u8
+    }
+}
+
+impl<'de> Deserialize<'de> for FacilitySerde {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<FacilitySerde, D::Error> {
+        let s = String::deserialize(d)?;
+        // This accepts both the `daemon` and `LOG_DAEMON` forms, case insensitive.
+        s.parse()
+            .map(FacilitySerde)
+            .map_err(|()| D::Error::unknown_variant(&s, FACILITIES))
+    }
+}
+
+impl Serialize for FacilitySerde {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "cfg-help")]
+impl structdoc::StructDoc for FacilitySerde {
+    fn document() -> structdoc::Documentation {
+        use structdoc::{Documentation, Field, Tagging};
+
+        let facilities = FACILITIES
+            .iter()
+            .map(|name| (*name, Field::new(Documentation::leaf_empty(), "")));
+        Documentation::enum_(facilities, Tagging::External)
+    }
+}
+
+/// This error can be returned when initialization of logging to syslog fails.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", _0)]
+pub struct SyslogError(String);
+
+// Detected once when creating the logger, not for each message.
+fn local_hostname() -> Option<String> {
+    hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+}
+
+// The name of the binary we run as. The name of the crate would give us spirit-log, not the
+// application.
+fn process_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_owned)
+        })
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_owned())
+}
+
+/// Prepares the connecting to the network log collector.
+///
+/// The configuration (like the TLS certificates) is checked right away, but the actual
+/// connection happens later on, in the background.
+fn network_connector(
+    host: &str,
+    port: u16,
+    tls: bool,
+    domain: Option<&String>,
+    ca: Option<&PathBuf>,
+) -> Result<Connector, Error> {
+    let host = host.to_owned();
+    if !tls {
+        return Ok(Arc::new(move || {
+            Ok(Box::new(reconnect::tcp_connect(&host, port)?) as Box<dyn Write + Send>)
+        }));
+    }
+    #[cfg(feature = "tls")]
+    {
+        let mut builder = TlsConnector::builder();
+        if let Some(ca) = ca {
+            let pem = fs::read(ca)?;
+            builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+        let connector = builder.build()?;
+        let domain = domain.cloned().unwrap_or_else(|| host.clone());
+        Ok(Arc::new(move || {
+            let conn = reconnect::tcp_connect(&host, port)?;
+            let conn = connector
+                .connect(&domain, conn)
+                .map_err(|e| err_msg(format!("TLS handshake with {} failed: {}", host, e)))?;
+            Ok(Box::new(conn) as Box<dyn Write + Send>)
+        }))
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        let _ = (domain, ca);
+        Err(err_msg(
+            "TLS for network logging requested, but the tls feature is not compiled in",
+        ))
+    }
+}
+
+/// The time zone of the timestamps in the logs.
+///
+/// In the configuration, this is one of `LOCAL`, `UTC`, a fixed offset (`+02:00`) or, with the
+/// `timezone` feature, a name of a time zone (`Europe/Prague`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clock {
+    /// The local time of the machine.
+    Local,
+    /// The UTC time.
+    Utc,
+    /// A fixed offset from UTC.
+    Fixed(FixedOffset),
+    /// A named time zone, including its daylight saving time changes.
+    ///
+    /// Available only with the `timezone` feature.
+    #[cfg(feature = "timezone")]
+    Tz(Tz),
+}
+
+impl Clock {
+    fn now(self, format: &str) -> DelayedFormat<StrftimeItems> {
+        match self {
+            Clock::Local => Local::now().format(format),
+            Clock::Utc => Utc::now().format(format),
+            Clock::Fixed(offset) => Utc::now().with_timezone(&offset).format(format),
+            #[cfg(feature = "timezone")]
+            Clock::Tz(tz) => Utc::now().with_timezone(&tz).format(format),
+        }
+    }
+}
+
+/// Checks the `time_format` can be used, by formatting a fixed timestamp with it.
+///
+/// Chrono detects invalid formats only when formatting (and the [`Display`] then fails), so this
+/// allows reporting it when loading the configuration instead of on the first logged record.
+fn check_time_format(format: &str) -> Result<(), Error> {
+    let epoch = Utc.timestamp_opt(0, 0).unwrap();
+    let mut out = String::new();
+    write!(out, "{}", epoch.format(format))
+        .map_err(|_| err_msg(format!("Invalid time format {:?}", format)))
+}
+
+// Parses offsets in the `+02:00`, `-0530` or `+02` forms.
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let s = &s[1..];
+    if !s.is_ascii() {
+        return None;
+    }
+    let (hours, minutes) = match (s.find(':'), s.len()) {
+        (Some(colon), _) => (&s[..colon], &s[colon + 1..]),
+        (None, 4) => (&s[..2], &s[2..]),
+        (None, _) => (s, "0"),
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+impl<'de> Deserialize<'de> for Clock {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Clock, D::Error> {
+        let s = String::deserialize(d)?;
+        match &s as &str {
+            "LOCAL" => return Ok(Clock::Local),
+            "UTC" => return Ok(Clock::Utc),
+            _ => (),
+        }
+        if let Some(offset) = parse_offset(&s) {
+            return Ok(Clock::Fixed(offset));
+        }
+        #[cfg(feature = "timezone")]
+        {
+            if let Ok(tz) = s.parse() {
+                return Ok(Clock::Tz(tz));
+            }
+        }
+        Err(D::Error::invalid_value(
+            Unexpected::Str(&s),
+            &"LOCAL, UTC, a fixed offset or a timezone name",
+        ))
+    }
+}
+
+impl Serialize for Clock {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Clock::Local => s.serialize_str("LOCAL"),
+            Clock::Utc => s.serialize_str("UTC"),
+            Clock::Fixed(offset) => s.serialize_str(&offset.to_string()),
+            #[cfg(feature = "timezone")]
+            Clock::Tz(tz) => s.serialize_str(tz.name()),
+        }
+    }
+}
+
+#[cfg(feature = "cfg-help")]
+impl structdoc::StructDoc for Clock {
+    fn document() -> structdoc::Documentation {
+        structdoc::Documentation::leaf("clock")
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::Local
+    }
+}
+
+fn default_time_format() -> String {
+    "%+".to_owned()
+}
+
+fn cmdline_time_format() -> String {
+    "%F %T%.3f".to_owned()
+}
+
+/// The format of the log records.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "cfg-help", derive(StructDoc))]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Only the message, without any other fields.
+    MessageOnly,
+    /// The time, log level, log target and message in columns.
+    Short,
+    /// The time, log level, thread name, log target and message in columns.
+    Extended,
+    /// The time, log level, thread name, file name and line, log target and message in columns.
     Full,
     /// The time, log level, thread name, file name and line, log target and message in columns
     /// separated by tabs.
@@ -403,24 +1470,75 @@ enum Format {
     // TODO: Custom
 }
 
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::MessageOnly => "message-only",
+            Format::Short => "short",
+            Format::Extended => "extended",
+            Format::Full => "full",
+            Format::Machine => "machine",
+            Format::Json => "json",
+            Format::Logstash => "logstash",
+        }
+    }
+}
+
 impl Default for Format {
     fn default() -> Self {
         Format::Short
     }
 }
 
+/// Finishes a line of the text formats, truncated to `max_line`.
+fn finish(out: FormatCallback, line: Arguments, max_line: Option<usize>) {
+    out.finish(format_args!(
+        "{}",
+        Limited {
+            message: line,
+            max: max_line,
+        }
+    ))
+}
+
 #[cfg(not(feature = "background"))]
-fn get_thread_name(thread: &thread::Thread) -> &str {
-    thread.name().unwrap_or(UNKNOWN_THREAD)
+fn get_thread_name(thread: &thread::Thread) -> Cow<'_, str> {
+    thread_name(thread)
 }
 
 #[cfg(feature = "background")]
 use background::get_thread_name;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg(not(feature = "background"))]
+fn get_thread_id() -> Option<u64> {
+    tid::current()
+}
+
+#[cfg(feature = "background")]
+use background::get_thread_id;
+
+fn deserialize_flush_interval<'de, D>(d: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let interval = spirit::utils::deserialize_opt_duration(d)?;
+    if interval == Some(Duration::from_secs(0)) {
+        return Err(D::Error::invalid_value(
+            Unexpected::Other("zero duration"),
+            &"a positive flush interval",
+        ));
+    }
+    Ok(interval)
+}
+
+/// Configuration of a single logger.
+///
+/// This is usually read as part of the [`Cfg`], but can be also built in code by the
+/// [`LoggerBuilder`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
 #[serde(rename_all = "kebab-case")] // TODO: Make deny-unknown-fields work
-struct Logger {
+pub struct Logger {
     #[serde(flatten)]
     destination: LogDestination,
 
@@ -438,8 +1556,29 @@ struct Logger {
     time_format: String,
 
     /// Format of log messages.
+    ///
+    /// Defaults to `json` for the network and http destinations and to `short` for the others.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    format: Option<Format>,
+
+    /// Width of the column with the log target in the padded formats.
+    ///
+    /// Defaults to 30.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_width: Option<usize>,
+
+    /// Width of the column with the thread name in the padded formats.
+    ///
+    /// Defaults to 30 in the `extended` format and 10 in the `full` one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_width: Option<usize>,
+
+    /// Include the OS thread ID in the `full` and `machine` formats.
+    ///
+    /// This is the ID tools like `top -H` or `perf` show, allowing to match the log lines with
+    /// what they see. Currently available on Linux and Windows, other platforms show `-`.
     #[serde(default)]
-    format: Format,
+    thread_id: bool,
 
     /// The level on which to log messages.
     ///
@@ -447,75 +1586,320 @@ struct Logger {
     #[serde(default)]
     level: LevelFilterSerde,
 
+    /// The most severe level of messages to write into this logger.
+    ///
+    /// Together with `level`, this allows the logger to take only a band of levels. For example,
+    /// `level = "DEBUG"` and `max-level = "INFO"` writes only the `INFO` and `DEBUG` messages, so
+    /// the more severe ones can go to another logger without appearing in both.
+    ///
+    /// Not limited if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_level: Option<LevelFilterSerde>,
+
+    /// Write only the messages of exactly this level.
+    ///
+    /// This takes precedence over `level` and `max-level`. The modules in `per-module` are still
+    /// logged according to their overrides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    only: Option<LevelSerde>,
+
     /// Overrides of log level per each module.
     ///
     /// The map allows for overriding log levels of each separate module (log target) separately.
     /// This allows silencing a verbose one or getting more info out of misbehaving one.
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     per_module: HashMap<String, LevelFilterSerde>,
+
+    /// Maximum number of messages per second this logger lets through.
+    ///
+    /// If more messages come, they are dropped and a single summary line with the number of
+    /// suppressed messages is written once the logger has a budget again. Each logger has its own
+    /// independent budget. Must be positive.
+    ///
+    /// Unlimited if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "cfg-help", structdoc(leaf = "Positive integer"))]
+    rate_limit: Option<NonZeroU32>,
+
+    /// Flush the logger in this interval.
+    ///
+    /// Makes sure the messages don't stay in buffers for too long, so they are not lost if the
+    /// application crashes. Must not be zero. Not flushed periodically if not set.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "spirit::utils::serialize_opt_duration",
+        deserialize_with = "deserialize_flush_interval",
+        default
+    )]
+    flush_interval: Option<Duration>,
+
+    /// Escape newlines and other control characters in the messages.
+    ///
+    /// Prevents untrusted input in the messages from forging fake log lines or sending escape
+    /// sequences to the terminal. Applies only to the text formats, the JSON ones are always
+    /// escaped.
+    #[serde(default)]
+    sanitize: bool,
+
+    /// Maximum length of a line, in bytes.
+    ///
+    /// Longer lines are truncated and `…` is appended. In the JSON formats, only the message is
+    /// truncated, so the result stays valid JSON. Must be at least 3, to fit the `…`.
+    ///
+    /// Unlimited if not set.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "truncate::deserialize_max",
+        default
+    )]
+    max_line: Option<usize>,
+
+    /// Pretty-print the records of the `json` format.
+    ///
+    /// This spreads each record over multiple lines, so it breaks tools expecting a JSON per
+    /// line. Meant for reading the logs by a human during debugging only.
+    #[serde(default)]
+    pretty: bool,
+
+    /// Constant fields added to each record of the `json` and `logstash` formats.
+    ///
+    /// Useful to tell apart records of different services (eg. `service = "auth"`) when they are
+    /// aggregated in one place. The text formats ignore these.
+    ///
+    /// These are merged with (and take precedence over) the fields registered by the application.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    static_fields: BTreeMap<String, String>,
+
+    /// Custom names of the log levels, used instead of the default uppercase ones.
+    ///
+    /// Applies to both the text and the JSON formats.
+    #[serde(default)]
+    level_names: LevelNames,
+
+    /// Color the log levels in the `short`, `extended` and `full` formats.
+    ///
+    /// One of `auto`, `always` and `never` (the default). With `auto`, only the loggers to
+    /// `stdout` or `stderr` connected to a terminal get colors and only if neither the `NO_COLOR`
+    /// environment variable (set to anything non-empty) nor `TERM=dumb` ask otherwise. The
+    /// `always` colors regardless of these.
+    #[serde(default)]
+    color: ColorMode,
 }
 
 impl Logger {
-    fn create(&self) -> Result<Dispatch, Error> {
+    /// Is it worth keeping the logger across configuration reloads if it doesn't change?
+    ///
+    /// Loggers holding connections keep them. On the other hand, files need to be reopened on
+    /// reload for logrotate to work and recreating the rest is cheap. The TCP connections are an
+    /// exception, they are reopened to re-resolve the host (the collector may have moved) and
+    /// connect in the background anyway.
+    fn keep_alive(&self) -> bool {
+        match self.destination.chosen() {
+            LogDestination::File { .. }
+            | LogDestination::Network { .. }
+            | LogDestination::StdOut { .. }
+            | LogDestination::StdErr { .. } => false,
+            #[cfg(unix)]
+            LogDestination::Pipe { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// The format of the records, either the configured one or the default of the destination.
+    fn format(&self) -> Format {
+        self.format
+            .unwrap_or_else(|| self.destination.default_format())
+    }
+
+    /// The level for the modules without an override.
+    fn base_level(&self) -> LevelFilter {
+        match self.only {
+            Some(only) => only.0.to_level_filter(),
+            None => self.level.0,
+        }
+    }
+
+    fn info(&self) -> LoggerInfo {
+        let max_level = self
+            .per_module
+            .values()
+            .map(|level| level.0)
+            .fold(self.base_level(), cmp::max);
+        let format = match self.destination.chosen() {
+            LogDestination::Syslog { .. } => None,
+            #[cfg(feature = "journald")]
+            LogDestination::Journald => None,
+            _ => Some(self.format()),
+        };
+        LoggerInfo {
+            destination: self.destination.to_string(),
+            max_level,
+            format,
+        }
+    }
+
+    /// Creates the logger.
+    ///
+    /// The result can be installed by [`install`], or combined with other loggers.
+    pub fn create(&self) -> Result<Dispatch, Error> {
+        self.create_shared(&mut SharedFiles::default())
+    }
+
+    /// Creates the logger, sharing the files with the other loggers created with the same `files`.
+    fn create_shared(&self, files: &mut SharedFiles) -> Result<Dispatch, Error> {
         trace!("Creating logger for {:?}", self);
-        let mut logger = Dispatch::new().level(self.level.0);
+        let mut logger = Dispatch::new().level(self.base_level());
         logger = self
             .per_module
             .iter()
             .fold(logger, |logger, (module, level)| {
                 logger.level_for(module.clone(), level.0)
             });
+        if let Some(only) = self.only {
+            let overridden = self.per_module.keys().cloned().collect::<Vec<_>>();
+            logger = logger.filter(move |metadata| {
+                metadata.level() == only.0
+                    || overridden
+                        .iter()
+                        .any(|module| in_module(metadata.target(), module))
+            });
+        } else if let Some(max_level) = self.max_level {
+            // The more severe levels are the smaller ones
+            logger = logger.filter(move |metadata| metadata.level() >= max_level.0);
+        }
+        check_time_format(&self.time_format)
+            .with_context(|_| format!("Can't create logger {}", self.destination))?;
         let clock = self.clock;
         let time_format = self.time_format.clone();
-        let format = self.format;
-        match self.destination {
+        let format = self.format();
+        let target_width = self.target_width.unwrap_or(30);
+        let thread_width = self.thread_width;
+        let thread_id = self.thread_id;
+        let sanitize = self.sanitize;
+        let max_line = self.max_line;
+        let pretty = self.pretty;
+        let static_fields = fields::merged(&self.static_fields);
+        let level_names = self.level_names.clone();
+        let color = self.color.resolve(match self.destination.chosen() {
+            LogDestination::StdOut { .. } => io::stdout().is_terminal(),
+            LogDestination::StdErr { .. } => io::stderr().is_terminal(),
+            _ => false,
+        });
+        let sd_prefix = match self.destination.chosen() {
+            LogDestination::StdOut { sd_prefix, .. } | LogDestination::StdErr { sd_prefix, .. } => {
+                *sd_prefix
+            }
+            _ => false,
+        };
+        match self.destination.chosen() {
             // We don't want to format syslog
             LogDestination::Syslog { .. } => (),
+            // Nor journald, it has its own fields
+            #[cfg(feature = "journald")]
+            LogDestination::Journald => (),
             // We do with the other things
             _ => {
                 logger = logger.format(move |out, message, record| {
+                    let text = Sanitized { message, sanitize };
+                    let level = level_names.name(record.level());
+                    let colored = Colored {
+                        level: record.level(),
+                        name: level,
+                        on: color,
+                    };
+                    let priority = SdPrefix {
+                        level: record.level(),
+                        on: sd_prefix,
+                    };
                     match format {
-                        Format::MessageOnly => out.finish(format_args!("{}", message)),
-                        Format::Short => out.finish(format_args!(
-                            "{} {:5} {:30} {}",
-                            clock.now(&time_format),
-                            record.level(),
-                            record.target(),
-                            message,
-                        )),
-                        Format::Extended => {
-                            out.finish(format_args!(
-                                "{} {:5} {:30} {:30} {}",
+                        Format::MessageOnly => {
+                            let line = format_args!("{}{}{}", priority, text, KvText(record));
+                            finish(out, line, max_line)
+                        }
+                        Format::Short => finish(
+                            out,
+                            format_args!(
+                                "{}{} {:5} {:tw$} {}{}",
+                                priority,
                                 clock.now(&time_format),
-                                record.level(),
-                                get_thread_name(&thread::current()),
+                                colored,
                                 record.target(),
-                                message,
-                            ));
+                                text,
+                                KvText(record),
+                                tw = target_width,
+                            ),
+                            max_line,
+                        ),
+                        Format::Extended => {
+                            finish(
+                                out,
+                                format_args!(
+                                    "{} {:5} {:thw$} {:tw$} {}{}",
+                                    clock.now(&time_format),
+                                    colored,
+                                    get_thread_name(&thread::current()),
+                                    record.target(),
+                                    text,
+                                    KvText(record),
+                                    thw = thread_width.unwrap_or(30),
+                                    tw = target_width,
+                                ),
+                                max_line,
+                            );
                         }
                         Format::Full => {
-                            out.finish(format_args!(
-                                "{} {:5} {:10} {:>25}:{:<5} {:30} {}",
-                                clock.now(&time_format),
-                                record.level(),
-                                get_thread_name(&thread::current()),
-                                record.file().unwrap_or("<unknown>"),
-                                record.line().unwrap_or(0),
-                                record.target(),
-                                message,
-                            ));
+                            finish(
+                                out,
+                                format_args!(
+                                    "{} {:5} {:thw$} {}{:>25}:{:<5} {:tw$} {}{}",
+                                    clock.now(&time_format),
+                                    colored,
+                                    get_thread_name(&thread::current()),
+                                    TidColumn {
+                                        tid: if thread_id {
+                                            Some(get_thread_id())
+                                        } else {
+                                            None
+                                        },
+                                        width: 7,
+                                        separator: ' ',
+                                    },
+                                    record.file().unwrap_or("<unknown>"),
+                                    record.line().unwrap_or(0),
+                                    record.target(),
+                                    text,
+                                    KvText(record),
+                                    thw = thread_width.unwrap_or(10),
+                                    tw = target_width,
+                                ),
+                                max_line,
+                            );
                         }
                         Format::Machine => {
-                            out.finish(format_args!(
-                                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
-                                clock.now(&time_format),
-                                record.level(),
-                                get_thread_name(&thread::current()),
-                                record.file().unwrap_or("<unknown>"),
-                                record.line().unwrap_or(0),
-                                record.target(),
-                                message,
-                            ));
+                            finish(
+                                out,
+                                format_args!(
+                                    "{}\t{}\t{}\t{}{}\t{}\t{}\t{}{}",
+                                    clock.now(&time_format),
+                                    level,
+                                    get_thread_name(&thread::current()),
+                                    TidColumn {
+                                        tid: if thread_id {
+                                            Some(get_thread_id())
+                                        } else {
+                                            None
+                                        },
+                                        width: 0,
+                                        separator: '\t',
+                                    },
+                                    record.file().unwrap_or("<unknown>"),
+                                    record.line().unwrap_or(0),
+                                    record.target(),
+                                    text,
+                                    KvText(record),
+                                ),
+                                max_line,
+                            );
                         }
                         Format::Json => {
                             // We serialize it by putting things into a structure and using serde
@@ -530,7 +1914,11 @@ impl Logger {
                                 file: Option<&'a str>,
                                 line: Option<u32>,
                                 target: &'a str,
-                                message: &'a Arguments<'a>,
+                                message: Limited<&'a Arguments<'a>>,
+                                #[serde(flatten)]
+                                kv: KvJson<'a, 'a>,
+                                #[serde(flatten)]
+                                static_fields: &'a BTreeMap<String, String>,
                             }
                             // Unfortunately, the Arguments thing produced by format_args! doesn't
                             // like to live in a variable ‒ all attempts to put it into a let
@@ -541,18 +1929,27 @@ impl Logger {
                             // problem.
                             let log = |msg: &Msg| {
                                 // TODO: Maybe use some shortstring or so here to avoid allocation?
-                                let msg = serde_json::to_string(msg)
-                                    .expect("Failed to serialize JSON log");
+                                let msg = if pretty {
+                                    serde_json::to_string_pretty(msg)
+                                } else {
+                                    serde_json::to_string(msg)
+                                };
+                                let msg = msg.expect("Failed to serialize JSON log");
                                 out.finish(format_args!("{}", msg));
                             };
                             log(&Msg {
                                 timestamp: format_args!("{}", clock.now(&time_format)),
-                                level: format_args!("{}", record.level()),
+                                level: format_args!("{}", level),
                                 thread_name: &get_thread_name(&thread::current()),
                                 file: record.file(),
                                 line: record.line(),
                                 target: record.target(),
-                                message,
+                                message: Limited {
+                                    message,
+                                    max: max_line,
+                                },
+                                kv: KvJson(record),
+                                static_fields: &static_fields,
                             });
                         }
                         Format::Logstash => {
@@ -569,7 +1966,11 @@ impl Logger {
                                 level: Arguments<'a>,
                                 thread_name: &'a str,
                                 logger_name: &'a str,
-                                message: &'a Arguments<'a>,
+                                message: Limited<&'a Arguments<'a>>,
+                                #[serde(flatten)]
+                                kv: KvJson<'a, 'a>,
+                                #[serde(flatten)]
+                                static_fields: &'a BTreeMap<String, String>,
                             }
                             // Unfortunately, the Arguments thing produced by format_args! doesn't
                             // like to live in a variable ‒ all attempts to put it into a let
@@ -587,149 +1988,1149 @@ impl Logger {
                             log(&Msg {
                                 timestamp: format_args!("{}", clock.now(&time_format)),
                                 version: 1,
-                                level: format_args!("{}", record.level()),
+                                level: format_args!("{}", level),
                                 thread_name: &get_thread_name(&thread::current()),
                                 logger_name: record.target(),
-                                message,
+                                message: Limited {
+                                    message,
+                                    max: max_line,
+                                },
+                                kv: KvJson(record),
+                                static_fields: &static_fields,
                             });
                         }
                     }
                 });
             }
         }
-        match self.destination {
-            LogDestination::File { ref filename } => Ok(logger.chain(fern::log_file(filename)?)),
-            LogDestination::Syslog { ref host } => {
-                let formatter = syslog::Formatter3164 {
-                    facility: syslog::Facility::LOG_USER,
-                    hostname: host.clone(),
-                    // TODO: Does this give us the end-user crate or us?
-                    process: env!("CARGO_PKG_NAME").to_owned(),
-                    pid: 0,
-                };
+        let mut logger = match *self.destination.chosen() {
+            #[cfg(unix)]
+            LogDestination::File {
+                ref filename,
+                create_dirs,
+                mode,
+                owner,
+                group,
+            } => {
+                if create_dirs {
+                    create_parent(filename)?;
+                }
+                let open = || file::open(filename, mode, owner, group);
+                logger.chain(Box::new(files.writer(filename, open)?) as Box<dyn Write + Send>)
+            }
+            #[cfg(not(unix))]
+            LogDestination::File {
+                ref filename,
+                create_dirs,
+            } => {
+                if create_dirs {
+                    create_parent(filename)?;
+                }
+                let open = || fern::log_file(filename).map_err(Error::from);
+                logger.chain(Box::new(files.writer(filename, open)?) as Box<dyn Write + Send>)
+            }
+            LogDestination::Syslog {
+                ref host,
+                ref path,
+                facility,
+                syslog_format,
+                ref app_name,
+                ref structured_data,
+                ref severities,
+                fallback,
+            } => {
+                let hostname = host.clone().or_else(local_hostname);
+                let process = app_name.clone().unwrap_or_else(process_name);
                 // TODO: Other destinations than just unix
-                Ok(logger
-                    .chain(syslog::unix(formatter).map_err(|e| SyslogError(format!("{}", e)))?))
+                let syslog = match syslog_format {
+                    SyslogFormat::Rfc3164 => {
+                        let formatter = syslog::Formatter3164 {
+                            facility: facility.0,
+                            hostname,
+                            process,
+                            pid: 0,
+                        };
+                        let syslog = match path {
+                            Some(path) => syslog::unix_custom(formatter, path),
+                            None => syslog::unix(formatter),
+                        };
+                        syslog
+                            .map(|syslog| {
+                                Box::new(Rfc3164::new(syslog, severities.clone())) as Box<dyn Log>
+                            })
+                            .map_err(|e| SyslogError(format!("{}", e)))
+                    }
+                    SyslogFormat::Rfc5424 => Rfc5424::connect(
+                        path.as_ref().map(PathBuf::as_path),
+                        facility.0,
+                        hostname,
+                        process,
+                        structured_data,
+                        severities.clone(),
+                    )
+                    .map(|syslog| Box::new(syslog) as Box<dyn Log>)
+                    .map_err(|e| SyslogError(format!("{}", e))),
+                };
+                match syslog {
+                    Ok(syslog) => logger.chain(syslog),
+                    Err(e) if fallback => {
+                        if !SYSLOG_FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+                            warn!("Can't connect to syslog, logging to stderr instead: {}", e);
+                        }
+                        let stderr = Logger {
+                            destination: LogDestination::StdErr {
+                                buffering: Buffering::default(),
+                                sd_prefix: false,
+                            },
+                            ..self.clone()
+                        };
+                        return stderr.create_shared(files);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            #[cfg(feature = "journald")]
+            LogDestination::Journald => logger.chain(Box::new(journald::Journald) as Box<dyn Log>),
+            LogDestination::Network {
+                ref host,
+                port,
+                tls,
+                ref domain,
+                ref ca,
+                ref batch,
+                framing,
+            } => {
+                let connector = network_connector(host, port, tls, domain.as_ref(), ca.as_ref())?;
+                let name = format!("{}:{}", host, port);
+                let conn = ReconnectWriter::new(name, connector, framing);
+                let mut conn = Box::new(conn) as Box<dyn Write + Send>;
+                if let Some(batch) = batch {
+                    conn = batch.wrap(conn)?;
+                }
+                logger.chain(framing.wrap(conn))
+            }
+            LogDestination::NetworkUdp {
+                ref host,
+                port,
+                ref batch,
+            } => {
+                let mut conn = Box::new(UdpWriter::connect(host, port)?) as Box<dyn Write + Send>;
+                if let Some(batch) = batch {
+                    conn = batch.wrap(conn)?;
+                }
+                logger.chain(conn)
             }
-            LogDestination::Network { ref host, port } => {
-                // TODO: Reconnection support
-                let conn = TcpStream::connect((&host as &str, port))?;
-                Ok(logger.chain(Box::new(conn) as Box<dyn Write + Send>))
+            #[cfg(unix)]
+            LogDestination::Pipe { ref path } => {
+                logger.chain(Box::new(PipeWriter::new(path.clone())) as Box<dyn Write + Send>)
             }
-            LogDestination::StdOut => Ok(logger.chain(io::stdout())),
-            LogDestination::StdErr => Ok(logger.chain(io::stderr())),
+            LogDestination::RingBuffer { capacity } => {
+                logger.chain(Box::new(ring::writer(capacity)) as Box<dyn Write + Send>)
+            }
+            #[cfg(feature = "http")]
+            LogDestination::Http {
+                ref url,
+                batch_size,
+                batch_interval,
+                queue_size,
+                retries,
+                ref elasticsearch_index,
+            } => {
+                let http = HttpLog::new(HttpCfg {
+                    url: url.clone(),
+                    batch_size,
+                    batch_interval: batch_interval.unwrap_or_else(|| Duration::from_secs(1)),
+                    queue_size: queue_size.get(),
+                    retries,
+                    elasticsearch_index: elasticsearch_index.clone(),
+                })?;
+                logger.chain(Box::new(http) as Box<dyn Log>)
+            }
+            LogDestination::StdOut { buffering, .. } => logger.chain(buffering.wrap(io::stdout())),
+            LogDestination::StdErr { buffering, .. } => logger.chain(buffering.wrap(io::stderr())),
+            LogDestination::Either { .. } => unreachable!("Either resolved by chosen"),
+        };
+        if let Some(rate) = self.rate_limit {
+            let (level, inner) = logger.into_log();
+            let limited = RateLimited::new(inner, rate.get())?;
+            logger = Dispatch::new()
+                .level(level)
+                .chain(Box::new(limited) as Box<dyn Log>);
         }
+        if let Some(interval) = self.flush_interval {
+            let (level, inner) = logger.into_log();
+            let flushed = PeriodicFlush::new(inner, interval)?;
+            logger = Dispatch::new()
+                .level(level)
+                .chain(Box::new(flushed) as Box<dyn Log>);
+        }
+        Ok(logger)
     }
 }
 
 impl Default for Logger {
     fn default() -> Self {
         Self {
-            destination: LogDestination::StdErr,
+            destination: LogDestination::StdErr {
+                buffering: Buffering::default(),
+                sd_prefix: false,
+            },
             level: LevelFilterSerde(LevelFilter::Warn),
             per_module: HashMap::new(),
+            max_level: None,
+            only: None,
             clock: Clock::Local,
             time_format: cmdline_time_format(),
-            format: Format::Short,
+            format: Some(Format::Short),
+            target_width: None,
+            thread_width: None,
+            thread_id: false,
+            rate_limit: None,
+            flush_interval: None,
+            sanitize: false,
+            max_line: None,
+            pretty: false,
+            static_fields: BTreeMap::new(),
+            level_names: LevelNames::default(),
+            color: ColorMode::default(),
         }
     }
 }
 
-fn create<'a, I>(logging: I) -> Result<Dispatch, Error>
-where
-    I: IntoIterator<Item = &'a Logger>,
-{
-    debug!("Creating loggers");
-    logging
-        .into_iter()
-        .map(Logger::create)
-        .fold_results(Dispatch::new(), Dispatch::chain)
-        .map_err(Error::from)
-}
-
-/// A configuration fragment to set up logging.
-///
-/// By flattening this into the configuration structure, the program can load options for
-/// configuring logging. It adds a new top-level array `logging`. Each item describes one logger,
-/// with separate log levels and destination.
-///
-/// See the [crate examples](index.html#examples) for the use.
+/// A builder of a [`Logger`], to construct one in code instead of reading it from configuration.
 ///
-/// # Logger options
+/// The defaults are the same as with the configuration file. The destination is `stderr`.
 ///
-/// These are valid for all loggers:
+/// # Examples
 ///
-/// * `level`: The log level to use. Valid options are `OFF`, `ERROR`, `WARN`, `INFO`, `DEBUG` and
-///   `TRACE`.
-/// * `per-module`: A map, setting log level overrides for specific modules (logging targets). This
-///   one is optional.
-/// * `type`: Specifies the type of logger destination. Some of them allow specifying other
-///   options.
-/// * `clock`: Either `LOCAL` or `UTC`. Defaults to `LOCAL` if not present.
-/// * `time_format`: Time
-///   [format string](https://docs.rs/chrono/*/chrono/format/strftime/index.html). Defaults to
-///   `%+` (which is ISO 8601/RFC 3339). Note that the command line logger (one produced by `-l`)
-///   uses a more human-friendly format.
-/// * `format`: The format to use. There are few presets (and a custom may come in future).
-///   - `message-only`: The line contains only the message itself.
-///   - `short`: This is the default. `<timestamp> <level> <target> <message>`. Padded to form
-///     columns.
-///   - `extended`: <timestamp> <level> <thread-name> <target> <message>`. Padded to form columns.
-///   - `full`: `<timestamp> <level> <thread-name> <file>:<line> <target> <message>`. Padded to
-///     form columns.
-///   - `machine`: Like `full`, but columns are not padded by spaces, they are separated by a
-///     single `\t` character, for more convenient processing by tools like `cut`.
-///   - `json`: The fields of `full` are encoded into a `json` format, for convenient processing of
-///     more modern tools like logstash.
-///   - `logstash`: `json` format with fields named and formatted according to
-///     [Logback JSON encoder](https://github.com/logstash/logstash-logback-encoder#standard-fields)
+/// ```rust
+/// use log::LevelFilter;
+/// use spirit_log::{Cfg, Clock, Format, LoggerBuilder};
 ///
-/// The allowed types are:
-/// * `stdout`: The logs are sent to standard output. There are no additional options.
-/// * `stderr`: The logs are sent to standard error output. There are no additional options.
-/// * `file`: Logs are written to a file. The file is reopened every time a configuration is
-///   re-read (therefore every time the application gets `SIGHUP`), which makes it work with
-///   logrotate.
+/// let logger = LoggerBuilder::new()
+///     .file("/var/log/app.log")
+///     .level(LevelFilter::Info)
+///     .module("hyper", LevelFilter::Warn)
+///     .format(Format::Extended)
+///     .clock(Clock::Utc)
+///     .time_format("%F %T")
+///     .build();
+/// let cfg = Cfg::default().with_logger(logger);
+/// # drop(cfg);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LoggerBuilder {
+    logger: Logger,
+}
+
+impl LoggerBuilder {
+    /// Starts building a logger with the default settings.
+    pub fn new() -> Self {
+        LoggerBuilder {
+            logger: Logger {
+                level: LevelFilterSerde::default(),
+                clock: Clock::default(),
+                time_format: default_time_format(),
+                format: None,
+                ..Logger::default()
+            },
+        }
+    }
+
+    fn destination(self, destination: LogDestination) -> Self {
+        LoggerBuilder {
+            logger: Logger {
+                destination,
+                ..self.logger
+            },
+        }
+    }
+
+    /// Logs to the standard error output (the default).
+    pub fn stderr(self) -> Self {
+        self.destination(LogDestination::StdErr {
+            buffering: Buffering::default(),
+            sd_prefix: false,
+        })
+    }
+
+    /// Logs to the standard output.
+    pub fn stdout(self) -> Self {
+        self.destination(LogDestination::StdOut {
+            buffering: Buffering::default(),
+            sd_prefix: false,
+        })
+    }
+
+    /// Logs into a file.
+    ///
+    /// The file is appended to or created if it doesn't exist.
+    pub fn file<P: Into<PathBuf>>(self, filename: P) -> Self {
+        self.destination(LogDestination::File {
+            filename: filename.into(),
+            create_dirs: false,
+            #[cfg(unix)]
+            mode: None,
+            #[cfg(unix)]
+            owner: None,
+            #[cfg(unix)]
+            group: None,
+        })
+    }
+
+    /// Sends the logs over a (plain) TCP connection.
+    pub fn network<H: Into<String>>(self, host: H, port: u16) -> Self {
+        self.destination(LogDestination::Network {
+            host: host.into(),
+            port,
+            tls: false,
+            domain: None,
+            ca: None,
+            batch: None,
+            framing: Framing::default(),
+        })
+    }
+
+    /// Sends the logs to the local syslog.
+    pub fn syslog(self) -> Self {
+        self.destination(LogDestination::Syslog {
+            host: None,
+            path: None,
+            facility: FacilitySerde::default(),
+            syslog_format: SyslogFormat::default(),
+            app_name: None,
+            structured_data: HashMap::new(),
+            severities: Severities::default(),
+            fallback: false,
+        })
+    }
+
+    /// Sets the level on which to log messages.
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.logger.level = LevelFilterSerde(level);
+        self
+    }
+
+    /// Logs only the messages of exactly this level.
+    ///
+    /// This takes precedence over the [`level`][LoggerBuilder::level].
+    pub fn only(mut self, level: Level) -> Self {
+        self.logger.only = Some(LevelSerde(level));
+        self
+    }
+
+    /// Overrides the level for a specific module (log target).
+    pub fn module<M: Into<String>>(mut self, module: M, level: LevelFilter) -> Self {
+        self.logger
+            .per_module
+            .insert(module.into(), LevelFilterSerde(level));
+        self
+    }
+
+    /// Sets the format of the log records.
+    pub fn format(mut self, format: Format) -> Self {
+        self.logger.format = Some(format);
+        self
+    }
+
+    /// Sets when to color the log levels.
+    pub fn color(mut self, color: ColorMode) -> Self {
+        self.logger.color = color;
+        self
+    }
+
+    /// Sets the time zone of the timestamps.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.logger.clock = clock;
+        self
+    }
+
+    /// Sets the [format](https://docs.rs/chrono/~0.4/chrono/format/strftime/index.html) of the
+    /// timestamps.
+    ///
+    /// An invalid format is reported when creating the logger.
+    pub fn time_format<F: Into<String>>(mut self, time_format: F) -> Self {
+        self.logger.time_format = time_format.into();
+        self
+    }
+
+    /// Finishes the logger.
+    pub fn build(self) -> Logger {
+        self.logger
+    }
+}
+
+impl Default for LoggerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A description of one logger, for diagnostics.
+///
+/// Allows finding out what loggers will be created from the configuration (for example to print
+/// them on startup), as the installed logger can't be inspected any more. These are returned by
+/// the [`Cfg::loggers`] and [`CfgAndOptsRef::loggers`] methods.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoggerInfo {
+    destination: String,
+    max_level: LevelFilter,
+    format: Option<Format>,
+}
+
+impl LoggerInfo {
+    /// A human readable description of where the logger sends the messages.
+    ///
+    /// For example `stderr` or `file /var/log/app.log`.
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// The most verbose level the logger is interested in.
+    ///
+    /// This includes the per-module overrides.
+    pub fn max_level(&self) -> LevelFilter {
+        self.max_level
+    }
+
+    /// The format of the records.
+    ///
+    /// This is `None` for the destinations with their own format (like syslog).
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+}
+
+impl Display for LoggerInfo {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "{} ({})", self.destination, self.max_level)
+    }
+}
+
+/// The description of the created loggers, waiting to be logged once they get installed.
+static BANNER: Mutex<Vec<LoggerInfo>> = Mutex::new(Vec::new());
+
+/// Remembers the loggers to describe by [`log_banner`].
+fn prepare_banner<'a, I>(logging: I)
+where
+    I: IntoIterator<Item = &'a Logger>,
+{
+    *BANNER.lock().unwrap_or_else(PoisonError::into_inner) =
+        logging.into_iter().map(Logger::info).collect();
+}
+
+/// Logs the lines prepared by [`prepare_banner`], if any.
+fn log_banner() {
+    let banner = mem::take(&mut *BANNER.lock().unwrap_or_else(PoisonError::into_inner));
+    for (i, logger) in banner.iter().enumerate() {
+        let format = logger.format.map(Format::name).unwrap_or("native");
+        info!(
+            "logger[{}]: {} level={} format={}",
+            i, logger.destination, logger.max_level, format,
+        );
+    }
+}
+
+/// Was the warning about falling back from syslog to stderr already printed?
+static SYSLOG_FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Creates the directory the log file lives in.
+fn create_parent(filename: &Path) -> Result<(), Error> {
+    if let Some(dir) = filename.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|_| {
+            format!(
+                "Failed to create directory {} for log file {}",
+                dir.display(),
+                filename.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// The logger to use when logging is turned off by the `disabled` feature.
+fn disabled() -> Dispatch {
+    Dispatch::new().level(LevelFilter::Off)
+}
+
+fn create<'a, I>(logging: I) -> Result<Dispatch, Error>
+where
+    I: IntoIterator<Item = &'a Logger>,
+{
+    if cfg!(feature = "disabled") {
+        return Ok(disabled());
+    }
+    debug!("Creating loggers");
+    let mut files = SharedFiles::default();
+    logging
+        .into_iter()
+        .map(|logger| logger.create_shared(&mut files))
+        .fold_results(Dispatch::new(), Dispatch::chain)
+        .map_err(Error::from)
+}
+
+/// Dispatches records to the individual loggers.
+///
+/// The loggers are shared between the previous and the new [`Dispatch`] on reload. Each one
+/// comes with its most verbose level, so the records a logger would throw away anyway are
+/// skipped with a cheap comparison, before reaching the logger (and its formatting) at all.
+/// This matters for the hot `trace!` paths with many loggers configured.
+#[derive(Default)]
+struct MultiLog {
+    loggers: Vec<(LevelFilter, Arc<Box<dyn Log>>)>,
+}
+
+impl MultiLog {
+    fn max_level(&self) -> LevelFilter {
+        self.loggers
+            .iter()
+            .map(|(level, _)| *level)
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+}
+
+impl Log for MultiLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.loggers
+            .iter()
+            .any(|(level, log)| metadata.level() <= *level && log.enabled(metadata))
+    }
+    fn log(&self, record: &Record) {
+        for (level, log) in &self.loggers {
+            if record.level() <= *level {
+                log.log(record);
+            }
+        }
+    }
+    fn flush(&self) {
+        for (_, log) in &self.loggers {
+            log.flush();
+        }
+    }
+}
+
+/// The loggers created last time.
+///
+/// This is the [`Seed`][Fragment::Seed] of the logging [`Fragment`]s. It allows keeping the
+/// loggers whose configuration didn't change on reload, so for example an HTTP logger doesn't
+/// lose its queue. Files are still reopened on each reload, to support logrotate, and TCP
+/// connections to re-resolve the host name.
+#[derive(Default)]
+pub struct LoggerCache {
+    loggers: Vec<CachedLogger>,
+}
+
+struct CachedLogger {
+    cfg: Logger,
+    level: LevelFilter,
+    log: Arc<Box<dyn Log>>,
+}
+
+impl Debug for LoggerCache {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_list()
+            .entries(self.loggers.iter().map(|cached| &cached.cfg))
+            .finish()
+    }
+}
+
+fn create_cached<'a, I>(
+    cache: &mut LoggerCache,
+    logging: I,
+    best_effort: bool,
+) -> Result<Dispatch, Error>
+where
+    I: IntoIterator<Item = &'a Logger>,
+{
+    if cfg!(feature = "disabled") {
+        cache.loggers.clear();
+        return Ok(disabled());
+    }
+    debug!("Creating loggers");
+    // The cache is updated only on success, failed reload keeps the old loggers alive for the next
+    // one.
+    let mut old = mem::take(&mut cache.loggers);
+    let mut loggers = Vec::new();
+    let mut multi = MultiLog::default();
+    let mut failures = Vec::new();
+    let mut files = SharedFiles::default();
+    for logger in logging {
+        let pos = old
+            .iter()
+            .position(|cached| logger.keep_alive() && cached.cfg == *logger);
+        let cached = match pos {
+            Some(pos) => {
+                debug!("Keeping logger {}", logger.destination);
+                old.swap_remove(pos)
+            }
+            None => {
+                let (level, log) = match logger.create_shared(&mut files) {
+                    Ok(created) => created.into_log(),
+                    Err(e) if best_effort => {
+                        failures.push((logger.destination.to_string(), e));
+                        continue;
+                    }
+                    Err(e) => {
+                        old.extend(loggers);
+                        cache.loggers = old;
+                        return Err(e);
+                    }
+                };
+                CachedLogger {
+                    cfg: logger.clone(),
+                    level,
+                    log: Arc::new(log),
+                }
+            }
+        };
+        multi.loggers.push((cached.level, Arc::clone(&cached.log)));
+        loggers.push(cached);
+    }
+    if !failures.is_empty() && loggers.is_empty() {
+        cache.loggers = old;
+        let (_, e) = failures.swap_remove(0);
+        return Err(e.context("None of the loggers could be created").into());
+    }
+    // Tell the loggers that made it about the ones that didn't
+    for (destination, e) in failures {
+        multi.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target(module_path!())
+                .args(format_args!(
+                    "Skipping logger {}: {}",
+                    destination,
+                    e.iter_chain().join("; ")
+                ))
+                .build(),
+        );
+    }
+    cache.loggers = loggers;
+    let level = multi.max_level();
+    Ok(Dispatch::new()
+        .level(level)
+        .chain(Box::new(multi) as Box<dyn Log>))
+}
+
+/// A configuration fragment to set up logging.
+///
+/// By flattening this into the configuration structure, the program can load options for
+/// configuring logging. It adds a new top-level array `logging`. Each item describes one logger,
+/// with separate log levels and destination.
+///
+/// See the [crate examples](index.html#examples) for the use.
+///
+/// Normally, if any of the loggers fails to be created (for example, a network logger can't
+/// connect), the whole logging configuration is refused. If the top-level `logging-best-effort`
+/// option is set to `true`, the failing loggers are skipped instead and the failures are logged
+/// as warnings into the remaining ones. It still fails if none of the loggers can be created.
+///
+/// If there are no loggers configured at all, the warnings and errors still go to `stderr`
+/// (similar to the logging during startup), so the problems don't pass silently.
+///
+/// If the top-level `logging-tee-errors` option is set to `true` and the logs go into a file, the
+/// errors are also sent to `stderr`, so they are visible on the console. The additional logger
+/// uses the same format as the (first) file logger. It is not added if there already is a
+/// logger to `stderr`.
+///
+/// If the top-level `logging-env-filter` option is set to `true`, another logger to `stderr` is
+/// added as set by the `RUST_LOG` environment variable (a different variable can be chosen by
+/// the `logging-env-filter-var` option). It takes the usual comma separated list of directives,
+/// `target=level` to set the level of a target, a bare `level` for everything else (which is
+/// `OFF` otherwise) and a bare `target` to get everything of that target (eg.
+/// `RUST_LOG=warn,myapp=debug,hyper`). This allows getting more logs without touching the
+/// configuration file. Nothing is added if the variable is not set.
+///
+/// If the top-level `logging-banner` option is set to `true`, a line describing each logger (its
+/// destination, level and format) is logged on the `INFO` level whenever the loggers are
+/// installed (on startup and on configuration reload), like
+/// `logger[0]: file /var/log/app.log level=DEBUG format=json`. This allows checking the
+/// configuration took effect. It goes through the very loggers it describes, so a logger
+/// without the `INFO` level doesn't show it.
+///
+/// # Logger options
+///
+/// These are valid for all loggers:
+///
+/// * `level`: The log level to use. Valid options are `OFF`, `ERROR`, `WARN`, `INFO`, `DEBUG` and
+///   `TRACE`.
+/// * `max-level`: The most severe level this logger takes (eg. with `level = "DEBUG"` and
+///   `max-level = "INFO"`, only `INFO` and `DEBUG` messages are logged). Unlimited if not present.
+/// * `only`: Log only the messages of exactly this level (eg. `only = "WARN"`). Takes precedence
+///   over `level` and `max-level`, but the modules in `per-module` still follow their overrides.
+/// * `per-module`: A map, setting log level overrides for specific modules (logging targets). This
+///   one is optional. The panics are logged under the `panic` target (see [`PANIC_TARGET`]).
+/// * `target-width`, `thread-width`: Widths of the target and thread name columns in the padded
+///   formats. Default to 30 for the target and 30 or 10 (depending on the format) for the thread
+///   name.
+/// * `thread-id`: Add a column with the OS thread ID (as shown by `top -H`, `perf`, etc.) after
+///   the thread name in the `full` and `machine` formats. Available on Linux and Windows, other
+///   platforms show `-` instead. Defaults to `false`.
+/// * `rate-limit`: Maximum number of messages per second (positive). Excess messages are dropped
+///   and a summary of how many were suppressed is logged instead. Unlimited if not present.
+/// * `flush-interval`: Flush the logger periodically in this interval (eg. `5s`, must not be zero),
+///   so the messages don't stay in buffers for too long.
+/// * `sanitize`: Escape newlines and other control characters in the messages of the text
+///   formats, so untrusted input can't forge log lines. Defaults to `false`.
+/// * `max-line`: Maximum length of a line in bytes. Longer ones are truncated (at a character
+///   boundary) and end with `…`, so it must be at least 3. The JSON formats truncate only the
+///   message, to stay valid. Unlimited if not present.
+/// * `pretty`: Pretty-print the records of the `json` format over multiple lines. This breaks the
+///   one record per line property many tools depend on, so use it only for reading the logs
+///   interactively. Defaults to `false`.
+/// * `static-fields`: A map of constant string fields added to every record of the `json` and
+///   `logstash` formats (eg. `{ service = "auth", env = "prod" }`). Ignored by the text formats.
+///   More fields can be added from the code by [`add_static_field`] or the
+///   [`Cfg::static_field`] extension.
+/// * `level-names`: Custom names of the levels in the output, both text and JSON (eg.
+///   `{ ERROR = "E", WARN = "W" }`). Levels not present keep their default names.
+/// * `type`: Specifies the type of logger destination. Some of them allow specifying other
+///   options.
+/// * `clock`: Either `LOCAL`, `UTC` or a fixed offset from UTC (eg. `+02:00`). With the `timezone`
+///   feature, a timezone name (eg. `Europe/Prague`) is accepted too. Defaults to `LOCAL` if not
+///   present.
+/// * `time_format`: Time
+///   [format string](https://docs.rs/chrono/*/chrono/format/strftime/index.html). Defaults to
+///   `%+` (which is ISO 8601/RFC 3339). Note that the command line logger (one produced by `-l`)
+///   uses a more human-friendly format. An invalid format is rejected when the logger is created.
+/// * `format`: The format to use. There are few presets (and a custom may come in future).
+///   - `message-only`: The line contains only the message itself.
+///   - `short`: This is the default (except for the network and http destinations, which default
+///     to `json`). `<timestamp> <level> <target> <message>`. Padded to form
+///     columns.
+///   - `extended`: <timestamp> <level> <thread-name> <target> <message>`. Padded to form columns.
+///   - `full`: `<timestamp> <level> <thread-name> <file>:<line> <target> <message>`. Padded to
+///     form columns.
+///   - `machine`: Like `full`, but columns are not padded by spaces, they are separated by a
+///     single `\t` character, for more convenient processing by tools like `cut`.
+///   - `json`: The fields of `full` are encoded into a `json` format, for convenient processing of
+///     more modern tools like logstash.
+///   - `logstash`: `json` format with fields named and formatted according to
+///     [Logback JSON encoder](https://github.com/logstash/logstash-logback-encoder#standard-fields)
+///
+///   With the `kv` feature, the structured key-value pairs of the log records are appended as
+///   `key=value` to the text formats and added as additional fields to the JSON ones. Note that
+///   they don't survive the [`Background`] transformation.
+///
+/// The allowed types are:
+/// * `stdout`: The logs are sent to standard output. There are no additional options.
+/// * `stderr`: The logs are sent to standard error output. There are no additional options.
+/// * `file`: Logs are written to a file. The file is reopened every time a configuration is
+///   re-read (therefore every time the application gets `SIGHUP`), which makes it work with
+///   logrotate. Multiple loggers may write into the same file (eg. with different formats or
+///   level bands), they share the handle so the lines don't get mixed together.
 ///   - `filename`: The path to the file where to put the logs.
+///   - `create-dirs`: Create the directory of the file if it doesn't exist yet. Defaults to
+///     `false`.
+///   - `mode`: The permissions of the file, as an octal string (eg. `"0640"`). Unix only.
+///   - `owner`, `group`: Numeric IDs of the user and group to own the file. Useful when the
+///     application starts as root and drops privileges later on (eg. with `spirit-daemonize`),
+///     so the unprivileged user can reopen the file on reload (the mode and ownership are
+///     changed only if they differ). Unix only.
 /// * `network`: The application connects to a given host and port over TCP and sends logs there.
+///   The connection is reopened on every configuration reload and the host name is resolved
+///   again, so a collector that moved is picked up (only the `network-udp` loggers are kept across
+///   reloads). The connecting happens in the background and doesn't block the application, even if
+///   the host is unreachable. A lost connection is re-established. Up to 1000 lines are queued
+///   while not connected, further ones replace the oldest.
 ///   - `host`: The hostname (or IP address) to connect to.
 ///   - `port`: The port to use.
+///   - `tls`: Encrypt the connection with TLS (needs the `tls` feature). Defaults to `false`.
+///   - `domain`: The domain to validate the server certificate against. Defaults to `host`.
+///   - `ca`: A PEM file with an additional certificate authority to trust.
+///   - `batch`: Send the lines in batches, see below.
+///   - `framing`: How the records are delimited in the stream. One of `newline` (the default),
+///     `null` (a null byte instead of the newline, which keeps multi-line messages together) or
+///     `length-prefixed` (each record is preceded by its length as a 32-bit big endian number).
+/// * `network-udp`: Each log line is sent as a separate UDP datagram to the given host and port.
+///   Lines that don't fit into a datagram are lost, as are any datagrams lost in the network.
+///   - `host`: The hostname (or IP address) to send to.
+///   - `port`: The port to use.
+///   - `batch`: Send the lines in batches, each batch as one datagram (the `size` therefore must
+///     fit into a datagram).
+///
+///   The `batch` table of the network destinations has these options:
+///   - `size`: Send once this many bytes accumulate. Defaults to 8192.
+///   - `linger`: Send whatever accumulated at least this often, so a quiet application's logs
+///     don't wait for too long. Defaults to `100ms`.
+/// * `pipe`: Writes the logs into a named pipe (FIFO), for consumption by an external log
+///   shipper. The pipe is reopened on configuration reload, like the file. The writes never
+///   block, if there's no reader or it doesn't keep up, the lines are dropped. Available only on
+///   unix.
+///   - `path`: The path to the (already existing) pipe.
+/// * `ring-buffer`: Keeps the most recent lines in memory, so the application can show them (for
+///   example on a debug endpoint) through the [`RingBuffer`] handle. All the loggers of this type
+///   share the same buffer.
+///   - `capacity`: How many lines to keep.
 /// * `syslog`: Sends the logs to syslog. This ignores all the formatting and time options, as
 ///   syslog handles this itself.
+///   - `host`: Overrides the host value in the log messages. Detected from the local machine if
+///     not present.
+///   - `path`: The path to the unix socket of the syslog (eg. one bind-mounted into a container).
+///     Defaults to `/dev/log` (or `/var/run/syslog` if that one doesn't exist).
+///   - `facility`: The syslog facility to log under (`kern`, `user`, `mail`, `daemon`, `auth`,
+///     `syslog`, `lpr`, `news`, `uucp`, `cron`, `authpriv`, `ftp`, `local0` to `local7`). The
+///     `LOG_` prefixed forms (eg. `LOG_DAEMON`) are accepted too. Defaults to `user`.
+///   - `syslog-format`: Either `rfc3164` (the default) or `rfc5424`.
+///   - `app-name`: The application (process) name in the messages. Defaults to the file name of
+///     the executable.
+///   - `structured-data`: A map of structured data elements (each being a map of parameters) to
+///     attach to the messages. Used only with `rfc5424`.
+///   - `severities`: Overrides of the syslog severities of the levels (eg.
+///     `{ WARN = "notice", TRACE = "debug" }`). The severities are `emerg`, `alert`, `crit`, `err`,
+///     `warning`, `notice`, `info` and `debug` (the `LOG_` prefixed forms like `LOG_ERR` are
+///     accepted too). By default, `ERROR` maps to `err`, `WARN` to `warning`, `INFO` to `info` and
+///     both `DEBUG` and `TRACE` to `debug`.
+///   - `fallback`: If the syslog can't be connected to (eg. in a container without `/dev/log`),
+///     log to stderr instead (with a warning) of failing. Defaults to `false`.
+/// * `journald`: Sends the logs directly to the systemd journal, preserving the source location,
+///   target and thread name as structured fields. Like `syslog`, it ignores the formatting and time
+///   options. Available only with the `journald` feature.
+/// * `http`: POSTs batches of log lines, separated by newlines, to an HTTP endpoint from a
+///   background thread. Best combined with the `json` or `logstash` format. Available only with
+///   the `http` feature.
+///   - `url`: Where to send the logs.
+///   - `batch-size`: Send once this many lines accumulate. Defaults to 100.
+///   - `batch-interval`: Send at least this often. Defaults to `1s`.
+///   - `queue-size`: Maximum number of lines waiting to be sent. More are dropped (and their
+///     number reported on stderr), so a slow endpoint doesn't block the application. Must be
+///     positive, defaults to 10000.
+///   - `retries`: How many times to retry a failed batch (with exponential back off) before
+///     dropping it. Defaults to 3.
+///   - `elasticsearch-index`: If set, the batches are in the format of the Elasticsearch `_bulk`
+///     API, indexing into this index (the `url` should point to the `_bulk` endpoint).
+/// * `either`: Uses one of two destinations. This allows switching between them by overriding a
+///   single option, for example with an environment variable.
+///   - `select`: Which one to use, `a` or `b`.
+///   - `a`, `b`: The destinations, each a table with its own `type` and options.
+///
+///   ```toml
+///   [[logging]]
+///   level = "INFO"
+///   type = "either"
+///   select = "a"
+///   a = { type = "file", filename = "/var/log/app.log" }
+///   b = { type = "stderr" }
+///   ```
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
 pub struct Cfg {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     logging: Vec<Logger>,
+
+    /// Skip the loggers that fail to be created instead of failing the whole configuration.
+    #[serde(default, rename = "logging-best-effort")]
+    best_effort: bool,
+
+    /// Also send errors to stderr when logging into a file.
+    #[serde(default, rename = "logging-tee-errors")]
+    tee_errors: bool,
+
+    /// Add a logger to stderr as set by an environment variable (`RUST_LOG` by default).
+    #[serde(default, rename = "logging-env-filter")]
+    env_filter: bool,
+
+    /// The environment variable to read by the `logging-env-filter` option.
+    #[serde(
+        default,
+        rename = "logging-env-filter-var",
+        skip_serializing_if = "Option::is_none"
+    )]
+    env_filter_var: Option<String>,
+
+    /// Log a line describing each logger once they are installed.
+    #[serde(default, rename = "logging-banner")]
+    banner: bool,
+}
+
+/// The default environment variable of the `logging-env-filter` option.
+const ENV_FILTER_VAR: &str = "RUST_LOG";
+
+/// Parses the `target=level,target2=level` style filter of `RUST_LOG`.
+///
+/// A directive without a target sets the level of everything else (which is `OFF` by default), a
+/// target without a level enables everything of the target.
+fn parse_env_filter(spec: &str) -> Result<(LevelFilter, HashMap<String, LevelFilterSerde>), Error> {
+    let mut level = LevelFilter::Off;
+    let mut per_module = HashMap::new();
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.find('=') {
+            Some(pos) => {
+                let module = directive[..pos].trim().to_owned();
+                let module_level = parse_level(directive[pos + 1..].trim())?;
+                per_module.insert(module, LevelFilterSerde(module_level));
+            }
+            None => match parse_level(directive) {
+                Ok(parsed) => level = parsed,
+                Err(_) => {
+                    per_module.insert(directive.to_owned(), LevelFilterSerde(LevelFilter::Trace));
+                }
+            },
+        }
+    }
+    Ok((level, per_module))
 }
 
 struct Configured;
 
+/// Who asked for the logging to be initialized.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Registration {
+    /// Through the [`Cfg::init_extension`] or [`Cfg::init_extension_no_panic_hook`].
+    Manual { panic_hook: bool },
+    /// By the [`LogInstaller`] of the named pipeline.
+    Pipeline(String),
+}
+
+impl Registration {
+    /// Does the registration ask for something else than the other one?
+    ///
+    /// Registering the same thing again is fine, as is a pipeline after a manual registration
+    /// (that one is there to choose how the pipeline initializes). But two pipelines would replace
+    /// each other's loggers on every reload.
+    fn conflicts(&self, other: &Registration) -> bool {
+        match (self, other) {
+            (Registration::Manual { panic_hook: a }, Registration::Manual { panic_hook: b }) => {
+                a != b
+            }
+            (Registration::Pipeline(a), Registration::Pipeline(b)) => a != b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Registration {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Registration::Manual { panic_hook: true } => write!(fmt, "init_extension"),
+            Registration::Manual { panic_hook: false } => {
+                write!(fmt, "init_extension_no_panic_hook")
+            }
+            Registration::Pipeline(name) => write!(fmt, "pipeline {}", name),
+        }
+    }
+}
+
+/// The registrations of the logging initialization seen since the last one that took effect.
+static REGISTRATIONS: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
 impl Cfg {
+    /// Adds a logger, as if it was in the `logging` configuration.
+    ///
+    /// This allows building the configuration in code, together with the [`LoggerBuilder`].
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logging.push(logger);
+        self
+    }
+
+    /// Describes the loggers this configuration creates.
+    ///
+    /// Note that when combined with the command line through [`CfgAndOpts`], the set of loggers
+    /// may differ, see [`CfgAndOptsRef::loggers`].
+    pub fn loggers(&self) -> Vec<LoggerInfo> {
+        let extra = self.own_extra().unwrap_or_default();
+        self.logging
+            .iter()
+            .chain(&extra)
+            .map(Logger::info)
+            .collect()
+    }
+
+    /// The loggers added to the `logging` ones when the configuration is used on its own.
+    fn own_extra(&self) -> Result<Vec<Logger>, Error> {
+        let mut extra = self.extra(&self.logging)?;
+        // No logging at all ‒ keep logging errors to stderr, like during the startup
+        if self.logging.is_empty() && extra.is_empty() {
+            extra.push(Logger::default());
+        }
+        Ok(extra)
+    }
+
+    /// The loggers synthesized from the top-level options, to be added to the `logging` ones.
+    fn extra<'a, I>(&self, logging: I) -> Result<Vec<Logger>, Error>
+    where
+        I: IntoIterator<Item = &'a Logger>,
+    {
+        let env = self.env_logger()?;
+        // The one from the environment goes to stderr already
+        let tee = if env.is_some() {
+            None
+        } else {
+            self.tee(logging)
+        };
+        Ok(env.into_iter().chain(tee).collect())
+    }
+
+    /// Synthesizes the logger from the environment variable, if asked for and the variable is set.
+    fn env_logger(&self) -> Result<Option<Logger>, Error> {
+        if !self.env_filter {
+            return Ok(None);
+        }
+        let var = self.env_filter_var.as_deref().unwrap_or(ENV_FILTER_VAR);
+        let spec = match env::var(var) {
+            Ok(spec) => spec,
+            Err(_) => return Ok(None),
+        };
+        let (level, per_module) = parse_env_filter(&spec)
+            .with_context(|_| format!("Invalid log filter in the {} environment variable", var))?;
+        Ok(Some(Logger {
+            level: LevelFilterSerde(level),
+            per_module,
+            ..Logger::default()
+        }))
+    }
+
+    /// Synthesizes the logger sending errors to stderr, if asked for and it makes sense.
+    fn tee<'a, I>(&self, logging: I) -> Option<Logger>
+    where
+        I: IntoIterator<Item = &'a Logger>,
+    {
+        if !self.tee_errors {
+            return None;
+        }
+        let mut file = None;
+        for logger in logging {
+            match logger.destination.chosen() {
+                // The errors are already on the console
+                LogDestination::StdErr { .. } => return None,
+                LogDestination::File { .. } if file.is_none() => file = Some(logger),
+                _ => (),
+            }
+        }
+        file.map(|file| Logger {
+            destination: LogDestination::StdErr {
+                buffering: Buffering::default(),
+                sd_prefix: false,
+            },
+            level: LevelFilterSerde(LevelFilter::Error),
+            per_module: HashMap::new(),
+            max_level: None,
+            only: None,
+            rate_limit: None,
+            flush_interval: None,
+            format: Some(file.format()),
+            ..file.clone()
+        })
+    }
+
     /// This provides an [`Extension`] to initialize logging.
     ///
     /// It calls [`init`] and sets up a basic logger (`WARN` and more serious going to `stderr`).
+    /// It also flushes the installed logger when the application terminates.
+    ///
+    /// The level of the basic logger can be overridden by the `SPIRIT_LOG_BOOTSTRAP` environment
+    /// variable (eg. `SPIRIT_LOG_BOOTSTRAP=DEBUG`), to see what happens before the command line
+    /// and configuration are loaded.
     ///
     /// This is seldom used directly (but can be), the [`LogInstaller`] makes sure it is called.
+    ///
+    /// The initialization happens only once. Later registrations are ignored, but if they look
+    /// like a mistake (for example two different logging pipelines, which would replace each
+    /// other's loggers on each reload), a warning is logged.
     pub fn init_extension<E: Extensible>() -> impl Extension<E> {
-        |mut e: E| {
-            if e.singleton::<Configured>() {
-                init();
-                let logger = Logger {
-                    destination: LogDestination::StdErr,
-                    level: LevelFilterSerde(LevelFilter::Warn),
-                    per_module: HashMap::new(),
-                    clock: Clock::Local,
-                    time_format: cmdline_time_format(),
-                    format: Format::Short,
+        Self::init_extension_inner(Registration::Manual { panic_hook: true })
+    }
+
+    /// Like [`init_extension`][Cfg::init_extension], but doesn't touch the panic hook.
+    ///
+    /// This is for cases when the application (or whatever it is embedded into) sets its own
+    /// panic hook. Panics are not logged then.
+    ///
+    /// The initialization happens only once, so to take effect, this needs to be registered
+    /// before the logging [`Pipeline`][spirit::Pipeline] (which registers the
+    /// [`init_extension`][Cfg::init_extension] itself). Registering both this and the
+    /// [`init_extension`][Cfg::init_extension] manually is considered a mistake and only the first
+    /// one takes effect (with a warning).
+    pub fn init_extension_no_panic_hook<E: Extensible>() -> impl Extension<E> {
+        Self::init_extension_inner(Registration::Manual { panic_hook: false })
+    }
+
+    /// This provides an [`Extension`] adding a constant field to the `json` and `logstash`
+    /// records.
+    ///
+    /// See [`add_static_field`] for details. The extension needs to be registered before the
+    /// configuration is loaded, which is the case when it's registered with the builder.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use spirit::prelude::*;
+    /// use spirit_log::Cfg as Logging;
+    ///
+    /// Spirit::<Empty, Empty>::new()
+    ///     .with(Logging::static_field("schema_version", "2"))
+    ///     .with(Logging::static_field("commit_sha", option_env!("COMMIT_SHA").unwrap_or("dev")))
+    ///     .run(|_| Ok(()));
+    /// ```
+    pub fn static_field<E, N, V>(name: N, value: V) -> impl Extension<E>
+    where
+        E: Extensible,
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let name = name.into();
+        let value = value.into();
+        move |e: E| {
+            add_static_field(name, value);
+            e
+        }
+    }
+
+    fn init_extension_inner<E: Extensible>(registration: Registration) -> impl Extension<E> {
+        move |mut e: E| {
+            let first = e.singleton::<Configured>();
+            let mut registrations = REGISTRATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+            if first {
+                // A new application (or a test) starts from scratch
+                registrations.clear();
+            } else if let Some(prev) = registrations.iter().find(|r| r.conflicts(&registration)) {
+                warn!(
+                    "Logging initialized a second time by {}, conflicting with {}; only the first \
+                     one takes effect",
+                    registration, prev,
+                );
+            }
+            let panic_hook = match registration {
+                Registration::Manual { panic_hook } => panic_hook,
+                Registration::Pipeline(_) => true,
+            };
+            registrations.push(registration);
+            drop(registrations);
+            if first {
+                init_inner(panic_hook);
+                let bootstrap = Logger {
+                    level: LevelFilterSerde(bootstrap_level()),
+                    ..Logger::default()
                 };
-                install(create(iter::once(&logger)).unwrap());
+                install(create(iter::once(&bootstrap)).unwrap());
+                // Make sure the last messages before shutdown get out of any buffers
+                e = e.on_terminate(|| log::logger().flush());
             }
             e
         }
     }
 }
 
+/// The log target under which the panics are logged by the panic hook.
+///
+/// Usable in the `per-module` overrides of the loggers to include or exclude the panics.
+pub const PANIC_TARGET: &str = "panic";
+
+/// The environment variable to override the level of the logger used during startup.
+const BOOTSTRAP_ENV: &str = "SPIRIT_LOG_BOOTSTRAP";
+
+fn bootstrap_level() -> LevelFilter {
+    match env::var(BOOTSTRAP_ENV) {
+        Ok(level) => parse_level(&level).unwrap_or_else(|e| {
+            // The logging is not set up yet
+            eprintln!("{} in {}", e, BOOTSTRAP_ENV);
+            LevelFilter::Warn
+        }),
+        Err(_) => LevelFilter::Warn,
+    }
+}
+
 static INIT_CALLED: AtomicBool = AtomicBool::new(false);
 
 /// Initialize the global state.
@@ -742,7 +3143,20 @@ static INIT_CALLED: AtomicBool = AtomicBool::new(false);
 /// It is needed only if the crate is used in the manual way. This is taken care of if used through
 /// the [Pipeline][spirit::Pipeline].
 pub fn init() {
-    log_panics::init();
+    init_inner(true);
+}
+
+/// Like [`init`], but without setting the panic hook.
+///
+/// For applications that set up their own panic hook. Panics are not logged then.
+pub fn init_no_panic_hook() {
+    init_inner(false);
+}
+
+fn init_inner(panic_hook: bool) {
+    if panic_hook {
+        log_panics::init();
+    }
     let _ = log_reroute::init();
     INIT_CALLED.store(true, Ordering::Relaxed);
 }
@@ -751,17 +3165,24 @@ pub fn init() {
 ///
 /// This is a lower-level alternative to [`install`]. This allows putting an arbitrary logger in
 /// (with the corresponding log level at which it makes sense to try log the messages).
+///
+/// The previous logger is flushed after the replacement. Loggers added through [`add_logger`]
+/// stay in place.
 pub fn install_parts(level: LevelFilter, logger: Box<dyn Log>) {
     assert!(
         INIT_CALLED.load(Ordering::Relaxed),
         "spirit_log::init not called yet"
     );
-    let actual_level = cmp::min(level, STATIC_MAX_LEVEL);
-    log::set_max_level(actual_level);
+    let actual_level = cmp::min(level, MAX_LEVEL);
+    // Also sets the global max level, taking the dynamic loggers into account
+    let logger = dynamic::wrap(level, logger);
+    let old = log_reroute::REROUTE.get();
     log_reroute::reroute_boxed(logger);
+    // Whatever the old logger still has buffered must not get lost.
+    old.flush();
     debug!(
         "Installed loggers with global level filter {:?} (compiled with {:?}, runtime config {:?})",
-        actual_level, STATIC_MAX_LEVEL, level,
+        actual_level, MAX_LEVEL, level,
     );
 }
 
@@ -780,16 +3201,29 @@ pub fn install(logger: Dispatch) {
     install_parts(level, logger);
 }
 
+// Always similar, as the files need to be reopened. But unchanged loggers are reused through the
+// seed.
+impl Comparable for Cfg {
+    fn compare(&self, _: &Self) -> Comparison {
+        Comparison::Similar
+    }
+}
+
 impl Fragment for Cfg {
-    type Driver = TrivialDriver;
-    type Seed = ();
+    type Driver = CacheSimilar<Self>;
+    type Seed = LoggerCache;
     type Resource = Dispatch;
     type Installer = LogInstaller;
-    fn make_seed(&self, _name: &str) -> Result<(), Error> {
-        Ok(())
+    fn make_seed(&self, _name: &str) -> Result<LoggerCache, Error> {
+        Ok(LoggerCache::default())
     }
-    fn make_resource(&self, _: &mut (), _name: &str) -> Result<Dispatch, Error> {
-        create(&self.logging)
+    fn make_resource(&self, cache: &mut LoggerCache, _name: &str) -> Result<Dispatch, Error> {
+        let extra = self.own_extra()?;
+        let logger = create_cached(cache, self.logging.iter().chain(&extra), self.best_effort)?;
+        if self.banner {
+            prepare_banner(self.logging.iter().chain(&extra));
+        }
+        Ok(logger)
     }
 }
 
@@ -812,7 +3246,8 @@ impl Fragment for Cfg {
 /// * If there are no loggers in configuration but there's no `-l`, errors are logged to stderr.
 ///   This is the case before configuration is loaded or if it contains no loggers. We want to
 ///   report errors *somewhere*.
-// TODO: Non-owned version too?
+///
+/// If you already hold references to both parts, [`CfgAndOptsRef`] avoids the need to clone them.
 #[derive(Clone, Debug)]
 pub struct CfgAndOpts {
     /// The configuration options.
@@ -821,30 +3256,122 @@ pub struct CfgAndOpts {
     pub opts: Opts,
 }
 
+impl CfgAndOpts {
+    /// Describes the loggers this creates.
+    ///
+    /// See [`CfgAndOptsRef::loggers`].
+    pub fn loggers(&self) -> Vec<LoggerInfo> {
+        CfgAndOptsRef {
+            cfg: &self.cfg,
+            opts: &self.opts,
+        }
+        .loggers()
+    }
+}
+
+impl Comparable for CfgAndOpts {
+    fn compare(&self, _: &Self) -> Comparison {
+        Comparison::Similar
+    }
+}
+
 impl Fragment for CfgAndOpts {
-    type Driver = TrivialDriver;
-    type Seed = ();
+    type Driver = CacheSimilar<Self>;
+    type Seed = LoggerCache;
     type Resource = Dispatch;
     type Installer = LogInstaller;
     const RUN_BEFORE_CONFIG: bool = true;
-    fn make_seed(&self, _name: &str) -> Result<(), Error> {
-        Ok(())
+    fn make_seed(&self, _name: &str) -> Result<LoggerCache, Error> {
+        Ok(LoggerCache::default())
+    }
+    fn make_resource(
+        &self,
+        cache: &mut LoggerCache,
+        name: &'static str,
+    ) -> Result<Dispatch, Error> {
+        CfgAndOptsRef {
+            cfg: &self.cfg,
+            opts: &self.opts,
+        }
+        .make_resource(cache, name)
     }
-    fn make_resource(&self, _: &mut (), _name: &str) -> Result<Dispatch, Error> {
-        let mut cmd = self.opts.logger_cfg();
+}
+
+/// A non-owned version of [`CfgAndOpts`].
+///
+/// It acts exactly the same, but holds only references to the configuration and command line
+/// options.
+#[derive(Copy, Clone, Debug)]
+pub struct CfgAndOptsRef<'a> {
+    /// The configuration options.
+    pub cfg: &'a Cfg,
+    /// The command line options.
+    pub opts: &'a Opts,
+}
+
+impl<'a> Comparable for CfgAndOptsRef<'a> {
+    fn compare(&self, _: &Self) -> Comparison {
+        Comparison::Similar
+    }
+}
+
+impl<'a> Fragment for CfgAndOptsRef<'a> {
+    type Driver = CacheSimilar<Self>;
+    type Seed = LoggerCache;
+    type Resource = Dispatch;
+    type Installer = LogInstaller;
+    const RUN_BEFORE_CONFIG: bool = true;
+    fn make_seed(&self, _name: &str) -> Result<LoggerCache, Error> {
+        Ok(LoggerCache::default())
+    }
+    fn make_resource(&self, cache: &mut LoggerCache, _name: &str) -> Result<Dispatch, Error> {
+        let cmd = self.cmd_logger();
+        let extra = self.cfg.extra(self.selected(&cmd))?;
+        let logging = self.selected(&cmd).chain(&extra);
+        let logger = create_cached(cache, logging, self.cfg.best_effort)?;
+        if self.cfg.banner {
+            prepare_banner(self.selected(&cmd).chain(&extra));
+        }
+        Ok(logger)
+    }
+}
+
+impl<'a> CfgAndOptsRef<'a> {
+    fn cmd_logger(&self) -> Option<Logger> {
+        let cmd = self.opts.logger_cfg();
         // No logging at all ‒ log errors to stderr
         if self.cfg.logging.is_empty() && cmd.is_none() {
-            cmd = Some(Logger::default());
+            Some(Logger::default())
+        } else {
+            cmd
         }
-        create(
-            self.cfg
-                .logging
-                .iter()
-                // A command line overrides any logger to stderr in configuration. But only if it
-                // is set at all.
-                .filter(|l| l.destination != LogDestination::StdErr || cmd.is_none())
-                .chain(cmd.as_ref()),
-        )
+    }
+
+    fn selected<'s>(&'s self, cmd: &'s Option<Logger>) -> impl Iterator<Item = &'s Logger> {
+        self.cfg
+            .logging
+            .iter()
+            // A command line overrides any logger to stderr in configuration. But only if it is
+            // set at all.
+            .filter(move |l| match l.destination.chosen() {
+                LogDestination::StdErr { .. } => cmd.is_none(),
+                _ => true,
+            })
+            .chain(cmd.as_ref())
+    }
+
+    /// Describes the loggers this creates.
+    ///
+    /// This takes the [interaction on stderr](struct.CfgAndOpts.html#interaction-on-stderr) into
+    /// account, so it lists exactly the loggers that get installed. Loggers added by further
+    /// transformations (like [`ExtraLogger`]) are not included.
+    pub fn loggers(&self) -> Vec<LoggerInfo> {
+        let cmd = self.cmd_logger();
+        let extra = self.cfg.extra(self.selected(&cmd)).unwrap_or_default();
+        self.selected(&cmd)
+            .chain(&extra)
+            .map(Logger::info)
+            .collect()
     }
 }
 
@@ -864,9 +3391,12 @@ impl<O, C> Installer<Dispatch, O, C> for LogInstaller {
     type UninstallHandle = ();
     fn install(&mut self, logger: Dispatch, _: &str) {
         install(logger);
+        log_banner();
     }
-    fn init<B: Extensible<Ok = B>>(&mut self, builder: B, _name: &str) -> Result<B, Error> {
-        builder.with(Cfg::init_extension())
+    fn init<B: Extensible<Ok = B>>(&mut self, builder: B, name: &str) -> Result<B, Error> {
+        builder.with(Cfg::init_extension_inner(Registration::Pipeline(
+            name.to_owned(),
+        )))
     }
 }
 
@@ -874,8 +3404,814 @@ impl<O, C> Installer<(LevelFilter, Box<dyn Log>), O, C> for LogInstaller {
     type UninstallHandle = ();
     fn install(&mut self, (level, logger): (LevelFilter, Box<dyn Log>), _: &str) {
         install_parts(level, logger);
+        log_banner();
+    }
+    fn init<B: Extensible<Ok = B>>(&mut self, builder: B, name: &str) -> Result<B, Error> {
+        builder.with(Cfg::init_extension_inner(Registration::Pipeline(
+            name.to_owned(),
+        )))
+    }
+}
+
+/// A [`Transformation`] to add another logger in parallel to the configured ones.
+///
+/// Every time the loggers are created (on startup and on each configuration reload), the closure is
+/// called to provide the additional logger, together with the level it is interested in. It is
+/// then chained to the loggers created from the configuration and the global log level is
+/// adjusted so that the additional logger gets everything it wants.
+///
+/// This is handy for integrating with things like [`sentry`](https://crates.io/crates/sentry).
+///
+/// Note that this operates on the [`Dispatch`], therefore it needs to come before the
+/// [`Background`] transformation (if that one is used).
+///
+/// # Examples
+///
+/// ```rust
+/// use log::LevelFilter;
+/// use serde::Deserialize;
+/// use spirit::prelude::*;
+/// use spirit_log::{Cfg as LogCfg, ExtraLogger};
+/// # struct SentryLogger;
+/// # impl SentryLogger { fn new() -> Self { SentryLogger } }
+/// # impl log::Log for SentryLogger {
+/// #     fn enabled(&self, _: &log::Metadata) -> bool { true }
+/// #     fn log(&self, _: &log::Record) {}
+/// #     fn flush(&self) {}
+/// # }
+///
+/// #[derive(Clone, Debug, Default, Deserialize)]
+/// struct Cfg {
+///     #[serde(flatten)]
+///     log: LogCfg,
+/// }
+///
+/// impl Cfg {
+///     fn log(&self) -> LogCfg {
+///         self.log.clone()
+///     }
+/// }
+///
+/// fn main() {
+///     Spirit::<Empty, Cfg>::new()
+///         .with(
+///             Pipeline::new("logging")
+///                 .extract_cfg(Cfg::log)
+///                 // For example sentry_log::SentryLogger (or anything else implementing Log)
+///                 .transform(ExtraLogger::new(|| {
+///                     (Box::new(SentryLogger::new()) as Box<dyn log::Log>, LevelFilter::Error)
+///                 })),
+///         )
+///         .run(|_spirit| Ok(()));
+/// }
+/// ```
+///
+/// [`Transformation`]: spirit::fragment::Transformation
+#[derive(Clone, Debug)]
+pub struct ExtraLogger<L>(L);
+
+impl<L> ExtraLogger<L>
+where
+    L: FnMut() -> (Box<dyn Log>, LevelFilter),
+{
+    /// Creates the transformation from the closure producing the additional logger.
+    pub fn new(logger: L) -> Self {
+        ExtraLogger(logger)
+    }
+}
+
+impl<L, I, F> Transformation<Dispatch, I, F> for ExtraLogger<L>
+where
+    L: FnMut() -> (Box<dyn Log>, LevelFilter),
+{
+    type OutputResource = Dispatch;
+    type OutputInstaller = I;
+    fn installer(&mut self, original: I, _name: &'static str) -> I {
+        original
+    }
+    fn transform(
+        &mut self,
+        dispatch: Dispatch,
+        _fragment: &F,
+        _name: &'static str,
+    ) -> Result<Dispatch, Error> {
+        let (logger, level) = (self.0)();
+        Ok(dispatch.chain(Dispatch::new().level(level).chain(logger)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_util::{self, TempFile};
+
+    #[test]
+    fn process_name_is_binary() {
+        // The test binary is called spirit_log-<hash>
+        assert!(process_name().starts_with("spirit_log"));
+    }
+
+    #[test]
+    fn facility_names() {
+        let daemon: FacilitySerde = serde_json::from_str(r#""LOG_DAEMON""#).unwrap();
+        assert_eq!(FacilitySerde(Facility::LOG_DAEMON), daemon);
+        let local3: FacilitySerde = serde_json::from_str(r#""local3""#).unwrap();
+        assert_eq!(r#""local3""#, serde_json::to_string(&local3).unwrap());
+        let err = serde_json::from_str::<FacilitySerde>(r#""local8""#).unwrap_err();
+        assert!(err.to_string().contains("local7"));
+    }
+
+    #[test]
+    fn clock() {
+        let clock = |s: &str| serde_json::from_value::<Clock>(serde_json::Value::from(s));
+        assert!(matches!(clock("LOCAL").unwrap(), Clock::Local));
+        assert!(matches!(clock("UTC").unwrap(), Clock::Utc));
+        for (input, secs) in &[("+02:00", 7200), ("-0530", -19800), ("+01", 3600)] {
+            match clock(input).unwrap() {
+                Clock::Fixed(offset) => assert_eq!(*secs, offset.local_minus_utc()),
+                c => panic!("Unexpected clock {:?}", c),
+            }
+        }
+        assert!(clock("+25:00").is_err());
+        assert!(clock("+aé1").is_err());
+        assert!(clock("Nowhere/Nothing").is_err());
+        #[cfg(feature = "timezone")]
+        assert!(matches!(clock("Europe/Prague").unwrap(), Clock::Tz(_)));
+        let fixed = clock("-05:30").unwrap();
+        assert_eq!(r#""-05:30""#, serde_json::to_string(&fixed).unwrap());
+    }
+
+    #[test]
+    fn time_format() {
+        let logger = |format: &str| {
+            serde_json::from_value::<Logger>(serde_json::json!({
+                "type": "stderr",
+                "time-format": format,
+            }))
+            .unwrap()
+        };
+        assert!(logger("%Y-%m-%d %H:%M:%S%.3f").create().is_ok());
+        let err = logger("%Y %Q").create().unwrap_err();
+        let msg = err.iter_chain().join("; ");
+        assert!(msg.contains("stderr"), "{}", msg);
+        assert!(msg.contains(r#""%Y %Q""#), "{}", msg);
+    }
+
+    #[test]
+    fn bootstrap() {
+        assert_eq!(LevelFilter::Warn, bootstrap_level());
+        env::set_var(BOOTSTRAP_ENV, "debug");
+        assert_eq!(LevelFilter::Debug, bootstrap_level());
+        env::set_var(BOOTSTRAP_ENV, "loud");
+        assert_eq!(LevelFilter::Warn, bootstrap_level());
+        env::remove_var(BOOTSTRAP_ENV);
+    }
+
+    #[test]
+    fn verbosity() {
+        let opts =
+            |args: &[&str]| OptsVerbose::from_iter(iter::once("app").chain(args.iter().cloned()));
+        assert_eq!(LevelFilter::Warn, opts(&[]).level(LevelFilter::Warn));
+        assert_eq!(LevelFilter::Trace, opts(&["-vvv"]).level(LevelFilter::Warn));
+        assert_eq!(
+            LevelFilter::Trace,
+            opts(&["-v", "-vvvv"]).level(LevelFilter::Info)
+        );
+        assert_eq!(LevelFilter::Error, opts(&["-qq"]).level(LevelFilter::Info));
+        assert_eq!(LevelFilter::Off, opts(&["-qqqqq"]).level(LevelFilter::Info));
+        assert_eq!(
+            LevelFilter::Debug,
+            opts(&["-vv", "-q"]).level(LevelFilter::Info)
+        );
+        let logger = opts(&["-v"]).opts(LevelFilter::Info).logger_cfg().unwrap();
+        assert_eq!(
+            LogDestination::StdErr {
+                buffering: Buffering::Line,
+                sd_prefix: false,
+            },
+            logger.destination
+        );
+        assert_eq!(LevelFilter::Debug, logger.level.0);
+    }
+
+    #[test]
+    fn opts_builder() {
+        let parsed = Opts::from_iter(&["app", "-l", "INFO", "-L", "spirit=TRACE"]);
+        let built = Opts::from(LevelFilter::Info).with_module("spirit", LevelFilter::Trace);
+        assert_eq!(
+            format!("{:?}", parsed.logger_cfg()),
+            format!("{:?}", built.logger_cfg())
+        );
+        assert!(Opts::default().logger_cfg().is_none());
+    }
+
+    #[test]
+    fn loggers() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [
+                {"type": "stderr", "level": "INFO"},
+                {"type": "file", "filename": "/tmp/log", "per-module": {"spirit": "TRACE"}}
+            ]}"#,
+        )
+        .unwrap();
+        let info = |destination: &str, max_level| LoggerInfo {
+            destination: destination.to_owned(),
+            max_level,
+            format: Some(Format::Short),
+        };
+        let stderr = info("stderr", LevelFilter::Info);
+        let file = info("file /tmp/log", LevelFilter::Trace);
+        assert_eq!(vec![stderr.clone(), file.clone()], cfg.loggers());
+        let warn = info("stderr", LevelFilter::Warn);
+        assert_eq!(vec![warn.clone()], Cfg::default().loggers());
+        let both = CfgAndOpts {
+            cfg,
+            opts: Opts::from(LevelFilter::Debug),
+        };
+        // The command line replaces the stderr logger
+        let cmd = info("stderr", LevelFilter::Debug);
+        assert_eq!(vec![file, cmd], both.loggers());
+        let empty = CfgAndOpts {
+            cfg: Cfg::default(),
+            opts: Opts::default(),
+        };
+        assert_eq!(vec![warn], empty.loggers());
+    }
+
+    #[test]
+    fn builder() {
+        let parsed: Logger = serde_json::from_value(serde_json::json!({
+            "type": "file",
+            "filename": "/tmp/log",
+            "level": "INFO",
+            "per-module": { "hyper": "WARN" },
+            "format": "extended",
+            "clock": "UTC",
+        }))
+        .unwrap();
+        let built = LoggerBuilder::new()
+            .file("/tmp/log")
+            .level(LevelFilter::Info)
+            .module("hyper", LevelFilter::Warn)
+            .format(Format::Extended)
+            .clock(Clock::Utc)
+            .build();
+        assert_eq!(parsed, built);
+    }
+
+    #[test]
+    fn tee_errors() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [
+                {"type": "network-udp", "host": "127.0.0.1", "port": 9, "format": "json"},
+                {"type": "file", "filename": "/tmp/log", "format": "machine", "level": "DEBUG"}
+            ], "logging-tee-errors": true}"#,
+        )
+        .unwrap();
+        let tee = cfg.tee(&cfg.logging).unwrap();
+        assert_eq!(
+            LogDestination::StdErr {
+                buffering: Buffering::Line,
+                sd_prefix: false,
+            },
+            tee.destination
+        );
+        assert_eq!(LevelFilter::Error, tee.level.0);
+        assert_eq!(Some(Format::Machine), tee.format);
+        assert_eq!(3, cfg.loggers().len());
+
+        // Not with the command line logger to stderr
+        let both = CfgAndOpts {
+            cfg,
+            opts: Opts::from(LevelFilter::Info),
+        };
+        assert_eq!(3, both.loggers().len());
+        let without = CfgAndOpts {
+            cfg: both.cfg,
+            opts: Opts::default(),
+        };
+        assert_eq!(3, without.loggers().len());
+        assert_eq!("stderr", without.loggers()[2].destination());
+    }
+
+    #[test]
+    fn destination_format() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [
+                {"type": "network-udp", "host": "127.0.0.1", "port": 9},
+                {"type": "network-udp", "host": "127.0.0.1", "port": 9, "format": "short"},
+                {"type": "stderr"}
+            ]}"#,
+        )
+        .unwrap();
+        let formats = cfg.logging.iter().map(Logger::format).collect::<Vec<_>>();
+        assert_eq!(vec![Format::Json, Format::Short, Format::Short], formats);
+    }
+
+    #[test]
+    fn env_filter_parse() {
+        let (level, per_module) = parse_env_filter("warn, myapp=debug,hyper,").unwrap();
+        assert_eq!(LevelFilter::Warn, level);
+        assert_eq!(2, per_module.len());
+        assert_eq!(LevelFilter::Debug, per_module["myapp"].0);
+        assert_eq!(LevelFilter::Trace, per_module["hyper"].0);
+        assert_eq!(LevelFilter::Off, parse_env_filter("").unwrap().0);
+        assert!(parse_env_filter("myapp=loud").is_err());
+    }
+
+    #[test]
+    fn env_filter() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [{"type": "file", "filename": "/tmp/log"}],
+                "logging-tee-errors": true,
+                "logging-env-filter": true,
+                "logging-env-filter-var": "SPIRIT_LOG_TEST_ENV_FILTER"}"#,
+        )
+        .unwrap();
+        // Not set, only the tee is added
+        assert_eq!(2, cfg.loggers().len());
+        env::set_var("SPIRIT_LOG_TEST_ENV_FILTER", "info,spirit=trace");
+        let loggers = cfg.loggers();
+        assert_eq!(2, loggers.len());
+        assert_eq!("stderr", loggers[1].destination());
+        assert_eq!(LevelFilter::Trace, loggers[1].max_level());
+        env::set_var("SPIRIT_LOG_TEST_ENV_FILTER", "spirit=chatty");
+        assert!(cfg.env_logger().is_err());
+        env::remove_var("SPIRIT_LOG_TEST_ENV_FILTER");
+    }
+
+    #[test]
+    fn syslog_severities() {
+        let default = Severities::default();
+        let levels = [
+            (Level::Error, Severity::Err),
+            (Level::Warn, Severity::Warning),
+            (Level::Info, Severity::Info),
+            (Level::Debug, Severity::Debug),
+            (Level::Trace, Severity::Debug),
+        ];
+        for &(level, severity) in &levels {
+            assert_eq!(severity, default.severity(level));
+        }
+        assert_eq!(3, Severity::Err as u8);
+        assert_eq!(4, Severity::Warning as u8);
+
+        let logger: Logger = serde_json::from_str(
+            r#"{"type": "syslog", "severities": {"WARN": "LOG_CRIT", "TRACE": "notice"}}"#,
+        )
+        .unwrap();
+        match logger.destination {
+            LogDestination::Syslog { severities, .. } => {
+                assert_eq!(Severity::Crit, severities.severity(Level::Warn));
+                assert_eq!(Severity::Notice, severities.severity(Level::Trace));
+                assert_eq!(Severity::Err, severities.severity(Level::Error));
+            }
+            _ => panic!("Not a syslog"),
+        }
+    }
+
+    #[test]
+    fn syslog_fallback() {
+        let logger: Logger =
+            serde_json::from_str(r#"{"type": "syslog", "fallback": true}"#).unwrap();
+        // Either there's syslog or it uses stderr, but succeeds in both cases
+        assert!(logger.create().is_ok());
+    }
+
+    #[test]
+    fn ring_buffer_cfg() {
+        let logger: Logger =
+            serde_json::from_str(r#"{"type": "ring-buffer", "capacity": 100}"#).unwrap();
+        assert_eq!("ring buffer (100)", logger.destination.to_string());
+    }
+
+    #[test]
+    fn flush_interval_positive() {
+        let cfg = r#"{"type": "stderr", "flush-interval": "0s"}"#;
+        assert!(serde_json::from_str::<Logger>(cfg).is_err());
+        let cfg = r#"{"type": "stderr", "flush-interval": "5s"}"#;
+        let logger: Logger = serde_json::from_str(cfg).unwrap();
+        assert_eq!(Some(Duration::from_secs(5)), logger.flush_interval);
+    }
+
+    #[test]
+    fn rate_limit_positive() {
+        let cfg = r#"{"type": "stderr", "rate-limit": 0}"#;
+        assert!(serde_json::from_str::<Logger>(cfg).is_err());
+        let cfg = r#"{"type": "stderr", "rate-limit": 10}"#;
+        let logger: Logger = serde_json::from_str(cfg).unwrap();
+        assert_eq!(NonZeroU32::new(10), logger.rate_limit);
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn http_queue_size() {
+        let cfg = r#"{"type": "http", "url": "http://localhost/", "queue-size": 0}"#;
+        assert!(serde_json::from_str::<Logger>(cfg).is_err());
+        let cfg = r#"{"type": "http", "url": "http://localhost/", "queue-size": 5}"#;
+        serde_json::from_str::<Logger>(cfg).unwrap();
+    }
+
+    #[test]
+    fn syslog_path() {
+        use std::os::unix::net::UnixDatagram;
+
+        for format in &["rfc3164", "rfc5424"] {
+            let path = TempFile::new("syslog");
+            let server = UnixDatagram::bind(path.path()).unwrap();
+            let logger: Logger = serde_json::from_value(serde_json::json!({
+                "type": "syslog",
+                "path": path.path(),
+                "syslog-format": format,
+            }))
+            .unwrap();
+            let (_, logger) = logger.create().unwrap().into_log();
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("Hello"))
+                    .build(),
+            );
+            let mut buf = [0; 1024];
+            let len = server.recv(&mut buf).unwrap();
+            let msg = String::from_utf8_lossy(&buf[..len]);
+            assert!(msg.ends_with("Hello"), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn level_band() {
+        let logger: Logger =
+            serde_json::from_str(r#"{"type": "stderr", "level": "DEBUG", "max-level": "INFO"}"#)
+                .unwrap();
+        let (_, logger) = logger.create().unwrap().into_log();
+        let enabled = |level| logger.enabled(&Metadata::builder().level(level).build());
+        assert!(!enabled(Level::Error));
+        assert!(!enabled(Level::Warn));
+        assert!(enabled(Level::Info));
+        assert!(enabled(Level::Debug));
+        assert!(!enabled(Level::Trace));
+    }
+
+    #[test]
+    #[cfg(not(feature = "disabled"))]
+    fn keep_connections() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [
+                {"type": "network-udp", "host": "127.0.0.1", "port": 9},
+                {"type": "stderr"},
+                {"type": "network", "host": "127.0.0.1", "port": 9}
+            ]}"#,
+        )
+        .unwrap();
+        let mut cache = cfg.make_seed("logging").unwrap();
+        let _ = cfg.make_resource(&mut cache, "logging").unwrap();
+        let first = cache
+            .loggers
+            .iter()
+            .map(|c| Arc::clone(&c.log))
+            .collect::<Vec<_>>();
+        let _ = cfg.make_resource(&mut cache, "logging").unwrap();
+        assert_eq!(3, cache.loggers.len());
+        // The UDP one is kept, the stderr is recreated and the TCP one reconnects
+        assert!(Arc::ptr_eq(&first[0], &cache.loggers[0].log));
+        assert!(!Arc::ptr_eq(&first[1], &cache.loggers[1].log));
+        assert!(!Arc::ptr_eq(&first[2], &cache.loggers[2].log));
+
+        let changed: Cfg = serde_json::from_str(
+            r#"{"logging": [{"type": "network-udp", "host": "127.0.0.1", "port": 10}]}"#,
+        )
+        .unwrap();
+        let _ = changed.make_resource(&mut cache, "logging").unwrap();
+        assert_eq!(1, cache.loggers.len());
+        assert!(!Arc::ptr_eq(&first[0], &cache.loggers[0].log));
     }
-    fn init<B: Extensible<Ok = B>>(&mut self, builder: B, _name: &str) -> Result<B, Error> {
-        builder.with(Cfg::init_extension())
+
+    #[test]
+    #[cfg(not(feature = "disabled"))]
+    fn keep_connections_on_failure() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [{"type": "network-udp", "host": "127.0.0.1", "port": 9}]}"#,
+        )
+        .unwrap();
+        let mut cache = cfg.make_seed("logging").unwrap();
+        let _ = cfg.make_resource(&mut cache, "logging").unwrap();
+        let first = Arc::clone(&cache.loggers[0].log);
+
+        // The file can't be created, so the reload fails before getting to the UDP one
+        let broken: Cfg = serde_json::from_str(
+            r#"{"logging": [
+                {"type": "file", "filename": "/nonexistent/dir/test.log"},
+                {"type": "network-udp", "host": "127.0.0.1", "port": 9}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(broken.make_resource(&mut cache, "logging").is_err());
+        assert_eq!(1, cache.loggers.len());
+        assert!(Arc::ptr_eq(&first, &cache.loggers[0].log));
+
+        let _ = cfg.make_resource(&mut cache, "logging").unwrap();
+        assert_eq!(1, cache.loggers.len());
+        assert!(Arc::ptr_eq(&first, &cache.loggers[0].log));
+    }
+
+    #[test]
+    #[cfg(feature = "disabled")]
+    fn disabled() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [{"type": "file", "filename": "/nonexistent/dir/log"}]}"#,
+        )
+        .unwrap();
+        let mut cache = cfg.make_seed("logging").unwrap();
+        let (max_level, _) = cfg.make_resource(&mut cache, "logging").unwrap().into_log();
+        assert_eq!(LevelFilter::Off, max_level);
+        assert!(cache.loggers.is_empty());
+    }
+
+    #[test]
+    fn create_dirs() {
+        let dir = TempFile::new("dirs");
+        let filename = dir.path().join("nested").join("test.log");
+        create_parent(&filename).unwrap();
+        assert!(dir.path().join("nested").is_dir());
+
+        // Can't create a directory inside a regular file
+        fs::write(&filename, "").unwrap();
+        let blocked = filename.join("sub").join("test.log");
+        let err = create_parent(&blocked).unwrap_err().to_string();
+        assert!(err.contains(&filename.join("sub").display().to_string()));
+        assert!(err.contains(&blocked.display().to_string()));
+    }
+
+    #[test]
+    #[cfg(not(feature = "disabled"))]
+    fn best_effort() {
+        let path = TempFile::new("best-effort");
+        let cfg = |best_effort: bool| -> Cfg {
+            let json = serde_json::json!({
+                "logging": [
+                    {"type": "file", "filename": "/nonexistent/dir/test.log"},
+                    {
+                        "type": "file",
+                        "filename": path.path(),
+                        "format": "message-only",
+                        "level": "WARN",
+                    },
+                ],
+                "logging-best-effort": best_effort,
+            });
+            serde_json::from_value(json).unwrap()
+        };
+        let mut cache = LoggerCache::default();
+        assert!(cfg(false).make_resource(&mut cache, "logging").is_err());
+
+        let (_, logger) = cfg(true)
+            .make_resource(&mut cache, "logging")
+            .unwrap()
+            .into_log();
+        logger.flush();
+        let content = path.content();
+        assert!(content.starts_with("Skipping logger file /nonexistent/dir/test.log: "));
+
+        // Nothing to log into
+        let all_failed: Cfg = serde_json::from_str(
+            r#"{"logging": [{"type": "file", "filename": "/nonexistent/dir/test.log"}],
+                "logging-best-effort": true}"#,
+        )
+        .unwrap();
+        assert!(all_failed.make_resource(&mut cache, "logging").is_err());
+    }
+
+    #[test]
+    fn banner() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [
+                {"type": "stderr", "level": "DEBUG", "format": "json"},
+                {"type": "syslog", "fallback": true}
+            ], "logging-banner": true}"#,
+        )
+        .unwrap();
+        let mut cache = cfg.make_seed("logging").unwrap();
+        let _ = cfg.make_resource(&mut cache, "logging").unwrap();
+        let banner = BANNER.lock().unwrap().clone();
+        assert_eq!(2, banner.len());
+        assert_eq!(LevelFilter::Debug, banner[0].max_level());
+        assert_eq!(Some(Format::Json), banner[0].format());
+        assert_eq!(None, banner[1].format());
+        log_banner();
+        assert!(BANNER.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn static_fields() {
+        let path = TempFile::new("static");
+        let logger = path.logger(serde_json::json!({
+            "format": "json",
+            "static-fields": {"service": "auth", "env": "prod"},
+        }));
+        test_util::log(&*logger, Level::Error, "test", "Hello");
+        let record: serde_json::Value = serde_json::from_str(&path.content()).unwrap();
+        assert_eq!("Hello", record["message"]);
+        assert_eq!("auth", record["service"]);
+        assert_eq!("prod", record["env"]);
+    }
+
+    #[test]
+    fn level_names() {
+        let path = TempFile::new("level-names");
+        let logger = path.logger(serde_json::json!({
+            "format": "machine",
+            "level": "WARN",
+            "level-names": {"ERROR": "error"},
+        }));
+        for &level in &[Level::Error, Level::Warn] {
+            test_util::log(&*logger, level, "test", "Hello");
+        }
+        let content = path.content();
+        let levels = content
+            .lines()
+            .map(|line| line.split('\t').nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["error", "WARN"], levels);
+    }
+
+    #[test]
+    fn only() {
+        let path = TempFile::new("only");
+        let json = serde_json::json!({
+            "type": "file",
+            "filename": path.path(),
+            "format": "message-only",
+            "only": "WARN",
+            "per-module": {"verbose": "DEBUG"},
+        });
+        let logger: Logger = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(LevelFilter::Debug, logger.info().max_level());
+        let logger = path.logger(json);
+        let records = [
+            (Level::Error, "app", "error"),
+            (Level::Warn, "app", "warn"),
+            (Level::Info, "app", "info"),
+            (Level::Debug, "verbose::inner", "debug"),
+            (Level::Debug, "verbose_not", "other debug"),
+        ];
+        for &(level, target, msg) in &records {
+            test_util::log(&*logger, level, target, msg);
+        }
+        assert_eq!("warn\ndebug\n", path.content());
+
+        let off = serde_json::from_value::<Logger>(serde_json::json!({"only": "OFF"}));
+        assert!(off.is_err());
+    }
+
+    #[test]
+    fn sd_prefix() {
+        let json = serde_json::json!({"type": "stdout", "sd-prefix": true});
+        let logger: Logger = serde_json::from_value(json).unwrap();
+        match logger.destination {
+            LogDestination::StdOut { sd_prefix, .. } => assert!(sd_prefix),
+            _ => panic!("Not stdout"),
+        }
+        let prefix = |level, on| SdPrefix { level, on }.to_string();
+        assert_eq!("<3>", prefix(Level::Error, true));
+        assert_eq!("<4>", prefix(Level::Warn, true));
+        assert_eq!("<6>", prefix(Level::Info, true));
+        assert_eq!("<7>", prefix(Level::Trace, true));
+        assert_eq!("", prefix(Level::Error, false));
+    }
+
+    #[test]
+    fn color() {
+        for &(mode, colored) in &[("always", true), ("auto", false), ("never", false)] {
+            let path = TempFile::new("color");
+            let logger = path.logger(serde_json::json!({ "color": mode }));
+            test_util::log(&*logger, Level::Error, "test", "Broken");
+            // Files are never a terminal, so only the forced colors get there
+            assert_eq!(colored, path.content().contains("\x1b[31mERROR\x1b[0m"));
+        }
+    }
+
+    #[test]
+    fn registration_conflicts() {
+        let hook = Registration::Manual { panic_hook: true };
+        let no_hook = Registration::Manual { panic_hook: false };
+        let logging = Registration::Pipeline("logging".to_owned());
+        let other = Registration::Pipeline("other".to_owned());
+        assert!(!hook.conflicts(&hook));
+        assert!(hook.conflicts(&no_hook));
+        assert!(!no_hook.conflicts(&logging));
+        assert!(!logging.conflicts(&logging));
+        assert!(logging.conflicts(&other));
+        assert_eq!("pipeline other", other.to_string());
+    }
+
+    #[test]
+    fn multi_log_short_circuit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Counting(AtomicUsize);
+
+        impl Log for Counting {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+            fn log(&self, _: &Record) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            fn flush(&self) {}
+        }
+
+        let counting = Arc::new(Counting::default());
+        let multi = MultiLog {
+            loggers: vec![
+                (
+                    LevelFilter::Warn,
+                    Arc::new(Box::new(Arc::clone(&counting)) as Box<dyn Log>),
+                ),
+                (
+                    LevelFilter::Off,
+                    Arc::new(Box::new(Arc::clone(&counting)) as Box<dyn Log>),
+                ),
+            ],
+        };
+        assert_eq!(LevelFilter::Warn, multi.max_level());
+        for &level in &[Level::Error, Level::Warn, Level::Info, Level::Trace] {
+            multi.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("Hello"))
+                    .build(),
+            );
+        }
+        assert_eq!(2, counting.0.load(Ordering::Relaxed));
+        assert!(!multi.enabled(&Metadata::builder().level(Level::Info).build()));
+    }
+
+    #[test]
+    fn either() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [{
+                "type": "either",
+                "select": "b",
+                "a": {"type": "file", "filename": "/tmp/log"},
+                "b": {"type": "either", "select": "a", "a": {"type": "stderr"}, "b": {"type": "stdout"}}
+            }]}"#,
+        )
+        .unwrap();
+        let logger = &cfg.logging[0];
+        assert_eq!(
+            LogDestination::StdErr {
+                buffering: Buffering::Line,
+                sd_prefix: false,
+            },
+            *logger.destination.chosen()
+        );
+        assert_eq!("stderr", logger.destination.to_string());
+        assert!(!logger.keep_alive());
+        let _ = logger.create().unwrap();
+    }
+
+    #[test]
+    fn unnamed_thread() {
+        let named = thread::Builder::new()
+            .name("worker".to_owned())
+            .spawn(|| thread_name(&thread::current()).into_owned())
+            .unwrap();
+        assert_eq!("worker", named.join().unwrap());
+        let unnamed = thread::spawn(|| {
+            let name = thread_name(&thread::current()).into_owned();
+            let expected = format!("{:?}", thread::current().id());
+            (name, expected)
+        });
+        let (name, expected) = unnamed.join().unwrap();
+        assert_eq!(format!("ThreadId({})", &name["thread-".len()..]), expected);
+    }
+
+    #[test]
+    fn panic_filtered() {
+        let cfg: Cfg = serde_json::from_str(
+            r#"{"logging": [{"type": "stderr", "level": "OFF", "per-module": {"panic": "ERROR"}}]}"#,
+        )
+        .unwrap();
+        let captured = Captured::new();
+        let (_, logger) = cfg.logging[0]
+            .create()
+            .unwrap()
+            .chain(captured.writer())
+            .into_log();
+        for &target in &["app", PANIC_TARGET] {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .target(target)
+                    .args(format_args!("Boom"))
+                    .build(),
+            );
+        }
+        let lines = captured.take();
+        assert_eq!(1, lines.len());
+        assert!(lines[0].contains(" panic "));
     }
 }
@@ -0,0 +1,94 @@
+//! The compile-time ceiling of the log levels, set by the `max_level_*` feature flags.
+
+use log::{LevelFilter, STATIC_MAX_LEVEL};
+
+/// The level chosen by one family of the feature flags, the most restrictive one wins.
+///
+/// Unlike the [`log`] crate, we don't refuse multiple flags at once, so `--all-features` still
+/// builds.
+const fn chosen(
+    off: bool,
+    error: bool,
+    warn: bool,
+    info: bool,
+    debug: bool,
+    trace: bool,
+) -> Option<LevelFilter> {
+    if off {
+        Some(LevelFilter::Off)
+    } else if error {
+        Some(LevelFilter::Error)
+    } else if warn {
+        Some(LevelFilter::Warn)
+    } else if info {
+        Some(LevelFilter::Info)
+    } else if debug {
+        Some(LevelFilter::Debug)
+    } else if trace {
+        Some(LevelFilter::Trace)
+    } else {
+        None
+    }
+}
+
+const DEBUG_LEVEL: Option<LevelFilter> = chosen(
+    cfg!(feature = "max_level_off"),
+    cfg!(feature = "max_level_error"),
+    cfg!(feature = "max_level_warn"),
+    cfg!(feature = "max_level_info"),
+    cfg!(feature = "max_level_debug"),
+    cfg!(feature = "max_level_trace"),
+);
+
+const RELEASE_LEVEL: Option<LevelFilter> = chosen(
+    cfg!(feature = "release_max_level_off"),
+    cfg!(feature = "release_max_level_error"),
+    cfg!(feature = "release_max_level_warn"),
+    cfg!(feature = "release_max_level_info"),
+    cfg!(feature = "release_max_level_debug"),
+    cfg!(feature = "release_max_level_trace"),
+);
+
+/// Combines the flags the same way as the [`log`] crate does.
+const fn resolve(
+    release: bool,
+    debug_level: Option<LevelFilter>,
+    release_level: Option<LevelFilter>,
+    static_level: LevelFilter,
+) -> LevelFilter {
+    let level = match (release, release_level, debug_level) {
+        (true, Some(level), _) | (_, _, Some(level)) => level,
+        _ => LevelFilter::Trace,
+    };
+    if (static_level as usize) < (level as usize) {
+        static_level
+    } else {
+        level
+    }
+}
+
+/// The most verbose level that can ever get logged.
+///
+/// This is the more restrictive one of our feature flags and the ones of the [`log`] crate.
+pub(crate) const MAX_LEVEL: LevelFilter = resolve(
+    !cfg!(debug_assertions),
+    DEBUG_LEVEL,
+    RELEASE_LEVEL,
+    STATIC_MAX_LEVEL,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_levels() {
+        use LevelFilter::*;
+        assert_eq!(Trace, resolve(false, None, None, Trace));
+        assert_eq!(Debug, resolve(false, Some(Debug), Some(Info), Trace));
+        assert_eq!(Info, resolve(true, Some(Debug), Some(Info), Trace));
+        assert_eq!(Debug, resolve(true, Some(Debug), None, Trace));
+        assert_eq!(Warn, resolve(true, Some(Debug), Some(Info), Warn));
+        assert_eq!(Some(Off), chosen(true, false, false, true, false, true));
+    }
+}
@@ -0,0 +1,80 @@
+//! Flushing a logger in regular intervals.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error;
+use log::{Log, Metadata, Record};
+
+use crate::worker::Ticker;
+
+/// A logger wrapper that flushes the inner logger periodically from a helper thread.
+///
+/// The thread terminates as soon as the wrapper is dropped (eg. when the logger gets replaced on
+/// configuration reload).
+pub(crate) struct PeriodicFlush {
+    inner: Arc<Box<dyn Log>>,
+    _flusher: Ticker,
+}
+
+impl PeriodicFlush {
+    pub(crate) fn new(inner: Box<dyn Log>, interval: Duration) -> Result<Self, Error> {
+        let inner = Arc::new(inner);
+        let flushed = Arc::clone(&inner);
+        let flusher = Ticker::start("spirit-log-flush", interval, move || flushed.flush())?;
+        Ok(Self {
+            inner,
+            _flusher: flusher,
+        })
+    }
+}
+
+impl Log for PeriodicFlush {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+    }
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    struct Flushes(Arc<AtomicUsize>);
+
+    impl Log for Flushes {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, _: &Record) {}
+        fn flush(&self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn flushes_until_dropped() {
+        let cnt = Arc::new(AtomicUsize::new(0));
+        let flushed = PeriodicFlush::new(
+            Box::new(Flushes(Arc::clone(&cnt))),
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(cnt.load(Ordering::Relaxed) > 0);
+        drop(flushed);
+        // Give the thread a chance to notice
+        thread::sleep(Duration::from_millis(50));
+        let after_drop = cnt.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(after_drop, cnt.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,112 @@
+//! Writing the logs into a named pipe (FIFO).
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Result as IoResult, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+/// A [`Write`] adapter writing whole lines into a named pipe, without ever blocking.
+///
+/// Fern flushes the writer after each log line, so whatever was written between two flushes is
+/// written into the pipe as one piece. If there's no reader on the other side (or it doesn't keep
+/// up and the pipe is full), the line is dropped. The pipe is opened again on the next line once
+/// the reader disappears, so a restarted reader gets connected again.
+///
+/// Note that lines longer than `PIPE_BUF` (usually 4kB) may get split or cut if the pipe is close
+/// to full.
+pub(crate) struct PipeWriter {
+    path: PathBuf,
+    pipe: Option<File>,
+    buffer: Vec<u8>,
+}
+
+impl PipeWriter {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            pipe: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn open(&self) -> IoResult<File> {
+        OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if self.pipe.is_none() {
+            match self.open() {
+                Ok(pipe) => self.pipe = Some(pipe),
+                // ENXIO ‒ nobody reads the pipe (yet)
+                Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => (),
+                Err(e) => {
+                    self.buffer.clear();
+                    return Err(e);
+                }
+            }
+        }
+        let result = match self.pipe.as_mut() {
+            Some(pipe) => pipe.write_all(&self.buffer),
+            None => Ok(()),
+        };
+        // Either written or dropped, we never try the same line again.
+        self.buffer.clear();
+        match result {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
+                self.pipe = None;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::process::Command;
+
+    use super::*;
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn reader_comes_and_goes() {
+        let fifo = TempFile::new("pipe");
+        let path = fifo.path();
+        // The crate forbids unsafe, so no calling mkfifo directly
+        assert!(Command::new("mkfifo").arg(path).status().unwrap().success());
+        let mut writer = PipeWriter::new(path.to_owned());
+        // Nobody listens, this just gets lost
+        writeln!(writer, "Lost").unwrap();
+        writer.flush().unwrap();
+
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .unwrap();
+        writeln!(writer, "Hello").unwrap();
+        writer.flush().unwrap();
+        let mut buf = [0; 64];
+        let len = reader.read(&mut buf).unwrap();
+        assert_eq!(b"Hello\n", &buf[..len]);
+
+        drop(reader);
+        writeln!(writer, "Lost again").unwrap();
+        writer.flush().unwrap();
+        assert!(writer.pipe.is_none());
+    }
+}
@@ -0,0 +1,186 @@
+//! Limiting the number of messages a logger lets through.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use log::{Level, Log, Metadata, Record};
+
+use crate::worker::Ticker;
+
+/// How often the helper thread checks if a summary of suppressed messages is due.
+const TICK: Duration = Duration::from_secs(1);
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    suppressed: usize,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+    }
+}
+
+struct Shared {
+    inner: Box<dyn Log>,
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Shared {
+    fn bucket(&self) -> MutexGuard<'_, Bucket> {
+        // If someone panicked while holding the lock, the data is still fine to use.
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Tries to take a token.
+    ///
+    /// Returns `None` if the message should be dropped, otherwise the number of messages
+    /// suppressed since the last successful one.
+    fn acquire(&self) -> Option<usize> {
+        let mut bucket = self.bucket();
+        bucket.refill(self.rate);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            let suppressed = bucket.suppressed;
+            bucket.suppressed = 0;
+            Some(suppressed)
+        } else {
+            bucket.suppressed += 1;
+            None
+        }
+    }
+
+    /// Logs the summary of the suppressed messages if the budget allows messages again.
+    fn report(&self) {
+        let suppressed = {
+            let mut bucket = self.bucket();
+            bucket.refill(self.rate);
+            if bucket.tokens < 1.0 {
+                return;
+            }
+            let suppressed = bucket.suppressed;
+            bucket.suppressed = 0;
+            suppressed
+        };
+        self.summary(suppressed);
+    }
+
+    fn summary(&self, suppressed: usize) {
+        if suppressed > 0 {
+            self.inner.log(
+                &Record::builder()
+                    .args(format_args!("Suppressed {} messages", suppressed))
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .line(Some(line!()))
+                    .module_path(Some(module_path!()))
+                    .build(),
+            );
+        }
+    }
+}
+
+/// A logger wrapper that lets through at most `rate` messages per second.
+///
+/// It is a token bucket with capacity of one second worth of messages. Messages over the limit
+/// are dropped and counted. Once the budget allows messages again, a warning with the number of
+/// suppressed messages is logged ‒ before the next message that gets through, or by a helper
+/// thread if no message comes.
+///
+/// The thread terminates as soon as the wrapper is dropped.
+pub(crate) struct RateLimited {
+    shared: Arc<Shared>,
+    _reporter: Ticker,
+}
+
+impl RateLimited {
+    pub(crate) fn new(inner: Box<dyn Log>, rate: u32) -> Result<Self, Error> {
+        let rate = f64::from(rate);
+        let shared = Arc::new(Shared {
+            inner,
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                last: Instant::now(),
+                suppressed: 0,
+            }),
+        });
+        let reported = Arc::clone(&shared);
+        let reporter = Ticker::start("spirit-log-rate", TICK, move || reported.report())?;
+        Ok(Self {
+            shared,
+            _reporter: reporter,
+        })
+    }
+}
+
+impl Log for RateLimited {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.shared.inner.enabled(metadata)
+    }
+    fn log(&self, record: &Record) {
+        if !self.shared.inner.enabled(record.metadata()) {
+            return;
+        }
+        if let Some(suppressed) = self.shared.acquire() {
+            self.shared.summary(suppressed);
+            self.shared.inner.log(record);
+        }
+    }
+    fn flush(&self) {
+        self.shared.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Log for Counter {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, _: &Record) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn drops_over_limit() {
+        let cnt = Arc::new(AtomicUsize::new(0));
+        let limited = RateLimited::new(Box::new(Counter(Arc::clone(&cnt))), 5).unwrap();
+        for _ in 0..20 {
+            limited.log(&Record::builder().args(format_args!("msg")).build());
+        }
+        // Nobody can refill 15 tokens within a test run.
+        assert!(cnt.load(Ordering::Relaxed) < 10);
+        assert!(cnt.load(Ordering::Relaxed) >= 5);
+    }
+
+    #[test]
+    fn summary_without_more_messages() {
+        let cnt = Arc::new(AtomicUsize::new(0));
+        let limited = RateLimited::new(Box::new(Counter(Arc::clone(&cnt))), 5).unwrap();
+        for _ in 0..20 {
+            limited.log(&Record::builder().args(format_args!("msg")).build());
+        }
+        let passed = cnt.load(Ordering::Relaxed);
+        // The helper thread reports the suppressed ones once the budget refills
+        thread::sleep(TICK * 2 + Duration::from_millis(500));
+        assert_eq!(passed + 1, cnt.load(Ordering::Relaxed));
+    }
+}
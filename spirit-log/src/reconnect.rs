@@ -0,0 +1,275 @@
+//! A connection to a remote log collector, established and re-established in the background.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Error;
+
+use crate::framing::Framing;
+use crate::worker::report;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_SLEEP: Duration = Duration::from_secs(1);
+const RECONNECT_SLEEP_MAX: Duration = Duration::from_secs(30);
+const BACKLOG: usize = 1000;
+
+/// Something that can (repeatedly) open the connection.
+pub(crate) type Connector = Arc<dyn Fn() -> Result<Box<dyn Write + Send>, Error> + Send + Sync>;
+
+/// Opens a TCP connection, without waiting for too long for hosts that don't answer.
+pub(crate) fn tcp_connect(host: &str, port: u16) -> IoResult<TcpStream> {
+    let mut error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(conn) => return Ok(conn),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error
+        .unwrap_or_else(|| IoError::new(ErrorKind::NotFound, format!("Can't resolve {}", host))))
+}
+
+/// A [`Write`] adapter that connects in the background and reconnects when the connection is lost.
+///
+/// The connecting never blocks the logging, therefore the application starts even if the remote
+/// side is unreachable at the time. Whatever was written between two flushes (which is one line
+/// with fern) is queued until the connection is established. If too many lines accumulate, the
+/// oldest are dropped (and the number of dropped lines is reported once connected, as a record
+/// framed the same way as the others).
+pub(crate) struct ReconnectWriter {
+    name: String,
+    connector: Connector,
+    framing: Framing,
+    conn: Option<Box<dyn Write + Send>>,
+    pending: Option<Receiver<Result<Box<dyn Write + Send>, Error>>>,
+    next_attempt: Instant,
+    sleep: Duration,
+    buffer: Vec<u8>,
+    backlog: VecDeque<Vec<u8>>,
+    dropped: usize,
+}
+
+impl ReconnectWriter {
+    /// Creates the writer and starts connecting right away.
+    pub(crate) fn new(name: String, connector: Connector, framing: Framing) -> Self {
+        let mut writer = Self {
+            name,
+            connector,
+            framing,
+            conn: None,
+            pending: None,
+            next_attempt: Instant::now(),
+            sleep: RECONNECT_SLEEP,
+            buffer: Vec::new(),
+            backlog: VecDeque::new(),
+            dropped: 0,
+        };
+        writer.start();
+        writer
+    }
+
+    fn start(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        let connector = Arc::clone(&self.connector);
+        let started = thread::Builder::new()
+            .name("spirit-log-connect".to_owned())
+            .spawn(move || {
+                let _ = sender.send(connector());
+            });
+        match started {
+            Ok(_) => self.pending = Some(receiver),
+            Err(e) => self.failed(e.into()),
+        }
+    }
+
+    fn failed(&mut self, e: Error) {
+        report(format_args!("Failed to connect to {}: {}", self.name, e));
+        self.next_attempt = Instant::now() + self.sleep;
+        self.sleep = cmp::min(self.sleep * 2, RECONNECT_SLEEP_MAX);
+    }
+
+    /// Checks on the connection in progress or starts a new one if it's time.
+    fn poll_conn(&mut self) {
+        if self.conn.is_some() {
+            return;
+        }
+        let result = match self.pending.as_ref().map(Receiver::try_recv) {
+            Some(Err(TryRecvError::Empty)) => return,
+            Some(Ok(result)) => result,
+            Some(Err(TryRecvError::Disconnected)) => Err(failure::err_msg("Connecting panicked")),
+            None => {
+                if Instant::now() >= self.next_attempt {
+                    self.start();
+                }
+                return;
+            }
+        };
+        self.pending = None;
+        match result {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.sleep = RECONNECT_SLEEP;
+                if self.dropped > 0 {
+                    let msg = format!(
+                        "{} log lines dropped while not connected",
+                        mem::take(&mut self.dropped)
+                    );
+                    self.backlog.push_front(self.framing.frame(msg.as_bytes()));
+                }
+            }
+            Err(e) => self.failed(e),
+        }
+    }
+}
+
+impl Write for ReconnectWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if !self.buffer.is_empty() {
+            if self.backlog.len() >= BACKLOG {
+                self.backlog.pop_front();
+                self.dropped += 1;
+            }
+            self.backlog.push_back(mem::take(&mut self.buffer));
+        }
+        if self.backlog.is_empty() {
+            return Ok(());
+        }
+        self.poll_conn();
+        while let (Some(conn), Some(line)) = (self.conn.as_mut(), self.backlog.front()) {
+            match conn.write_all(line).and_then(|()| conn.flush()) {
+                Ok(()) => {
+                    self.backlog.pop_front();
+                }
+                Err(e) => {
+                    report(format_args!("Lost connection to {}: {}", self.name, e));
+                    // Keep the line and reconnect right away
+                    self.conn = None;
+                    self.next_attempt = Instant::now();
+                    self.poll_conn();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn log(writer: &mut ReconnectWriter, line: &str) {
+        writeln!(writer, "{}", line).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn queued_until_connected() {
+        let output = Shared::default();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let connector: Connector = {
+            let output = output.clone();
+            let attempts = Arc::clone(&attempts);
+            Arc::new(move || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(50));
+                Ok(Box::new(output.clone()) as Box<dyn Write + Send>)
+            })
+        };
+        let mut writer = ReconnectWriter::new("test".to_owned(), connector, Framing::Newline);
+        // Doesn't wait for the connection
+        log(&mut writer, "Hello");
+        assert!(writer.conn.is_none());
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(10));
+            log(&mut writer, "world");
+            if writer.conn.is_some() {
+                break;
+            }
+        }
+        assert_eq!(1, attempts.load(Ordering::Relaxed));
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("Hello\nworld\n"));
+    }
+
+    #[test]
+    fn unreachable() {
+        let connector: Connector = Arc::new(|| {
+            thread::sleep(Duration::from_millis(10));
+            Err(failure::err_msg("Unreachable"))
+        });
+        let mut writer = ReconnectWriter::new("test".to_owned(), connector, Framing::Newline);
+        let start = Instant::now();
+        for i in 0..BACKLOG + 10 {
+            log(&mut writer, &i.to_string());
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(BACKLOG, writer.backlog.len());
+        assert_eq!(10, writer.dropped);
+        assert_eq!(b"10\n", &writer.backlog[0][..]);
+    }
+
+    #[test]
+    fn dropped_notice_framed() {
+        let output = Shared::default();
+        let reachable = Arc::new(AtomicUsize::new(0));
+        let connector: Connector = {
+            let output = output.clone();
+            let reachable = Arc::clone(&reachable);
+            Arc::new(move || {
+                if reachable.load(Ordering::Relaxed) == 0 {
+                    Err(failure::err_msg("Unreachable"))
+                } else {
+                    Ok(Box::new(output.clone()) as Box<dyn Write + Send>)
+                }
+            })
+        };
+        let mut writer = ReconnectWriter::new("test".to_owned(), connector, Framing::Null);
+        for i in 0..BACKLOG + 1 {
+            log(&mut writer, &i.to_string());
+        }
+        reachable.store(1, Ordering::Relaxed);
+        for _ in 0..100 {
+            writer.next_attempt = Instant::now();
+            log(&mut writer, "x");
+            if writer.conn.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let notice = output.split('\0').next().unwrap();
+        assert!(
+            notice.ends_with(" log lines dropped while not connected"),
+            "{}",
+            output
+        );
+        assert!(!notice.contains('\n'));
+    }
+}
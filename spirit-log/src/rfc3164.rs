@@ -0,0 +1,51 @@
+//! Sending RFC 3164 formatted messages to the local syslog.
+//!
+//! The logger of the syslog crate is fine, but fern picks the severities of the messages for it
+//! in a fixed way.
+
+use std::sync::{Mutex, PoisonError};
+
+use log::{Log, Metadata, Record};
+use syslog::{Formatter3164, Logger, LoggerBackend};
+
+use crate::{Severities, Severity};
+
+/// A logger sending RFC 3164 messages with configurable severities.
+pub(crate) struct Rfc3164 {
+    inner: Mutex<Logger<LoggerBackend, String, Formatter3164>>,
+    severities: Severities,
+}
+
+impl Rfc3164 {
+    pub(crate) fn new(
+        inner: Logger<LoggerBackend, String, Formatter3164>,
+        severities: Severities,
+    ) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+            severities,
+        }
+    }
+}
+
+impl Log for Rfc3164 {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &Record) {
+        let msg = record.args().to_string();
+        let mut syslog = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        // Nowhere to report the errors to.
+        let _ = match self.severities.severity(record.level()) {
+            Severity::Emerg => syslog.emerg(msg),
+            Severity::Alert => syslog.alert(msg),
+            Severity::Crit => syslog.crit(msg),
+            Severity::Err => syslog.err(msg),
+            Severity::Warning => syslog.warning(msg),
+            Severity::Notice => syslog.notice(msg),
+            Severity::Info => syslog.info(msg),
+            Severity::Debug => syslog.debug(msg),
+        };
+    }
+    fn flush(&self) {}
+}
@@ -0,0 +1,126 @@
+//! Sending RFC 5424 formatted messages to the local syslog.
+//!
+//! The syslog crate has a formatter for it, but can't create a logger using it.
+
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{ErrorKind, Result as IoResult};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process;
+
+use chrono::{SecondsFormat, Utc};
+use log::{Log, Metadata, Record};
+use syslog::Facility;
+
+use crate::Severities;
+
+// The RFC says to escape these in the parameter values.
+fn escape(value: &str) -> String {
+    value.chars().fold(String::new(), |mut res, c| {
+        if c == '"' || c == '\\' || c == ']' {
+            res.push('\\');
+        }
+        res.push(c);
+        res
+    })
+}
+
+fn structured_data(data: &HashMap<String, HashMap<String, String>>) -> String {
+    if data.is_empty() {
+        return "-".to_owned();
+    }
+    let mut res = String::new();
+    for (id, params) in data {
+        res.push('[');
+        res.push_str(id);
+        for (name, value) in params {
+            write!(res, " {}=\"{}\"", name, escape(value)).unwrap();
+        }
+        res.push(']');
+    }
+    res
+}
+
+/// A logger sending RFC 5424 messages to the local syslog socket.
+pub(crate) struct Rfc5424 {
+    socket: UnixDatagram,
+    facility: Facility,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+    structured_data: String,
+    severities: Severities,
+}
+
+impl Rfc5424 {
+    /// Connects to the syslog socket at the `path`, or at the default location if not set.
+    pub(crate) fn connect(
+        path: Option<&Path>,
+        facility: Facility,
+        hostname: Option<String>,
+        app_name: String,
+        data: &HashMap<String, HashMap<String, String>>,
+        severities: Severities,
+    ) -> IoResult<Self> {
+        let socket = UnixDatagram::unbound()?;
+        match path {
+            Some(path) => socket.connect(path)?,
+            // The same locations as the syslog crate tries
+            None => match socket.connect("/dev/log") {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                    socket.connect("/var/run/syslog")?
+                }
+                res => res?,
+            },
+        }
+        Ok(Self {
+            socket,
+            facility,
+            hostname: hostname.unwrap_or_else(|| "-".to_owned()),
+            app_name,
+            pid: process::id(),
+            structured_data: structured_data(data),
+            severities,
+        })
+    }
+}
+
+impl Log for Rfc5424 {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &Record) {
+        let msg = format!(
+            "<{}>1 {} {} {} {} - {} {}",
+            self.facility as u8 | self.severities.severity(record.level()) as u8,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.hostname,
+            self.app_name,
+            self.pid,
+            self.structured_data,
+            record.args(),
+        );
+        // Nowhere to report the errors to.
+        let _ = self.socket.send(msg.as_bytes());
+    }
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_structured_data() {
+        assert_eq!("-", structured_data(&HashMap::new()));
+        let mut params = HashMap::new();
+        params.insert("name".to_owned(), r#"a "quoted" ]value"#.to_owned());
+        let mut data = HashMap::new();
+        data.insert("app@32473".to_owned(), params);
+        assert_eq!(
+            r#"[app@32473 name="a \"quoted\" \]value"]"#,
+            structured_data(&data)
+        );
+    }
+}
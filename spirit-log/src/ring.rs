@@ -0,0 +1,119 @@
+//! Keeping the most recent log lines in memory.
+
+use std::collections::VecDeque;
+use std::io::{Result as IoResult, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+struct Ring {
+    capacity: usize,
+    lines: VecDeque<String>,
+}
+
+impl Ring {
+    fn trim(&mut self) {
+        while self.lines.len() > self.capacity {
+            self.lines.pop_front();
+        }
+    }
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+    capacity: 0,
+    lines: VecDeque::new(),
+});
+
+// Pushing a line can't leave the buffer inconsistent, so the poisoning can be ignored.
+fn ring() -> MutexGuard<'static, Ring> {
+    RING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Creates a writer into the ring buffer, (re)setting its capacity.
+pub(crate) fn writer(capacity: usize) -> RingWriter {
+    let mut ring = ring();
+    ring.capacity = capacity;
+    ring.trim();
+    RingWriter { line: Vec::new() }
+}
+
+/// A writer storing whatever is written between two flushes as one line into the ring buffer.
+pub(crate) struct RingWriter {
+    line: Vec<u8>,
+}
+
+impl Write for RingWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim_end_matches(&['\n', '\r'][..]).to_owned();
+        self.line.clear();
+        let mut ring = ring();
+        ring.lines.push_back(line);
+        ring.trim();
+        Ok(())
+    }
+}
+
+/// A handle to the most recent log lines.
+///
+/// The lines are collected by the loggers with the `ring-buffer` destination. There's only one
+/// buffer in the application, so if there are multiple such loggers, they all write into it (and
+/// its capacity is the one of the last created).
+///
+/// This is useful for example to show the recent errors in an administration interface, without
+/// having to dig them out of the log files.
+///
+/// # Examples
+///
+/// ```rust
+/// let recent = spirit_log::ring_buffer();
+/// for line in recent.lines() {
+///     println!("{}", line);
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RingBuffer(());
+
+impl RingBuffer {
+    /// Returns a copy of the lines in the buffer, from the oldest one.
+    pub fn lines(&self) -> Vec<String> {
+        ring().lines.iter().cloned().collect()
+    }
+
+    /// Clears the buffer.
+    pub fn clear(&self) {
+        ring().lines.clear();
+    }
+}
+
+/// Returns the handle to the ring buffer with the most recent log lines.
+///
+/// See [`RingBuffer`].
+pub fn ring_buffer() -> RingBuffer {
+    RingBuffer(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let mut writer = writer(2);
+        for line in &["one", "two", "three"] {
+            writeln!(writer, "{}", line).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(vec!["two", "three"], ring_buffer().lines());
+        // Shrinking throws the oldest ones away
+        drop(super::writer(1));
+        assert_eq!(vec!["three"], ring_buffer().lines());
+        ring_buffer().clear();
+        assert!(ring_buffer().lines().is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+//! Escaping of control characters in the logged messages.
+
+use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+
+/// Passes the text through, escaping the control characters on the way.
+struct Escaper<'a, 'f>(&'a mut Formatter<'f>);
+
+impl Write for Escaper<'_, '_> {
+    fn write_str(&mut self, s: &str) -> FmtResult {
+        // Write the runs of harmless characters at once, only the control ones one by one.
+        let mut start = 0;
+        for (pos, c) in s.char_indices().filter(|(_, c)| c.is_control()) {
+            self.0.write_str(&s[start..pos])?;
+            write!(self.0, "{}", c.escape_default())?;
+            start = pos + c.len_utf8();
+        }
+        self.0.write_str(&s[start..])
+    }
+}
+
+/// Displays the message, with newlines, escape sequences and other control characters escaped if
+/// `sanitize` is set.
+///
+/// This prevents forging of fake log lines and messing with the terminal by untrusted input.
+pub(crate) struct Sanitized<T> {
+    pub(crate) message: T,
+    pub(crate) sanitize: bool,
+}
+
+impl<T: Display> Display for Sanitized<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        if self.sanitize {
+            write!(Escaper(fmt), "{}", self.message)
+        } else {
+            self.message.fmt(fmt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitized(message: &str, sanitize: bool) -> String {
+        Sanitized { message, sanitize }.to_string()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            "Hello\\nfake line\\r\\t\\u{1b}[31mred ř",
+            sanitized("Hello\nfake line\r\t\x1b[31mred ř", true)
+        );
+        assert_eq!("Hello\nworld", sanitized("Hello\nworld", false));
+    }
+}
@@ -0,0 +1,100 @@
+//! Sharing one log file between multiple loggers.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use failure::Error;
+
+/// The files opened while creating one set of loggers, by their canonical paths.
+///
+/// Loggers writing into the same file (eg. with different level bands) get the same handle, so
+/// their lines don't get interleaved.
+#[derive(Default)]
+pub(crate) struct SharedFiles {
+    files: HashMap<PathBuf, Arc<Mutex<File>>>,
+}
+
+impl SharedFiles {
+    /// Provides a writer into the file, opening it by `open` if it's not open yet.
+    pub(crate) fn writer<O>(&mut self, filename: &Path, open: O) -> Result<SharedFile, Error>
+    where
+        O: FnOnce() -> Result<File, Error>,
+    {
+        let existing = fs::canonicalize(filename)
+            .ok()
+            .and_then(|path| self.files.get(&path));
+        let file = match existing {
+            Some(file) => Arc::clone(file),
+            None => {
+                let file = Arc::new(Mutex::new(open()?));
+                // The file might not have existed before opening, so look at the path only now
+                let path = fs::canonicalize(filename).unwrap_or_else(|_| filename.to_owned());
+                self.files.insert(path, Arc::clone(&file));
+                file
+            }
+        };
+        Ok(SharedFile {
+            file,
+            line: Vec::new(),
+        })
+    }
+}
+
+/// A writer into a shared file.
+///
+/// Whatever is written between two flushes (a line, as fern flushes after each one) is written
+/// into the file at once, under a lock.
+pub(crate) struct SharedFile {
+    file: Arc<Mutex<File>>,
+    line: Vec<u8>,
+}
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = file.write_all(&self.line).and_then(|()| file.flush());
+        // Don't retry a partially written line with the next one
+        self.line.clear();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn shared() {
+        let file = TempFile::new("shared");
+        let path = file.path();
+        let open = || File::create(path).map_err(Error::from);
+        let mut files = SharedFiles::default();
+        let mut first = files.writer(path, open).unwrap();
+        // Through a different path, but the same file
+        let other = path
+            .parent()
+            .unwrap()
+            .join(".")
+            .join(path.file_name().unwrap());
+        let mut second = files
+            .writer(&other, || panic!("Opened the second time"))
+            .unwrap();
+        write!(first, "Hello ").unwrap();
+        writeln!(second, "Second").unwrap();
+        second.flush().unwrap();
+        writeln!(first, "world").unwrap();
+        first.flush().unwrap();
+        assert_eq!("Second\nHello world\n", file.content());
+    }
+}
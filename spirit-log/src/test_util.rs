@@ -0,0 +1,83 @@
+//! Helpers shared by the tests.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, Log, Record};
+
+use crate::Logger;
+
+/// A unique path in the temporary directory, removed (with whatever is there) when dropped.
+pub(crate) struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Picks the path, the `name` is only to tell the files of different tests apart.
+    ///
+    /// Nothing is created, the test does that.
+    pub(crate) fn new(name: &str) -> Self {
+        // Tests run in parallel, so the process ID alone is not enough
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "spirit-log-{}-{}-{}",
+            name,
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = TempFile(path);
+        file.remove();
+        file
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn content(&self) -> String {
+        fs::read_to_string(&self.0).unwrap()
+    }
+
+    /// Creates a logger writing into this file.
+    ///
+    /// The `cfg` contains the rest of the configuration of the logger, the type and filename are
+    /// filled in.
+    pub(crate) fn logger(&self, cfg: serde_json::Value) -> Box<dyn Log> {
+        let mut cfg = match cfg {
+            serde_json::Value::Object(cfg) => cfg,
+            cfg => panic!("Logger config {} is not an object", cfg),
+        };
+        cfg.insert("type".to_owned(), "file".into());
+        cfg.insert("filename".to_owned(), self.0.to_str().unwrap().into());
+        let logger: Logger = serde_json::from_value(cfg.into()).unwrap();
+        let (_, logger) = logger.create().unwrap().into_log();
+        logger
+    }
+
+    fn remove(&self) {
+        let _ = if self.0.is_dir() {
+            fs::remove_dir_all(&self.0)
+        } else {
+            fs::remove_file(&self.0)
+        };
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Logs a message with the given level and target and flushes the logger.
+pub(crate) fn log(logger: &dyn Log, level: Level, target: &str, msg: &str) {
+    logger.log(
+        &Record::builder()
+            .level(level)
+            .target(target)
+            .args(format_args!("{}", msg))
+            .build(),
+    );
+    logger.flush();
+}
@@ -0,0 +1,94 @@
+//! The OS-level identifier of the current thread.
+//!
+//! This is the number tools like `top -H`, `perf` or the debuggers show, unlike the
+//! [`ThreadId`][std::thread::ThreadId] of the Rust standard library.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+// The crate forbids unsafe, so no calling gettid directly. But the kernel exposes the thread as
+// /proc/thread-self -> <pid>/task/<tid>.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn native() -> Option<u64> {
+    let link = std::fs::read_link("/proc/thread-self").ok()?;
+    link.file_name()?.to_str()?.parse().ok()
+}
+
+// GetCurrentThreadId, wrapped by a crate so we don't need the unsafe.
+#[cfg(windows)]
+fn native() -> Option<u64> {
+    Some(thread_id::get() as u64)
+}
+
+// Other systems don't have a safe way to get it :-(
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+fn native() -> Option<u64> {
+    None
+}
+
+thread_local! {
+    // The ID doesn't change during the life of the thread, so ask the OS only once.
+    static TID: Option<u64> = native();
+}
+
+/// The OS thread ID of the current thread, if the platform has one.
+pub(crate) fn current() -> Option<u64> {
+    TID.with(|tid| *tid)
+}
+
+/// The thread ID column of the text formats.
+///
+/// Prints nothing if the column is turned off, otherwise the ID (or `-` if not available) padded
+/// to the width and followed by the separator.
+pub(crate) struct TidColumn {
+    pub(crate) tid: Option<Option<u64>>,
+    pub(crate) width: usize,
+    pub(crate) separator: char,
+}
+
+impl Display for TidColumn {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self.tid {
+            None => Ok(()),
+            Some(Some(tid)) => write!(fmt, "{:>w$}{}", tid, self.separator, w = self.width),
+            Some(None) => write!(fmt, "{:>w$}{}", "-", self.separator, w = self.width),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    #[cfg(any(target_os = "linux", windows))]
+    fn tid() {
+        let mine = current().unwrap();
+        assert_eq!(Some(mine), current());
+        let other = thread::spawn(current).join().unwrap().unwrap();
+        assert_ne!(mine, other);
+    }
+
+    #[test]
+    fn column() {
+        let off = TidColumn {
+            tid: None,
+            width: 7,
+            separator: ' ',
+        };
+        assert_eq!("", off.to_string());
+        let on = TidColumn {
+            tid: Some(Some(42)),
+            width: 7,
+            separator: ' ',
+        };
+        assert_eq!("     42 ", on.to_string());
+        let unknown = TidColumn {
+            tid: Some(None),
+            width: 0,
+            separator: '\t',
+        };
+        assert_eq!("-\t", unknown.to_string());
+    }
+}
@@ -0,0 +1,86 @@
+//! Limiting the length of the logged lines.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use serde::de::{Deserialize, Deserializer, Error as DeError, Unexpected};
+use serde::ser::{Serialize, Serializer};
+
+/// Appended to the truncated text.
+const MARKER: &str = "…";
+
+/// Deserializes the limit, refusing ones too short to hold even the [`MARKER`].
+pub(crate) fn deserialize_max<'de, D: Deserializer<'de>>(d: D) -> Result<Option<usize>, D::Error> {
+    let max = Option::<usize>::deserialize(d)?;
+    match max {
+        Some(max) if max < MARKER.len() => Err(D::Error::invalid_value(
+            Unexpected::Unsigned(max as u64),
+            &"at least 3 bytes, to fit the truncation mark",
+        )),
+        max => Ok(max),
+    }
+}
+
+/// Displays the message, truncated to at most `max` bytes if it is set.
+///
+/// The text is cut at a char boundary and the [`MARKER`] is appended (fitting into the `max`
+/// bytes together with the text, which needs `max` to be at least the length of the marker).
+pub(crate) struct Limited<T> {
+    pub(crate) message: T,
+    pub(crate) max: Option<usize>,
+}
+
+impl<T: Display> Display for Limited<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let max = match self.max {
+            Some(max) => max,
+            None => return self.message.fmt(fmt),
+        };
+        let text = self.message.to_string();
+        if text.len() <= max {
+            return fmt.write_str(&text);
+        }
+        let mut cut = max.saturating_sub(MARKER.len());
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        fmt.write_str(&text[..cut])?;
+        fmt.write_str(MARKER)
+    }
+}
+
+impl<T: Display> Serialize for Limited<T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(message: &str, max: Option<usize>) -> String {
+        Limited { message, max }.to_string()
+    }
+
+    #[test]
+    fn truncates() {
+        assert_eq!("Hello world", limited("Hello world", None));
+        assert_eq!("Hello world", limited("Hello world", Some(11)));
+        assert_eq!("Hello…", limited("Hello world", Some(8)));
+        // Doesn't cut the ť in half
+        assert_eq!("Žlu…", limited("Žluťoučký", Some(8)));
+        assert_eq!(
+            r#"{"message":"Hel…"}"#,
+            serde_json::json!({ "message": Limited { message: "Hello world", max: Some(6) } })
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn too_short() {
+        let max = |json| deserialize_max(&mut serde_json::Deserializer::from_str(json));
+        assert!(max("2").is_err());
+        assert_eq!(Some(3), max("3").unwrap());
+        assert_eq!(None, max("null").unwrap());
+    }
+}
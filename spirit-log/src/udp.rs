@@ -0,0 +1,66 @@
+//! Sending each log line as a separate UDP datagram.
+
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// A [`Write`] adapter sending whatever was written between two flushes as one datagram.
+///
+/// Fern flushes the writer after each log line, so this sends one datagram per line.
+pub(crate) struct UdpWriter {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl UdpWriter {
+    pub(crate) fn connect(host: &str, port: u16) -> IoResult<Self> {
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| IoError::new(ErrorKind::NotFound, format!("Can't resolve {}", host)))?;
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(Self {
+            socket,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl Write for UdpWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = self.socket.send(&self.buffer);
+        // Fire and forget ‒ don't try sending the same line again.
+        self.buffer.clear();
+        result.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datagram_per_flush() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let mut writer = UdpWriter::connect("127.0.0.1", port).unwrap();
+        let who = "world";
+        write!(writer, "Hello {}", who).unwrap();
+        writer.write_all(b"\n").unwrap();
+        writer.flush().unwrap();
+        let mut buf = [0; 100];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(b"Hello world\n", &buf[..len]);
+    }
+}
@@ -0,0 +1,47 @@
+//! Helper threads working for the loggers in the background.
+
+use std::fmt::Arguments;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use failure::{Error, ResultExt};
+
+/// Reports a problem of a logger.
+///
+/// We can't log the problem, we are the logger. Similar to what fern does with failing outputs,
+/// it goes to stderr.
+pub(crate) fn report(msg: Arguments) {
+    eprintln!("{}", msg);
+}
+
+/// A thread calling a callback in regular intervals.
+///
+/// The thread terminates as soon as the ticker is dropped.
+pub(crate) struct Ticker {
+    // Never sent to, dropping it wakes up and terminates the thread. The mutex is only to make it
+    // Sync, as older Senders are not.
+    _stop: Mutex<Sender<()>>,
+}
+
+impl Ticker {
+    /// Starts the thread, calling the `tick` each `interval`.
+    pub(crate) fn start<F>(name: &str, interval: Duration, mut tick: F) -> Result<Self, Error>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    tick();
+                }
+            })
+            .with_context(|_| format!("Failed to start the {} thread", name))?;
+        Ok(Self {
+            _stop: Mutex::new(stop),
+        })
+    }
+}
@@ -0,0 +1,30 @@
+use std::thread;
+
+use log::LevelFilter;
+use spirit_log::{Captured, PANIC_TARGET};
+
+// In its own test binary, as the panic hook and the logger are global
+#[test]
+// Nothing gets logged with the levels turned off at compile time
+#[cfg_attr(
+    any(feature = "max_level_off", feature = "release_max_level_off"),
+    ignore
+)]
+fn panic_target() {
+    let captured = Captured::new();
+    let (logger, _) = captured.logger(LevelFilter::Error);
+    spirit_log::init();
+    spirit_log::install_parts(LevelFilter::Error, logger);
+
+    let result = thread::Builder::new()
+        .name("doomed".to_owned())
+        .spawn(|| panic!("Boom"))
+        .unwrap()
+        .join();
+    assert!(result.is_err());
+
+    let lines = captured.take();
+    assert_eq!(1, lines.len());
+    let prefix = format!("ERROR {}: thread 'doomed' panicked at 'Boom'", PANIC_TARGET);
+    assert!(lines[0].starts_with(&prefix), "{}", lines[0]);
+}
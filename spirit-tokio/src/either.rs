@@ -211,7 +211,6 @@ mod other_either {
             }
         }
     }
-
 }
 
 macro_rules! either {
@@ -387,6 +386,12 @@ where
             _ => unreachable!("Seed vs. fragment mismatch"),
         }
     }
+    fn identity(&self) -> Option<String> {
+        match self {
+            Either::A(a) => a.identity(),
+            Either::B(b) => b.identity(),
+        }
+    }
 }
 
 /// An [`Installer`] for [`Either`] [`Resource`]s.
@@ -413,6 +418,16 @@ where
             Either::B(rb) => Either::B(self.1.install(rb, name)),
         }
     }
+    fn try_install(
+        &mut self,
+        resource: Either<RA, RB>,
+        name: &'static str,
+    ) -> Result<Self::UninstallHandle, Error> {
+        match resource {
+            Either::A(ra) => self.0.try_install(ra, name).map(Either::A),
+            Either::B(rb) => self.1.try_install(rb, name).map(Either::B),
+        }
+    }
     fn init<E: Extensible<Opts = O, Config = C, Ok = E>>(
         &mut self,
         builder: E,
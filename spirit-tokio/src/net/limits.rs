@@ -3,7 +3,9 @@
 //! „Naked“ listening sockets have two important problems:
 //!
 //! * They sometimes return errors when accepting, which often terminates the stream. Most of these
-//!   are actually recoverable error in practice, so the termination seldom makes sense.
+//!   are actually recoverable error in practice, so the termination seldom makes sense. Retrying
+//!   right away isn't a good idea either, as that just spins the CPU until the situation improves
+//!   (eg. until some file descriptors are freed), so the listener backs off for a while.
 //! * They have no limit on how many active connections they have spawned, allowing the application
 //!   to grow without limits and eat all OS resources.
 //!
@@ -13,16 +15,18 @@
 //! [`WithListenLimits`]: crate::net::limits::WithListenLimits
 //! [`TcpListenWithLimits`]: crate::net::TcpListenWithLimits
 
+use std::cmp;
 use std::fmt::Debug;
-use std::io::{Error as IoError, Read, Write};
+use std::io::{Error as IoError, ErrorKind, Read, Write};
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use failure::Error;
 use futures::task::AtomicTask;
-use futures::{Async, Poll, Stream};
+use futures::{Async, Future, Poll, Stream};
+use log::{debug, error, warn};
 use serde::de::DeserializeOwned;
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
@@ -32,8 +36,8 @@ use spirit::fragment::{Fragment, Stackable};
 #[cfg(feature = "cfg-help")]
 use structdoc::StructDoc;
 use structopt::StructOpt;
-use tk_listen::{ListenExt, SleepOnError};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Delay;
 
 use super::IntoIncoming;
 
@@ -47,6 +51,23 @@ pub trait ListenLimits {
     /// How long to sleep when error happens.
     fn error_sleep(&self) -> Duration;
 
+    /// The longest sleep after repeated errors.
+    ///
+    /// Each error following directly after another one doubles the sleep, up to this limit. The
+    /// default implementation returns [`error_sleep`][ListenLimits::error_sleep], therefore the
+    /// sleep stays the same.
+    fn error_sleep_max(&self) -> Duration {
+        self.error_sleep()
+    }
+
+    /// How many errors in a row are tolerated before giving up.
+    ///
+    /// If the limit is exceeded, the stream of incoming connections terminates with the last
+    /// error. The default implementation returns `None`, meaning to retry forever.
+    fn max_errors(&self) -> Option<usize> {
+        None
+    }
+
     /// Maximum number of active connections one instance will have.
     ///
     /// If you don't want the limit, return some huge number (`usize::max_value() / 2 - 1` is
@@ -118,7 +139,11 @@ where
         let inner = self.listener.make_resource(seed, name)?;
         Ok(LimitedListener {
             inner,
-            error_sleep: self.limits.error_sleep(),
+            backoff: Backoff {
+                error_sleep: self.limits.error_sleep(),
+                error_sleep_max: self.limits.error_sleep_max(),
+                max_errors: self.limits.max_errors(),
+            },
             max_conn: self.limits.max_conn(),
         })
     }
@@ -129,6 +154,9 @@ where
     {
         Listener::init(builder, name)
     }
+    fn identity(&self) -> Option<String> {
+        self.listener.identity()
+    }
 }
 
 fn default_error_sleep() -> Duration {
@@ -148,6 +176,10 @@ fn serialize_duration<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Err
 ///
 /// * `error-sleep`: The back-off time when non-fatal error happens, in human readable form.
 ///   Defaults to `100ms` if not present.
+/// * `error-sleep-max`: Each error directly following another one doubles the back-off time, up
+///   to this limit. Defaults to `error-sleep` (therefore the time doesn't grow).
+/// * `max-errors`: The listener gives up after more than this many errors in a row. Retries
+///   forever if not present.
 /// * `max-conn`: Maximum number of parallel connections on this listener. Defaults to no limit
 ///   (well, to `usize::max_value() / 2 - 1`, actually, for technical reasons, but that should be
 ///   effectively no limit).
@@ -170,6 +202,30 @@ pub struct Limits {
     )]
     error_sleep: Duration,
 
+    /// The longest back-off time after repeated errors.
+    ///
+    /// Each error that comes right after another one (without successfully accepting a
+    /// connection in between) doubles the time to wait, up to this limit.
+    ///
+    /// Defaults to `error-sleep`, so the time doesn't grow.
+    #[serde(
+        rename = "error-sleep-max",
+        default,
+        deserialize_with = "spirit::utils::deserialize_opt_duration",
+        serialize_with = "spirit::utils::serialize_opt_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    error_sleep_max: Option<Duration>,
+
+    /// Maximum number of errors in a row.
+    ///
+    /// If more than this many errors happen without successfully accepting a connection in between,
+    /// the listener gives up and terminates.
+    ///
+    /// If not set, it keeps trying forever.
+    #[serde(rename = "max-errors", skip_serializing_if = "Option::is_none")]
+    max_errors: Option<usize>,
+
     /// Maximum number of connections per one listener.
     ///
     /// If it is reached, more connections will not be accepted until some of the old ones are
@@ -178,7 +234,11 @@ pub struct Limits {
     /// Default to implementation limits if not set (2^31 - 1 on 32bit systems, 2^63 - 1 on 64bit
     /// systems), which is likely higher than what the OS can effectively handle ‒ so you can
     /// assume that if not set, there's no limit.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "max-conn",
+        alias = "max_conn",
+        skip_serializing_if = "Option::is_none"
+    )]
     max_conn: Option<usize>,
 }
 
@@ -186,6 +246,8 @@ impl Default for Limits {
     fn default() -> Self {
         Self {
             error_sleep: default_error_sleep(),
+            error_sleep_max: None,
+            max_errors: None,
             max_conn: None,
         }
     }
@@ -195,6 +257,12 @@ impl ListenLimits for Limits {
     fn error_sleep(&self) -> Duration {
         self.error_sleep
     }
+    fn error_sleep_max(&self) -> Duration {
+        self.error_sleep_max.unwrap_or(self.error_sleep)
+    }
+    fn max_errors(&self) -> Option<usize> {
+        self.max_errors
+    }
     fn max_conn(&self) -> usize {
         self.max_conn.unwrap_or_else(|| usize::max_value() / 2 - 1)
     }
@@ -209,7 +277,7 @@ impl ListenLimits for Limits {
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct LimitedListener<Inner> {
     inner: Inner,
-    error_sleep: Duration,
+    backoff: Backoff,
     max_conn: usize,
 }
 
@@ -217,9 +285,11 @@ impl<Inner: IntoIncoming> IntoIncoming for LimitedListener<Inner> {
     type Connection = LimitedConn<Inner::Connection>;
     type Incoming = LimitedIncoming<Inner::Incoming>;
     fn into_incoming(self) -> Self::Incoming {
-        let inner = self.inner.into_incoming().sleep_on_error(self.error_sleep);
         LimitedIncoming {
-            inner,
+            inner: self.inner.into_incoming(),
+            backoff: self.backoff,
+            errors: 0,
+            sleep: None,
             limit: Arc::new(ConnLimit {
                 max_conn: self.max_conn,
                 active_cnt: AtomicUsize::new(0),
@@ -229,6 +299,34 @@ impl<Inner: IntoIncoming> IntoIncoming for LimitedListener<Inner> {
     }
 }
 
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Backoff {
+    error_sleep: Duration,
+    error_sleep_max: Duration,
+    max_errors: Option<usize>,
+}
+
+impl Backoff {
+    /// How long to sleep after this many errors in a row.
+    fn sleep(&self, errors: usize) -> Duration {
+        // Not shifting further, by then it's surely over the max
+        let mult = 1 << cmp::min(errors.saturating_sub(1), 16);
+        let sleep = self
+            .error_sleep
+            .checked_mul(mult)
+            .unwrap_or(self.error_sleep_max);
+        cmp::max(cmp::min(sleep, self.error_sleep_max), self.error_sleep)
+    }
+}
+
+/// Errors that concern only the single connection, not the listener.
+fn is_conn_error(e: &IoError) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset
+    )
+}
+
 struct ConnLimit {
     max_conn: usize,
     // 2 * count of connections + I'm blocked flag
@@ -298,11 +396,66 @@ impl ConnLimit {
 ///
 /// This is what will come of the [`Fragment`] from [`WithListenLimits`]. It is a stream of
 /// accepted connections, but without the errors and slowing down when a limit is reached.
+///
+/// It terminates with an error only if the configured maximum number of errors in a row is
+/// exceeded.
 pub struct LimitedIncoming<Inner> {
-    inner: SleepOnError<Inner>,
+    inner: Inner,
+    backoff: Backoff,
+    errors: usize,
+    sleep: Option<Delay>,
     limit: Arc<ConnLimit>,
 }
 
+impl<Inner> LimitedIncoming<Inner>
+where
+    Inner: Stream<Error = IoError>,
+{
+    fn accept(&mut self) -> Poll<Option<Inner::Item>, IoError> {
+        loop {
+            if let Some(sleep) = self.sleep.as_mut() {
+                match sleep.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => (),
+                    // Timer errors only on shutdown. Continuing without sleep is the best we can
+                    // do.
+                    Err(e) => warn!("Failed to sleep after an error: {}", e),
+                }
+                self.sleep = None;
+            }
+            match self.inner.poll() {
+                Err(ref e) if is_conn_error(e) => {
+                    debug!("Connection failed before it was accepted: {}", e);
+                }
+                Err(e) => {
+                    self.errors += 1;
+                    let errors = self.errors;
+                    if self
+                        .backoff
+                        .max_errors
+                        .filter(|&max| errors > max)
+                        .is_some()
+                    {
+                        error!("Giving up accepting after {} errors: {}", self.errors, e);
+                        return Err(e);
+                    }
+                    let sleep = self.backoff.sleep(self.errors);
+                    error!(
+                        "Error accepting connection ({} in a row), sleeping for {:?}: {}",
+                        self.errors, sleep, e,
+                    );
+                    self.sleep = Some(Delay::new(Instant::now() + sleep));
+                }
+                Ok(Async::Ready(Some(conn))) => {
+                    self.errors = 0;
+                    return Ok(Async::Ready(Some(conn)));
+                }
+                Ok(other) => return Ok(other),
+            }
+        }
+    }
+}
+
 impl<Inner> Stream for LimitedIncoming<Inner>
 where
     Inner: Stream<Error = IoError>,
@@ -313,20 +466,17 @@ where
         if !self.limit.check() {
             return Ok(Async::NotReady);
         }
-        self.inner
-            .poll()
-            .map(|a| {
-                a.map(|o| {
-                    o.map(|i| {
-                        self.limit.active_cnt.fetch_add(2, Ordering::AcqRel);
-                        LimitedConn {
-                            inner: i,
-                            limit: Arc::clone(&self.limit),
-                        }
-                    })
+        self.accept().map(|a| {
+            a.map(|o| {
+                o.map(|i| {
+                    self.limit.active_cnt.fetch_add(2, Ordering::AcqRel);
+                    LimitedConn {
+                        inner: i,
+                        limit: Arc::clone(&self.limit),
+                    }
                 })
             })
-            .map_err(|()| unreachable!("SleepOnError doesn't error, it sleeps"))
+        })
     }
 }
 
@@ -339,6 +489,15 @@ pub struct LimitedConn<Inner> {
     limit: Arc<ConnLimit>,
 }
 
+impl<Inner> LimitedConn<Inner> {
+    /// Number of currently active connections of the listener this connection came from.
+    ///
+    /// This includes this connection. It can be used as a metric of how loaded the listener is.
+    pub fn active_connections(&self) -> usize {
+        self.limit.active_cnt.load(Ordering::Relaxed) / 2
+    }
+}
+
 impl<Inner> Drop for LimitedConn<Inner> {
     fn drop(&mut self) {
         self.limit.dec()
@@ -389,6 +548,7 @@ mod tests {
     // tests
     use corona::coroutine::CleanupStrategy;
     use corona::prelude::*;
+    use futures::stream;
     use spirit::prelude::*;
     use tokio::clock;
     use tokio::net::TcpStream;
@@ -398,6 +558,67 @@ mod tests {
     use super::*;
     use crate::net::{Listen, TcpListen};
 
+    #[test]
+    fn backoff() {
+        let backoff = Backoff {
+            error_sleep: Duration::from_millis(100),
+            error_sleep_max: Duration::from_secs(1),
+            max_errors: None,
+        };
+        assert_eq!(Duration::from_millis(100), backoff.sleep(1));
+        assert_eq!(Duration::from_millis(200), backoff.sleep(2));
+        assert_eq!(Duration::from_millis(800), backoff.sleep(4));
+        assert_eq!(Duration::from_secs(1), backoff.sleep(5));
+        assert_eq!(Duration::from_secs(1), backoff.sleep(1000));
+        let constant = Backoff {
+            error_sleep_max: Duration::from_millis(100),
+            ..backoff
+        };
+        assert_eq!(Duration::from_millis(100), constant.sleep(10));
+    }
+
+    #[test]
+    fn max_conn_spelling() {
+        // The old spelling is still accepted, so the existing configurations keep their limit
+        for json in &[r#"{"max-conn": 2}"#, r#"{"max_conn": 2}"#] {
+            let limits: Limits = serde_json::from_str(json).unwrap();
+            assert_eq!(Some(2), limits.max_conn);
+        }
+    }
+
+    #[test]
+    fn give_up() {
+        let accepted: Vec<Result<(), IoError>> = vec![
+            Ok(()),
+            Err(ErrorKind::ConnectionReset.into()),
+            Ok(()),
+            Err(ErrorKind::Other.into()),
+        ];
+        let incoming = LimitedIncoming {
+            inner: stream::iter_result(accepted),
+            backoff: Backoff {
+                error_sleep: Duration::from_millis(100),
+                error_sleep_max: Duration::from_millis(100),
+                max_errors: Some(0),
+            },
+            errors: 0,
+            sleep: None,
+            limit: Arc::new(ConnLimit {
+                max_conn: 10,
+                active_cnt: AtomicUsize::new(0),
+                wakeup: AtomicTask::new(),
+            }),
+        };
+        let mut incoming = incoming.wait();
+        assert!(incoming.next().unwrap().is_ok());
+        // The connection error is just skipped
+        assert!(incoming.next().unwrap().is_ok());
+        assert_eq!(
+            ErrorKind::Other,
+            incoming.next().unwrap().err().unwrap().kind()
+        );
+    }
+
     #[test]
     fn conn_limit() {
         Coroutine::new()
@@ -416,6 +637,7 @@ mod tests {
                     limits: Limits {
                         error_sleep: Duration::from_millis(100),
                         max_conn: Some(2),
+                        ..Limits::default()
                     },
                 };
                 let mut seed = incoming_cfg.make_seed("test_listener").unwrap();
@@ -429,8 +651,9 @@ mod tests {
 
                 corona::spawn(move || {
                     let conn1 = incoming.coro_next().unwrap().unwrap();
-                    let _conn2 = incoming.coro_next().unwrap().unwrap();
+                    let conn2 = incoming.coro_next().unwrap().unwrap();
                     assert_eq!(4, incoming.limit.active_cnt.load(Ordering::Relaxed));
+                    assert_eq!(2, conn2.active_connections());
                     let maybe = incoming
                         .extractor()
                         .timeout(Duration::from_millis(50))
@@ -445,6 +668,7 @@ mod tests {
                     drop(conn1);
                     let _conn3 = incoming.coro_next().unwrap().unwrap();
                     assert_eq!(4, incoming.limit.active_cnt.load(Ordering::Relaxed));
+                    assert_eq!(2, conn2.active_connections());
                 });
                 // Two are the limit
                 let _conn1 = TcpStream::connect(&addr).coro_wait().unwrap();
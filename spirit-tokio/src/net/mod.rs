@@ -12,7 +12,7 @@
 use std::cmp;
 use std::fmt::Debug;
 use std::io::Error as IoError;
-use std::net::{IpAddr, TcpListener as StdTcpListener, UdpSocket as StdUdpSocket};
+use std::net::{IpAddr, SocketAddr, TcpListener as StdTcpListener, UdpSocket as StdUdpSocket};
 use std::time::Duration;
 
 use failure::{Error, ResultExt};
@@ -231,6 +231,17 @@ impl Listen {
         }
         Ok(builder.bind((self.host, self.port))?)
     }
+
+    /// The address to bind to, identifying the socket among its siblings.
+    ///
+    /// The port 0 lets the OS pick any free port, so such sockets have no stable identity.
+    fn identity(&self) -> Option<String> {
+        if self.port == 0 {
+            None
+        } else {
+            Some(SocketAddr::new(self.host, self.port).to_string())
+        }
+    }
 }
 
 /// Abstracts over a configuration subfragment that applies further settings to an already accepted
@@ -344,6 +355,11 @@ impl Serialize for MaybeDuration {
 /// * `tcp-send-buf-size` (similar, but for the send end)
 /// * `tcp-keepalive` (optional, see [`MaybeDuration`])
 /// * `accepted-ttl` (optional, uses OS default if not set)
+///
+/// The buffer sizes are only hints to the OS. It is free to adjust them ‒ for example Linux
+/// doubles the requested size (the other half is used for its bookkeeping) and caps it at the
+/// `net.core.rmem_max` or `net.core.wmem_max` sysctl, so reading the size back may give a
+/// different value.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
 #[cfg_attr(feature = "cfg-help", derive(StructDoc))]
 pub struct TcpConfig {
@@ -560,6 +576,9 @@ where
             .map_err(Error::from)
             .map(|listener| ConfiguredStreamListener::new(listener, config))
     }
+    fn identity(&self) -> Option<String> {
+        self.listen.identity()
+    }
 }
 
 /// A [`TcpListen`] with all parameters set to [`Empty`].
@@ -636,13 +655,20 @@ where
             .with_context(|_| format!("Failed to make socket {}/{:?} async", name, self))
             .map_err(Error::from)
     }
+    fn identity(&self) -> Option<String> {
+        self.listen.identity()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     extern crate serde_json;
 
+    use std::net::Ipv4Addr;
+
     use self::serde_json::error::Error as JsonError;
+    use spirit::fragment::driver::{Driver, Instruction};
+    use spirit::fragment::pipeline::NopTransformation;
 
     use super::*;
 
@@ -678,4 +704,74 @@ mod tests {
     fn maybe_duration_default() {
         assert_eq!(MaybeDuration::Unset, MaybeDuration::load(r#"{}"#).unwrap());
     }
+
+    fn listen(port: u16, extra_cfg: u8) -> TcpListen<u8> {
+        TcpListen {
+            listen: Listen {
+                port,
+                host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                ..Listen::default()
+            },
+            tcp_config: TcpConfig::default(),
+            extra_cfg,
+        }
+    }
+
+    fn free_port() -> u16 {
+        StdTcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    type Listeners = Vec<TcpListen<u8>>;
+
+    /// Performs an update and returns the ports of the installed listeners and number of drops.
+    fn update(
+        driver: &mut <Listeners as Fragment>::Driver,
+        listeners: &Listeners,
+    ) -> (Vec<u16>, usize) {
+        let instructions = driver
+            .instructions::<_, ()>(listeners, &mut NopTransformation, "test")
+            .unwrap();
+        Driver::<Listeners>::confirm(driver, "test");
+        let mut installed = Vec::new();
+        let mut dropped = 0;
+        for instruction in instructions {
+            match instruction {
+                Instruction::Install { resource, .. } => {
+                    let (listener, _) = resource.into_parts();
+                    installed.push(listener.local_addr().unwrap().port());
+                }
+                Instruction::DropSpecific(_) => dropped += 1,
+                Instruction::DropAll => unreachable!("Sequences drop specific resources"),
+            }
+        }
+        installed.sort();
+        (installed, dropped)
+    }
+
+    #[test]
+    fn reorder_keeps_listeners() {
+        assert_eq!(None, listen(0, 0).identity());
+        let ports = [free_port(), free_port(), free_port()];
+        let mut driver = <Listeners as Fragment>::Driver::default();
+        let mut listeners = vec![listen(ports[0], 0), listen(ports[1], 0)];
+        assert_eq!(
+            (vec![ports[0], ports[1]], 0),
+            update(&mut driver, &listeners)
+        );
+
+        listeners.reverse();
+        assert_eq!((Vec::new(), 0), update(&mut driver, &listeners));
+
+        // Adding one in front and changing a sibling doesn't touch the unchanged one. The changed
+        // one gets a new resource, but on the same socket.
+        listeners[0].extra_cfg = 1;
+        listeners.insert(0, listen(ports[2], 0));
+        let mut changed = vec![ports[1], ports[2]];
+        changed.sort();
+        assert_eq!((changed, 1), update(&mut driver, &listeners));
+    }
 }
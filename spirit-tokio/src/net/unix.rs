@@ -66,6 +66,11 @@ impl Listen {
     pub fn create_datagram(&self) -> Result<StdUnixDatagram, Error> {
         StdUnixDatagram::bind(&self.path).map_err(Error::from)
     }
+
+    /// The path identifies the socket among its siblings.
+    fn identity(&self) -> Option<String> {
+        Some(self.path.display().to_string())
+    }
 }
 
 /// Additional configuration for unix domain stream sockets.
@@ -156,6 +161,9 @@ where
             .map_err(Error::from)
             .map(|listener| ConfiguredStreamListener::new(listener, config))
     }
+    fn identity(&self) -> Option<String> {
+        self.listen.identity()
+    }
 }
 
 /// Type alias for [`UnixListen`] without any unnecessary configuration options.
@@ -228,4 +236,7 @@ where
             })
             .map_err(Error::from)
     }
+    fn identity(&self) -> Option<String> {
+        self.listen.identity()
+    }
 }
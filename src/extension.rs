@@ -223,6 +223,61 @@ pub trait Extensible: Sized {
     where
         F: FnMut(&Self::Opts, &Arc<Self::Config>) + Send + 'static;
 
+    /// Adds a callback informed about the outcome of each configuration reload.
+    ///
+    /// The callback is called after every attempt to load the configuration (including the
+    /// initial one), whatever triggered it. It gets `Ok` if the new configuration was accepted
+    /// and the error if it failed to load or any of the [validators](#method.config_validator)
+    /// refused it (in which case the old configuration stays in use).
+    ///
+    /// The errors are logged anyway, this is for the application to react to them
+    /// programmatically ‒ for example to report them through a health endpoint.
+    ///
+    /// If registered on an already started [`Spirit`][crate::Spirit], it is called on the next
+    /// reload. It is dropped if it was already terminated.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// use spirit::prelude::*;
+    ///
+    /// let healthy = Arc::new(AtomicBool::new(false));
+    /// let healthy_hook = Arc::clone(&healthy);
+    /// let app = Spirit::<Empty, Empty>::new()
+    ///     .on_reload(move |result| healthy_hook.store(result.is_ok(), Ordering::Relaxed))
+    ///     .build(false)
+    ///     .unwrap();
+    /// assert!(healthy.load(Ordering::Relaxed));
+    /// # let refuse = Arc::new(AtomicBool::new(false));
+    /// # let refuse_validator = Arc::clone(&refuse);
+    /// # let spirit = app.spirit();
+    /// # spirit
+    /// #     .config_validator(move |_, _, _| {
+    /// #         if refuse_validator.load(Ordering::Relaxed) {
+    /// #             Err(failure::err_msg("Refused"))
+    /// #         } else {
+    /// #             Ok(spirit::validation::Action::new())
+    /// #         }
+    /// #     })
+    /// #     .unwrap();
+    /// # refuse.store(true, Ordering::Relaxed);
+    /// # spirit.config_reload().unwrap_err();
+    /// # assert!(!healthy.load(Ordering::Relaxed));
+    /// ```
+    ///
+    /// The default implementation (for implementors that predate this method) is built on top of
+    /// [`on_config`][Extensible::on_config], therefore it reports only the accepted
+    /// configurations. The implementations in this crate report the failures too.
+    fn on_reload<F>(self, mut hook: F) -> Self
+    where
+        F: FnMut(&Result<(), Error>) + Send + 'static,
+    {
+        self.on_config(move |_, _| hook(&Ok(())))
+    }
+
     /// Adds a callback for reacting to a signal.
     ///
     /// The [`Spirit`][crate::Spirit] reacts to some signals itself, in its own service
@@ -450,6 +505,13 @@ where
         self.map(|c| c.on_config(hook))
     }
 
+    fn on_reload<F>(self, hook: F) -> Self
+    where
+        F: FnMut(&Result<(), Error>) + Send + 'static,
+    {
+        self.map(|c| c.on_reload(hook))
+    }
+
     fn on_signal<F>(self, signal: libc::c_int, hook: F) -> Result<<Self as Extensible>::Ok, Error>
     where
         F: FnMut() + Send + 'static,
@@ -6,10 +6,12 @@
 //! [`Driver`]: crate::fragment::driver::Driver
 
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
 use std::iter;
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::Deref;
 
 use either::Either;
 use failure::Error;
@@ -25,20 +27,37 @@ use super::{Fragment, Transformation};
 /// removal. The [`CacheId`] is used for that. That is an opaque type, so the [`Driver`] can either
 /// create a default (non-unique) one or use this generator to create unique IDs.
 ///
-/// Note that a generator will never produce two equal IDs, but two different generators can both
-/// produce the same one. The [`Driver`] must take care not to use IDs from two different
-/// generators.
+/// Note that a generator will never produce an ID that is still in use (one that was produced and
+/// not [`release`][IdGen::release]d yet), but two different generators can both produce the same
+/// one. The [`Driver`] must take care not to use IDs from two different generators.
 ///
-/// The IDs are produced using the [`Iterator`] trait.
+/// The IDs are produced using the [`Iterator`] trait. The released ones are reused first, so the
+/// IDs stay small and the structures indexed by them don't grow in long-running applications
+/// with many configuration reloads.
 ///
 /// The generator implements a very small set of traits. In particular, it isn't [`Clone`]. This is
 /// on purpose, because cloning the generators could lead to surprising behaviours.
 #[derive(Debug)]
-pub struct IdGen(u128);
+pub struct IdGen {
+    next: u128,
+    free: Vec<CacheId>,
+}
 
 impl IdGen {
     fn new() -> Self {
-        IdGen(1)
+        IdGen {
+            next: 1,
+            free: Vec::new(),
+        }
+    }
+
+    /// Returns no longer used IDs to the generator, for reuse.
+    ///
+    /// It is up to the caller to make sure the resources identified by them are gone for good (eg.
+    /// the instructions to drop them were [`confirm`][Driver::confirm]ed). Releasing an ID that is
+    /// still in use leads to duplicate IDs.
+    pub fn release<I: IntoIterator<Item = CacheId>>(&mut self, ids: I) {
+        self.free.extend(ids);
     }
 }
 
@@ -51,9 +70,12 @@ impl Default for IdGen {
 impl Iterator for IdGen {
     type Item = CacheId;
     fn next(&mut self) -> Option<CacheId> {
-        let id = self.0;
-        self.0 = self
-            .0
+        if let Some(id) = self.free.pop() {
+            return Some(id);
+        }
+        let id = self.next;
+        self.next = self
+            .next
             .checked_add(1)
             .expect("WTF? Run out of 128bit cache IDs!?");
         Some(CacheId(id))
@@ -378,7 +400,12 @@ where
     where
         T: Transformation<F::Resource, I, F>,
     {
-        assert!(!self.proposition.active(), "Unclosed transaction");
+        if self.proposition.active() {
+            // Something panicked after the previous update was prepared, so nobody confirmed or
+            // aborted it. Return to the state before it.
+            warn!("Previous update of {} didn't finish, rolling it back", name);
+            self.abort(name);
+        }
 
         match self.compare(fragment) {
             Comparison::Dissimilar => {
@@ -481,7 +508,11 @@ where
     where
         T: Transformation<F::Resource, I, F>,
     {
-        assert!(self.proposition.is_none(), "Unclosed transaction");
+        if self.proposition.is_some() {
+            // The transformation panicked last time, so nobody confirmed or aborted it.
+            warn!("Previous update of {} didn't finish, rolling it back", name);
+            self.proposition.take();
+        }
         // maybe_cached means *is* cached for us
         if self.maybe_cached(fragment, name) {
             trace!(
@@ -529,15 +560,18 @@ where
 /// It is expected to be used as:
 /// * One mapping per slave driver, but sharing the [`IdGen`].
 /// * All instructions from the slave are [`translate`]d through the mapping.
-/// * The mapping is only stored and cached if [`confirm`] is called.
+/// * The mapping is only stored and cached if [`confirm`] is called. At that point, the IDs of the
+///   dropped resources can be returned to the generator by [`release_dropped`].
 ///
 /// TODO Example
 ///
 /// [`translate`]: IdMapping::translate
 /// [`confirm`]: Driver::confirm
+/// [`release_dropped`]: IdMapping::release_dropped
 #[derive(Clone, Debug, Default)]
 pub struct IdMapping {
     mapping: HashMap<CacheId, CacheId>,
+    dropped: Vec<CacheId>,
 }
 
 impl IdMapping {
@@ -572,21 +606,23 @@ impl IdMapping {
                 Instruction::DropAll => {
                     let mut mapping = HashMap::new();
                     mem::swap(&mut mapping, &mut self.mapping);
-                    Either::Left(
-                        mapping
-                            .into_iter()
-                            .map(|(_, outer_id)| Instruction::DropSpecific(outer_id)),
-                    )
+                    trace!("Expanding drop of all into {} drops", mapping.len());
+                    let dropped = mapping.into_values().collect::<Vec<_>>();
+                    self.dropped.extend(dropped.iter().cloned());
+                    Either::Left(dropped.into_iter().map(Instruction::DropSpecific))
                 }
                 Instruction::DropSpecific(id) => {
                     let id = self
                         .mapping
                         .remove(&id)
                         .expect("Inconsistent use of cache: missing ID to remove");
+                    trace!("Dropping {:?}", id);
+                    self.dropped.push(id);
                     Either::Right(iter::once(Instruction::DropSpecific(id)))
                 }
                 Instruction::Install { id, resource } => {
                     let new_id = id_gen.next().expect("Run out of cache IDs? Impossible");
+                    trace!("Installing {:?} as {:?}", id, new_id);
                     assert!(
                         self.mapping.insert(id, new_id).is_none(),
                         "Duplicate ID created"
@@ -606,6 +642,14 @@ impl IdMapping {
     pub fn active_target_ids(&self) -> impl Iterator<Item = &CacheId> {
         self.mapping.values()
     }
+
+    /// Returns the (translated) IDs dropped by the instructions so far to the generator.
+    ///
+    /// This must be called only once the instructions are confirmed, otherwise the IDs could be
+    /// handed out again while the resources still exist.
+    pub fn release_dropped(&mut self, id_gen: &mut IdGen) {
+        id_gen.release(self.dropped.drain(..));
+    }
 }
 
 #[derive(Debug, Default)]
@@ -613,6 +657,7 @@ struct ItemDriver<Driver> {
     driver: Driver,
     id_mapping: IdMapping,
     proposed_mapping: Option<IdMapping>,
+    identity: Option<String>,
     used: bool,
     new: bool,
 }
@@ -621,6 +666,10 @@ struct ItemDriver<Driver> {
 ///
 /// This driver is used to go from single [`Fragment`] to a sequence ‒ this is driver for things
 /// like `Vec<F>` or `HashSet<F>`.
+///
+/// The instances are matched with the previous ones either by their
+/// [`identity`][Fragment::identity], if they have one, or by what their drivers consider
+/// [cached][Driver::maybe_cached].
 #[derive(Debug)]
 pub struct SeqDriver<Item, SlaveDriver> {
     id_gen: IdGen,
@@ -660,29 +709,45 @@ where
     where
         T: Transformation<<Self::SubFragment as Fragment>::Resource, Ins, Self::SubFragment>,
     {
-        assert!(!self.transaction_open);
+        if self.transaction_open {
+            // Something (likely a transformation) panicked in the middle of the previous update,
+            // so nobody confirmed or aborted it. Return to the state before it.
+            warn!("Previous update of {} didn't finish, rolling it back", name);
+            self.abort(name);
+        }
         trace!("Updating sequence {}", name);
         self.transaction_open = true;
         let mut instructions = Vec::new();
         let mut errors = Vec::new();
+        let mut cached = 0;
+        let mut created = 0;
 
         for sub in fragment {
-            let existing = self
-                .sub_drivers
-                .iter_mut()
-                .find(|d| !d.used && d.driver.maybe_cached(sub, name));
+            let identity = sub.identity();
+            let existing = self.sub_drivers.iter_mut().find(|d| {
+                !d.used
+                    && match (&identity, &d.identity) {
+                        // Identified instances match by the identity only
+                        (Some(new), Some(old)) => new == old,
+                        (None, None) => d.driver.maybe_cached(sub, name),
+                        _ => false,
+                    }
+            });
             // unwrap_or_else angers the borrow checker here
             let slot = if let Some(existing) = existing {
                 trace!("Found existing version of instance in {}", name);
+                cached += 1;
                 existing
             } else {
                 trace!(
                     "Previous version of instance in {} not found, creating a new one",
                     name
                 );
+                created += 1;
                 self.sub_drivers.push(ItemDriver::default());
                 let slot = self.sub_drivers.last_mut().unwrap();
                 slot.new = true;
+                slot.identity = identity;
                 slot
             };
 
@@ -701,8 +766,10 @@ where
             }
         }
 
+        let mut removed = 0;
         for slot in &self.sub_drivers {
             if !slot.used {
+                removed += 1;
                 instructions.extend(
                     slot.id_mapping
                         .active_target_ids()
@@ -711,6 +778,13 @@ where
                 );
             }
         }
+        trace!(
+            "Sequence {} has {} instances matching the cache, {} new and {} removed",
+            name,
+            cached,
+            created,
+            removed,
+        );
 
         if errors.is_empty() {
             Ok(instructions)
@@ -720,10 +794,19 @@ where
         }
     }
     fn confirm(&mut self, name: &'static str) {
-        trace!("Confirming the whole sequence {}", name);
+        trace!(
+            "Confirming the whole sequence {} ({} of {} instances in use)",
+            name,
+            self.sub_drivers.iter().filter(|s| s.used).count(),
+            self.sub_drivers.len(),
+        );
         assert!(self.transaction_open);
         self.transaction_open = false;
-        // Get rid of the unused ones
+        // Get rid of the unused ones (their resources are dropped now, so the IDs are free)
+        for sub in self.sub_drivers.iter().filter(|s| !s.used) {
+            self.id_gen
+                .release(sub.id_mapping.active_target_ids().cloned());
+        }
         self.sub_drivers.retain(|s| s.used);
         // Confirm all the used ones, accept proposed mappings and mark everything as old for next
         // round.
@@ -732,12 +815,17 @@ where
             if let Some(mapping) = sub.proposed_mapping.take() {
                 sub.id_mapping = mapping;
             }
+            sub.id_mapping.release_dropped(&mut self.id_gen);
             sub.new = false;
             sub.used = false;
         }
     }
     fn abort(&mut self, name: &'static str) {
-        trace!("Aborting the whole sequence of {}", name);
+        trace!(
+            "Aborting the whole sequence of {} (discarding {} new instances)",
+            name,
+            self.sub_drivers.iter().filter(|s| s.new).count(),
+        );
         assert!(self.transaction_open);
         self.transaction_open = false;
         // Get rid of the new ones completely
@@ -764,6 +852,161 @@ where
     }
 }
 
+/// A plumbing [`Driver`] for maps of fragments.
+///
+/// This is the driver for things like `HashMap<K, F>`. Unlike the [`SeqDriver`], the instances
+/// are matched by their keys, not by their content. Therefore, an entry keeps its resource (or at
+/// least has a chance to reuse its cache) across reloads, as long as it keeps its key ‒ no matter
+/// what else changes in the map.
+///
+/// The name passed to the slave drivers contains the key too (eg. `listen[api]`), so log messages
+/// and errors identify the entry. Note that these names are allocated once for each key ever seen
+/// and never freed.
+#[derive(Debug)]
+pub struct MapDriver<Key, Item, SlaveDriver> {
+    id_gen: IdGen,
+    sub_drivers: HashMap<Key, (&'static str, ItemDriver<SlaveDriver>)>,
+    transaction_open: bool,
+    _item: PhantomData<fn(&Item)>,
+}
+
+// The derived Default balks on Item: !Default, but we *don't* need that
+impl<Key, Item, SlaveDriver> Default for MapDriver<Key, Item, SlaveDriver> {
+    fn default() -> Self {
+        Self {
+            id_gen: IdGen::new(),
+            sub_drivers: HashMap::new(),
+            transaction_open: false,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<F, K, I, SlaveDriver> Driver<F> for MapDriver<K, I, SlaveDriver>
+where
+    F: Fragment,
+    K: Clone + Display + Eq + Hash + 'static,
+    I: Fragment,
+    for<'a> &'a F: IntoIterator<Item = (&'a K, &'a I)>,
+    SlaveDriver: Driver<I> + Default,
+{
+    type SubFragment = SlaveDriver::SubFragment;
+    fn instructions<T, Ins>(
+        &mut self,
+        fragment: &F,
+        transform: &mut T,
+        name: &'static str,
+    ) -> Result<Vec<Instruction<T::OutputResource>>, Vec<Error>>
+    where
+        T: Transformation<<Self::SubFragment as Fragment>::Resource, Ins, Self::SubFragment>,
+    {
+        if self.transaction_open {
+            // Something (likely a transformation) panicked in the middle of the previous update,
+            // so nobody confirmed or aborted it. Return to the state before it.
+            warn!("Previous update of {} didn't finish, rolling it back", name);
+            self.abort(name);
+        }
+        trace!("Updating map {}", name);
+        self.transaction_open = true;
+        let mut instructions = Vec::new();
+        let mut errors = Vec::new();
+
+        for (key, sub) in fragment {
+            let (sub_name, slot) = self.sub_drivers.entry(key.clone()).or_insert_with(|| {
+                trace!("New entry {} in {}", key, name);
+                let sub_name = crate::utils::intern(format!("{}[{}]", name, key));
+                let slot = ItemDriver {
+                    new: true,
+                    ..ItemDriver::default()
+                };
+                (sub_name, slot)
+            });
+
+            slot.used = true;
+            match slot.driver.instructions(sub, transform, sub_name) {
+                Ok(new_instructions) => {
+                    let mapping = if slot.new {
+                        &mut slot.id_mapping
+                    } else {
+                        slot.proposed_mapping = Some(slot.id_mapping.clone());
+                        slot.proposed_mapping.as_mut().unwrap()
+                    };
+                    instructions.extend(mapping.translate(&mut self.id_gen, new_instructions));
+                }
+                Err(errs) => errors.extend(errs),
+            }
+        }
+
+        for (key, (_, slot)) in &self.sub_drivers {
+            if !slot.used {
+                trace!("Entry {} removed from {}", key, name);
+                instructions.extend(
+                    slot.id_mapping
+                        .active_target_ids()
+                        .cloned()
+                        .map(Instruction::DropSpecific),
+                );
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(instructions)
+        } else {
+            self.abort(name);
+            Err(errors)
+        }
+    }
+    fn confirm(&mut self, name: &'static str) {
+        trace!("Confirming the whole map {}", name);
+        assert!(self.transaction_open);
+        self.transaction_open = false;
+        // Get rid of the unused ones (their resources are dropped now, so the IDs are free)
+        for (_, sub) in self.sub_drivers.values().filter(|(_, s)| !s.used) {
+            self.id_gen
+                .release(sub.id_mapping.active_target_ids().cloned());
+        }
+        self.sub_drivers.retain(|_, (_, s)| s.used);
+        // Confirm all the used ones, accept proposed mappings and mark everything as old for next
+        // round.
+        for (sub_name, sub) in self.sub_drivers.values_mut() {
+            sub.driver.confirm(sub_name);
+            if let Some(mapping) = sub.proposed_mapping.take() {
+                sub.id_mapping = mapping;
+            }
+            sub.id_mapping.release_dropped(&mut self.id_gen);
+            sub.new = false;
+            sub.used = false;
+        }
+    }
+    fn abort(&mut self, name: &'static str) {
+        trace!("Aborting the whole map {}", name);
+        assert!(self.transaction_open);
+        self.transaction_open = false;
+        // Get rid of the new ones completely
+        self.sub_drivers.retain(|_, (_, s)| !s.new);
+        // Abort anything we touched before
+        for (sub_name, sub) in self.sub_drivers.values_mut() {
+            if sub.used {
+                sub.driver.abort(sub_name);
+                sub.proposed_mapping.take();
+                sub.used = false;
+            }
+            assert!(
+                sub.proposed_mapping.is_none(),
+                "Proposed mapping for something not used"
+            );
+        }
+    }
+    fn maybe_cached(&self, fragment: &F, _name: &'static str) -> bool {
+        fragment.into_iter().any(|(key, sub)| {
+            self.sub_drivers
+                .get(key)
+                .map(|(sub_name, slave)| slave.driver.maybe_cached(sub, sub_name))
+                .unwrap_or(false)
+        })
+    }
+}
+
 /// A [`Driver`] for a single-shot initialization.
 ///
 /// This driver creates the resource only the first time it is called. On an attempt to call it
@@ -837,9 +1080,10 @@ where
     }
 }
 
-/// An adaptor [`Driver`] for references.
+/// An adaptor [`Driver`] for references and smart pointers.
 ///
-/// This is used behind the scenes to wrap a driver for `F` to create a driver for `&F`.
+/// This is used behind the scenes to wrap a driver for `F` to create a driver for `&F`, `Box<F>`,
+/// `Arc<F>` or `Rc<F>`. The inner driver works on the pointed-to fragment.
 #[derive(Debug, Default)]
 pub struct RefDriver<Inner>(Inner);
 
@@ -853,18 +1097,23 @@ impl<Inner> RefDriver<Inner> {
     }
 }
 
-impl<'a, F: Fragment, Inner: Driver<F>> Driver<&'a F> for RefDriver<Inner> {
+impl<P, F, Inner> Driver<P> for RefDriver<Inner>
+where
+    P: Fragment + Deref<Target = F>,
+    F: Fragment,
+    Inner: Driver<F>,
+{
     type SubFragment = Inner::SubFragment;
     fn instructions<T, I>(
         &mut self,
-        fragment: &&F,
+        fragment: &P,
         transform: &mut T,
         name: &'static str,
     ) -> Result<Vec<Instruction<T::OutputResource>>, Vec<Error>>
     where
         T: Transformation<<Self::SubFragment as Fragment>::Resource, I, Self::SubFragment>,
     {
-        self.0.instructions(*fragment, transform, name)
+        self.0.instructions(&**fragment, transform, name)
     }
     fn confirm(&mut self, name: &'static str) {
         self.0.confirm(name);
@@ -872,7 +1121,345 @@ impl<'a, F: Fragment, Inner: Driver<F>> Driver<&'a F> for RefDriver<Inner> {
     fn abort(&mut self, name: &'static str) {
         self.0.abort(name);
     }
-    fn maybe_cached(&self, fragment: &&F, name: &'static str) -> bool {
-        self.0.maybe_cached(*fragment, name)
+    fn maybe_cached(&self, fragment: &P, name: &'static str) -> bool {
+        self.0.maybe_cached(&**fragment, name)
+    }
+}
+
+/// A [`Driver`] adaptor that keeps the resources if a key derived from the fragment stays the same.
+///
+/// The inner driver is consulted only if the key changes. This allows ignoring changes of parts
+/// of the fragment that don't influence the resource (for example a comment or description), so
+/// the resource is not recreated because of them.
+///
+/// Usually created through [`Pipeline::compare_by`][crate::Pipeline::compare_by].
+pub struct CacheBy<Inner, Key, KeyFn> {
+    inner: Inner,
+    key: KeyFn,
+    previous: Option<Key>,
+    proposition: Option<Key>,
+    delegated: bool,
+}
+
+impl<Inner, Key, KeyFn> CacheBy<Inner, Key, KeyFn> {
+    /// Creates the driver, wrapping the inner one and comparing the keys extracted by `key`.
+    pub fn new(inner: Inner, key: KeyFn) -> Self {
+        CacheBy {
+            inner,
+            key,
+            previous: None,
+            proposition: None,
+            delegated: false,
+        }
+    }
+}
+
+impl<F, Inner, Key, KeyFn> Driver<F> for CacheBy<Inner, Key, KeyFn>
+where
+    F: Fragment,
+    Inner: Driver<F>,
+    Key: PartialEq,
+    KeyFn: Fn(&F) -> Key,
+{
+    type SubFragment = Inner::SubFragment;
+    fn instructions<T, I>(
+        &mut self,
+        fragment: &F,
+        transform: &mut T,
+        name: &'static str,
+    ) -> Result<Vec<Instruction<T::OutputResource>>, Vec<Error>>
+    where
+        T: Transformation<<Self::SubFragment as Fragment>::Resource, I, Self::SubFragment>,
+    {
+        if self.proposition.is_some() || self.delegated {
+            // The transformation panicked last time, so nobody confirmed or aborted it.
+            warn!("Previous update of {} didn't finish, rolling it back", name);
+            self.proposition.take();
+            self.delegated = false;
+        }
+        let key = (self.key)(fragment);
+        if self.previous.as_ref() == Some(&key) {
+            trace!("The key of {} stays the same, keeping previous", name);
+            return Ok(Vec::new());
+        }
+        let instructions = self.inner.instructions(fragment, transform, name)?;
+        self.proposition = Some(key);
+        self.delegated = true;
+        Ok(instructions)
+    }
+    fn confirm(&mut self, name: &'static str) {
+        if mem::replace(&mut self.delegated, false) {
+            self.inner.confirm(name);
+        }
+        if let Some(proposition) = self.proposition.take() {
+            self.previous = Some(proposition);
+        }
+    }
+    fn abort(&mut self, name: &'static str) {
+        if mem::replace(&mut self.delegated, false) {
+            self.inner.abort(name);
+        }
+        self.proposition.take();
+    }
+    fn maybe_cached(&self, fragment: &F, name: &'static str) -> bool {
+        self.previous.as_ref() == Some(&(self.key)(fragment))
+            || self.inner.maybe_cached(fragment, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::fragment::pipeline::NopTransformation;
+    use crate::fragment::Stackable;
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Num(u8);
+
+    impl Stackable for Num {}
+
+    impl Fragment for Num {
+        type Driver = CacheEq<Num>;
+        type Installer = ();
+        type Seed = ();
+        type Resource = u8;
+        fn make_seed(&self, _: &'static str) -> Result<(), Error> {
+            Ok(())
+        }
+        fn make_resource(&self, _: &mut (), _: &'static str) -> Result<u8, Error> {
+            Ok(self.0)
+        }
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Named(&'static str, u8);
+
+    impl Stackable for Named {}
+
+    impl Fragment for Named {
+        type Driver = CacheEq<Named>;
+        type Installer = ();
+        type Seed = ();
+        type Resource = u8;
+        fn make_seed(&self, _: &'static str) -> Result<(), Error> {
+            Ok(())
+        }
+        fn make_resource(&self, _: &mut (), _: &'static str) -> Result<u8, Error> {
+            Ok(self.1)
+        }
+        fn identity(&self) -> Option<String> {
+            Some(self.0.to_owned())
+        }
+    }
+
+    type Map = HashMap<&'static str, Num>;
+
+    fn update<F, S>(driver: &mut F::Driver, fragment: &F) -> Vec<Instruction<u8>>
+    where
+        F: Fragment,
+        F::Driver: Driver<F, SubFragment = S>,
+        S: Fragment<Resource = u8>,
+    {
+        let instructions = driver
+            .instructions::<_, ()>(fragment, &mut NopTransformation, "test")
+            .unwrap();
+        driver.confirm("test");
+        instructions
+    }
+
+    fn installs(instructions: &[Instruction<u8>]) -> Vec<u8> {
+        let mut installed = instructions
+            .iter()
+            .filter_map(|i| match i {
+                Instruction::Install { resource, .. } => Some(*resource),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        installed.sort();
+        installed
+    }
+
+    fn drops(instructions: &[Instruction<u8>]) -> usize {
+        instructions
+            .iter()
+            .filter(|i| match i {
+                Instruction::DropSpecific(_) => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn cache_by_key() {
+        fn step<D: Driver<Named>>(driver: &mut D, fragment: &Named, confirm: bool) -> Vec<u8>
+        where
+            D::SubFragment: Fragment<Resource = u8>,
+        {
+            let instructions = driver
+                .instructions::<_, ()>(fragment, &mut NopTransformation, "test")
+                .unwrap();
+            if confirm {
+                driver.confirm("test");
+            } else {
+                driver.abort("test");
+            }
+            installs(&instructions)
+        }
+
+        let mut driver = CacheBy::new(CacheEq::<Named>::default(), |n: &Named| n.1);
+        assert_eq!(vec![1], step(&mut driver, &Named("a", 1), true));
+        assert!(driver.maybe_cached(&Named("b", 1), "test"));
+        // Only the name changed, which is not part of the key
+        assert!(step(&mut driver, &Named("b", 1), true).is_empty());
+        assert_eq!(vec![2], step(&mut driver, &Named("b", 2), true));
+        // An aborted update doesn't change the cache
+        assert_eq!(vec![3], step(&mut driver, &Named("b", 3), false));
+        assert!(step(&mut driver, &Named("c", 2), true).is_empty());
+    }
+
+    impl Comparable for Num {
+        fn compare(&self, other: &Num) -> Comparison {
+            if self == other {
+                Comparison::Same
+            } else {
+                Comparison::Dissimilar
+            }
+        }
+    }
+
+    #[test]
+    fn similar_recovers_unclosed() {
+        let mut driver = CacheSimilar::<Num>::default();
+        let mut step = |num, confirm| {
+            let instructions = driver
+                .instructions::<_, ()>(&Num(num), &mut NopTransformation, "test")
+                .unwrap();
+            if confirm {
+                driver.confirm("test");
+            }
+            installs(&instructions)
+        };
+        // Left open, as if the pipeline panicked
+        assert_eq!(vec![1], step(1, false));
+        assert_eq!(vec![2], step(2, true));
+        assert!(step(2, true).is_empty());
+    }
+
+    #[test]
+    fn map_keeps_entries_by_key() {
+        let mut driver = <Map as Fragment>::Driver::default();
+        let mut map = Map::new();
+        map.insert("a", Num(1));
+        map.insert("b", Num(2));
+        let instructions = update::<Map, _>(&mut driver, &map);
+        assert_eq!(vec![1, 2], installs(&instructions));
+
+        // Nothing changed, nothing happens
+        assert!(update::<Map, _>(&mut driver, &map).is_empty());
+
+        // Changing one entry replaces only that one
+        map.insert("b", Num(3));
+        map.insert("c", Num(1));
+        let instructions = update::<Map, _>(&mut driver, &map);
+        assert_eq!(vec![1, 3], installs(&instructions));
+        assert_eq!(1, drops(&instructions));
+
+        // Removing an entry drops its resource
+        map.remove("a");
+        let instructions = update::<Map, _>(&mut driver, &map);
+        assert!(installs(&instructions).is_empty());
+        assert_eq!(1, drops(&instructions));
+    }
+
+    #[test]
+    fn seq_matches_by_identity() {
+        let mut driver = <Vec<Named> as Fragment>::Driver::default();
+        let mut seq = vec![Named("a", 1), Named("b", 2)];
+        assert_eq!(vec![1, 2], installs(&update(&mut driver, &seq)));
+
+        // Adding one in front and changing a sibling doesn't touch the unchanged one
+        seq[1].1 = 3;
+        seq.insert(0, Named("c", 4));
+        let instructions = update(&mut driver, &seq);
+        assert_eq!(vec![3, 4], installs(&instructions));
+        assert_eq!(1, drops(&instructions));
+    }
+
+    #[test]
+    fn ids_recycled() {
+        let mut driver = <Vec<Num> as Fragment>::Driver::default();
+        let mut live = Vec::new();
+        for i in 1..100 {
+            let instructions = update(&mut driver, &vec![Num(i), Num(0)]);
+            for instruction in &instructions {
+                match instruction {
+                    Instruction::DropSpecific(id) => live.retain(|l| l != id),
+                    Instruction::Install { id, .. } => {
+                        // Never collides with something still alive (the drops in the same batch
+                        // happen only after the installs)
+                        assert!(!live.contains(id));
+                        live.push(*id);
+                    }
+                    Instruction::DropAll => unreachable!("Expanded by the mapping"),
+                }
+            }
+            // Only the one replaced and the one kept
+            assert_eq!(2, live.len());
+            assert!(live.iter().all(|id| id.0 <= 3));
+            for slot in &driver.sub_drivers {
+                assert_eq!(1, slot.id_mapping.mapping.len());
+                assert!(slot.id_mapping.dropped.is_empty());
+            }
+        }
+    }
+
+    /// Panics when transforming the 42.
+    struct Panicking;
+
+    impl<I> Transformation<u8, I, Num> for Panicking {
+        type OutputResource = u8;
+        type OutputInstaller = I;
+        fn installer(&mut self, installer: I, _: &str) -> I {
+            installer
+        }
+        fn transform(&mut self, resource: u8, _: &Num, _: &str) -> Result<u8, Error> {
+            assert_ne!(42, resource, "Panicking on 42");
+            Ok(resource)
+        }
+    }
+
+    #[test]
+    fn recovers_from_panic() {
+        let mut driver = <Vec<Num> as Fragment>::Driver::default();
+        let instructions = driver
+            .instructions::<_, ()>(&vec![Num(1)], &mut Panicking, "test")
+            .unwrap();
+        Driver::<Vec<Num>>::confirm(&mut driver, "test");
+        assert_eq!(vec![1], installs(&instructions));
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            driver.instructions::<_, ()>(&vec![Num(1), Num(2), Num(42)], &mut Panicking, "test")
+        }));
+        assert!(panicked.is_err());
+
+        // Rolled back to the state before the failed update, so only the 2 is new
+        let instructions = driver
+            .instructions::<_, ()>(&vec![Num(1), Num(2)], &mut Panicking, "test")
+            .unwrap();
+        Driver::<Vec<Num>>::confirm(&mut driver, "test");
+        assert_eq!(vec![2], installs(&instructions));
+        assert_eq!(0, drops(&instructions));
+    }
+
+    #[test]
+    fn behind_pointers() {
+        let mut driver = <Vec<Arc<Num>> as Fragment>::Driver::default();
+        let seq = vec![Arc::new(Num(1)), Arc::new(Num(2))];
+        assert_eq!(vec![1, 2], installs(&update(&mut driver, &seq)));
+        assert!(update(&mut driver, &seq).is_empty());
     }
 }
@@ -94,19 +94,26 @@
 //! [`Stackable`]: crate::fragment::Stackable
 //! [`Comparable`]: crate::fragment::driver::Comparable
 //! [`spirit_tokio`]: https://docs.rs/spirit-tokio
-use std::collections::{BTreeSet, BinaryHeap, HashSet, LinkedList};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, LinkedList};
+use std::fmt::Display;
 use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
 
-use failure::Error;
+use failure::{Error, ResultExt};
 use log::trace;
 use serde::de::DeserializeOwned;
 use structopt::StructOpt;
 
-use self::driver::{Driver, RefDriver, SeqDriver};
+use self::driver::{Driver, MapDriver, RefDriver, SeqDriver};
 use crate::extension::Extensible;
 
 pub mod driver;
 pub mod pipeline;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 
 /// An entity that is able to install a resource.
 ///
@@ -116,7 +123,11 @@ pub mod pipeline;
 /// An installer can be even a storage provided by a user where the resource is stored ‒ eg. a
 /// proxy object to the resource where it can be switched.
 ///
-/// Note that installation of the resource must not fail.
+/// Note that installation of the resource must not fail. Installers that can fail may implement
+/// the [`try_install`][Installer::try_install] method instead. In such case the [`Pipeline`] rolls
+/// back the whole batch of changes done during the same configuration reload.
+///
+/// [`Pipeline`]: crate::fragment::pipeline::Pipeline
 pub trait Installer<Resource, O, C> {
     /// A handle representing lifetime of the resource.
     ///
@@ -147,6 +158,28 @@ pub trait Installer<Resource, O, C> {
     /// [`UninstallHandle`][Installer::UninstallHandle].
     fn install(&mut self, resource: Resource, name: &'static str) -> Self::UninstallHandle;
 
+    /// Installs another instance of the resource, with the possibility to fail.
+    ///
+    /// This is what the [`Pipeline`] calls. The default implementation calls
+    /// [`install`][Installer::install] and never fails; installers that can fail may override it
+    /// (and make the [`install`][Installer::install] panic on failure).
+    ///
+    /// If the installation fails (or panics), the [`Pipeline`] logs the error and rolls back the
+    /// other install instructions of the same batch ‒ the newly installed resources are
+    /// uninstalled by dropping their handles and the ones that were to be removed are kept. This
+    /// makes a configuration reload all-or-nothing for the pipeline. Note that installers replacing
+    /// a single instance (with `()` as the handle) can't restore the previous instance, therefore
+    /// they should fail before replacing it.
+    ///
+    /// [`Pipeline`]: crate::fragment::pipeline::Pipeline
+    fn try_install(
+        &mut self,
+        resource: Resource,
+        name: &'static str,
+    ) -> Result<Self::UninstallHandle, Error> {
+        Ok(self.install(resource, name))
+    }
+
     /// Initialize the installer.
     ///
     /// The pipeline will run this method exactly once, upon being inserted into a
@@ -170,12 +203,56 @@ pub trait Installer<Resource, O, C> {
     }
 }
 
+/// A collection of [`UninstallHandle`][Installer::UninstallHandle]s, dropped in reverse order.
+///
+/// A `Vec` drops its elements from the first one. But resources installed later may depend on the
+/// earlier ones (eg. a listener using a shared pool), so tearing them down the other way around
+/// could sever the dependency too early. Installers that hand out multiple handles can use this
+/// to uninstall the last installed resource first.
+#[derive(Debug)]
+pub struct ReverseDrop<H>(Vec<H>);
+
+impl<H> Default for ReverseDrop<H> {
+    fn default() -> Self {
+        ReverseDrop(Vec::new())
+    }
+}
+
+impl<H> From<Vec<H>> for ReverseDrop<H> {
+    fn from(handles: Vec<H>) -> Self {
+        ReverseDrop(handles)
+    }
+}
+
+impl<H> FromIterator<H> for ReverseDrop<H> {
+    fn from_iter<I: IntoIterator<Item = H>>(iter: I) -> Self {
+        ReverseDrop(iter.into_iter().collect())
+    }
+}
+
+impl<H> Deref for ReverseDrop<H> {
+    type Target = [H];
+    fn deref(&self) -> &[H] {
+        &self.0
+    }
+}
+
+impl<H> Drop for ReverseDrop<H> {
+    fn drop(&mut self) {
+        while let Some(handle) = self.0.pop() {
+            drop(handle);
+        }
+    }
+}
+
 /// A sequence installer.
 ///
 /// This is an auxiliary installer wrapper, to install instances from a collection of fragments.
 ///
 /// Usually, this is used behind the scenes in things like `Vec<F: Fragment>` and shouldn't have to
 /// be used by user directly.
+///
+/// The resources are uninstalled in the reverse order of installation (see [`ReverseDrop`]).
 #[derive(Debug, Default)]
 pub struct SeqInstaller<Slave> {
     slave: Slave,
@@ -186,13 +263,24 @@ where
     Resource: IntoIterator,
     Slave: Installer<Resource::Item, O, C>,
 {
-    type UninstallHandle = Vec<Slave::UninstallHandle>;
+    type UninstallHandle = ReverseDrop<Slave::UninstallHandle>;
     fn install(&mut self, resource: Resource, name: &'static str) -> Self::UninstallHandle {
         resource
             .into_iter()
             .map(|r| self.slave.install(r, name))
             .collect()
     }
+    fn try_install(
+        &mut self,
+        resource: Resource,
+        name: &'static str,
+    ) -> Result<Self::UninstallHandle, Error> {
+        // If one of them fails, dropping the handles of the already installed ones uninstalls them
+        resource
+            .into_iter()
+            .map(|r| self.slave.try_install(r, name))
+            .collect()
+    }
     fn init<B: Extensible<Opts = O, Config = C, Ok = B>>(
         &mut self,
         builder: B,
@@ -212,6 +300,54 @@ where
 /// configuration, in addition to implementing the [`Fragment`] trait, mark the fragment by this
 /// trait. Then a default implementation of [`Fragment`] will be provided for the common
 /// collections.
+///
+/// This includes maps (`HashMap<K, F>` and `BTreeMap<K, F>`), which are useful for named
+/// instances (eg. `[listen.api]` and `[listen.admin]` sections in the configuration). Entries of
+/// maps are tracked by their keys across configuration reloads (see
+/// [`MapDriver`][crate::fragment::driver::MapDriver]).
+///
+/// Forgetting to mark the fragment results in a compile error saying it is not marked as
+/// `Stackable` when used in a collection.
+///
+/// # Examples
+///
+/// ```rust
+/// use failure::Error;
+/// use spirit::fragment::{Fragment, Installer, Stackable};
+/// use spirit::simple_fragment;
+///
+/// # #[derive(Default)]
+/// # struct SignatureInstaller;
+/// # impl<O, C> Installer<String, O, C> for SignatureInstaller {
+/// #     type UninstallHandle = ();
+/// #     fn install(&mut self, _: String, _: &str) {}
+/// # }
+/// struct Signature {
+///     key: String,
+/// }
+///
+/// simple_fragment! {
+///     impl Fragment for Signature {
+///         type Resource = String;
+///         type Installer = SignatureInstaller;
+///         fn create(&self, _name: &'static str) -> Result<String, Error> {
+///             Ok(self.key.clone())
+///         }
+///     }
+/// }
+///
+/// // Multiple signatures make sense
+/// impl Stackable for Signature {}
+///
+/// // So a Vec of them is a fragment too
+/// fn fragment<F: Fragment>(_: F) {}
+/// fragment(vec![Signature { key: "abc".to_owned() }]);
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not marked as `Stackable`",
+    label = "`{Self}` can't be used in collections (`Vec`, `HashMap`...) or `Option`s of fragments",
+    note = "if it makes sense to have multiple instances, add `impl Stackable for {Self} {{}}`"
+)]
 pub trait Stackable {}
 
 /// A trait similar to [`Stackable`], but marking the ability to be optional.
@@ -336,36 +472,67 @@ pub trait Fragment: Sized {
     {
         Ok(builder)
     }
-}
 
-impl<'a, F> Fragment for &'a F
-where
-    F: Fragment,
-{
-    type Driver = RefDriver<F::Driver>;
-    type Installer = F::Installer;
-    type Seed = F::Seed;
-    type Resource = F::Resource;
-    const RUN_BEFORE_CONFIG: bool = F::RUN_BEFORE_CONFIG;
-    fn make_seed(&self, name: &'static str) -> Result<Self::Seed, Error> {
-        F::make_seed(*self, name)
-    }
-    fn make_resource(
-        &self,
-        seed: &mut Self::Seed,
-        name: &'static str,
-    ) -> Result<Self::Resource, Error> {
-        F::make_resource(*self, seed, name)
+    /// A stable identity of the fragment inside a sequence.
+    ///
+    /// When the fragment is part of a sequence (eg. `Vec<F>`), the instances from the new
+    /// configuration are matched with the old ones to reuse what is possible. By default, this
+    /// happens by the content (through [`Driver::maybe_cached`]). If the fragment returns an
+    /// identity (eg. a name or a port from the configuration), it is matched only with the old
+    /// instance with the same identity instead. This makes sure an instance that didn't change
+    /// keeps its resource even if other instances are added, removed or reordered.
+    ///
+    /// The default implementation returns `None`. Wrappers around other fragments should delegate
+    /// to the inner one.
+    fn identity(&self) -> Option<String> {
+        None
     }
-    fn init<B: Extensible<Ok = B>>(builder: B, name: &'static str) -> Result<B, Error>
-    where
-        B::Config: DeserializeOwned + Send + Sync + 'static,
-        B::Opts: StructOpt + Send + Sync + 'static,
-    {
-        F::init(builder, name)
+}
+
+macro_rules! fragment_for_ptr {
+    ($($ptr: ty),*) => {
+        $(
+            impl<'a, F> Fragment for $ptr
+            where
+                F: Fragment,
+            {
+                type Driver = RefDriver<F::Driver>;
+                type Installer = F::Installer;
+                type Seed = F::Seed;
+                type Resource = F::Resource;
+                const RUN_BEFORE_CONFIG: bool = F::RUN_BEFORE_CONFIG;
+                fn make_seed(&self, name: &'static str) -> Result<Self::Seed, Error> {
+                    F::make_seed(&**self, name)
+                }
+                fn make_resource(
+                    &self,
+                    seed: &mut Self::Seed,
+                    name: &'static str,
+                ) -> Result<Self::Resource, Error> {
+                    F::make_resource(&**self, seed, name)
+                }
+                fn init<B: Extensible<Ok = B>>(builder: B, name: &'static str) -> Result<B, Error>
+                where
+                    B::Config: DeserializeOwned + Send + Sync + 'static,
+                    B::Opts: StructOpt + Send + Sync + 'static,
+                {
+                    F::init(builder, name)
+                }
+                fn identity(&self) -> Option<String> {
+                    F::identity(&**self)
+                }
+            }
+        )*
     }
 }
 
+fragment_for_ptr!(&'a F, Box<F>, Arc<F>, Rc<F>);
+
+impl<F: Stackable> Stackable for &F {}
+impl<F: Stackable> Stackable for Box<F> {}
+impl<F: Stackable> Stackable for Arc<F> {}
+impl<F: Stackable> Stackable for Rc<F> {}
+
 // TODO: Export the macro for other containers?
 // TODO: The where-* should be where-?
 macro_rules! fragment_for_seq {
@@ -497,8 +664,56 @@ macro_rules! simple_fragment {
     }
 }
 
-// TODO: How do we stack maps, etc?
-// TODO: Arcs, Rcs, Mutexes, refs, ...
+macro_rules! fragment_for_map {
+    ($container: ident<$key: ident, $base: ident $(, $extra: ident)*> where $($bounds: tt)+) => {
+        impl<$key, $base: Fragment + Stackable + 'static $(, $extra)*> Fragment
+            for $container<$key, $base $(, $extra)*>
+        where
+            $key: Clone + Display + 'static,
+            $($bounds)+
+        {
+            type Driver = MapDriver<$key, $base, $base::Driver>;
+            type Installer = SeqInstaller<$base::Installer>;
+            type Seed = Vec<$base::Seed>;
+            type Resource = Vec<$base::Resource>;
+            const RUN_BEFORE_CONFIG: bool = $base::RUN_BEFORE_CONFIG;
+            fn make_seed(&self, name: &'static str) -> Result<Self::Seed, Error> {
+                self.iter()
+                    .map(|(k, i)| {
+                        i.make_seed(name)
+                            .with_context(|_| format!("Failed to create {}[{}]", name, k))
+                            .map_err(Error::from)
+                    })
+                    .collect()
+            }
+            fn make_resource(&self, seed: &mut Self::Seed, name: &'static str)
+                -> Result<Self::Resource, Error>
+            {
+                // The iteration order is the same as in make_seed, the map didn't change
+                self.iter()
+                    .zip(seed)
+                    .map(|((k, i), s)| {
+                        i.make_resource(s, name)
+                            .with_context(|_| format!("Failed to create {}[{}]", name, k))
+                            .map_err(Error::from)
+                    })
+                    .collect()
+            }
+            fn init<B: Extensible<Ok = B>>(builder: B, name: &'static str) -> Result<B, Error>
+            where
+                B::Config: DeserializeOwned + Send + Sync + 'static,
+                B::Opts: StructOpt + Send + Sync + 'static,
+            {
+                $base::init(builder, name)
+            }
+        }
+    }
+}
+
+fragment_for_map!(HashMap<K, T, S> where K: Eq + Hash, S: BuildHasher);
+fragment_for_map!(BTreeMap<K, T> where K: Eq + Hash + Ord);
+
+// TODO: Mutexes?
 
 /// A trait describing something that extracts a fragment from configuration and command line
 /// options.
@@ -614,3 +829,29 @@ pub trait Transformation<InputResource, InputInstaller, SubFragment> {
         name: &'static str,
     ) -> Result<Self::OutputResource, Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    struct Recorded<'a>(usize, &'a Mutex<Vec<usize>>);
+
+    impl Drop for Recorded<'_> {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    #[test]
+    fn reverse_drop() {
+        let dropped = Mutex::new(Vec::new());
+        let handles = (0..3)
+            .map(|i| Recorded(i, &dropped))
+            .collect::<ReverseDrop<_>>();
+        assert_eq!(3, handles.len());
+        drop(handles);
+        assert_eq!(vec![2, 1, 0], *dropped.lock().unwrap());
+    }
+}
@@ -9,9 +9,11 @@
 //! documentation to provide some guidance and clickable links.
 //!
 //! [`Pipeline`]: crate::fragment::pipeline::Pipeline
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 
 use failure::{Backtrace, Error, Fail};
@@ -20,7 +22,7 @@ use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use structopt::StructOpt;
 
-use super::driver::{CacheId, Driver, Instruction};
+use super::driver::{CacheBy, CacheId, Driver, Instruction};
 use super::{Extractor, Fragment, Installer, Transformation};
 use crate::extension::{Extensible, Extension};
 use crate::validation::Action;
@@ -86,9 +88,12 @@ impl Fail for MultiError {
     }
 }
 
-struct InstallCache<I, O, C, R, H> {
+pub(crate) struct InstallCache<I, O, C, R, H> {
     installer: I,
-    cache: HashMap<CacheId, H>,
+    // The handles are kept with the order of installation. The IDs are recycled, so they don't
+    // tell the order.
+    cache: HashMap<CacheId, (u64, H)>,
+    installed: u64,
     _type: PhantomData<(R, O, C)>,
 }
 
@@ -96,21 +101,67 @@ impl<I, O, C, R> InstallCache<I, O, C, R, I::UninstallHandle>
 where
     I: Installer<R, O, C>,
 {
-    fn new(installer: I) -> Self {
+    pub(crate) fn new(installer: I) -> Self {
         Self {
             installer,
             cache: HashMap::new(),
+            installed: 0,
             _type: PhantomData,
         }
     }
-    fn interpret(&mut self, instruction: Instruction<R>, name: &'static str) {
-        match instruction {
-            Instruction::DropAll => self.cache.clear(),
-            Instruction::DropSpecific(id) => assert!(self.cache.remove(&id).is_some()),
-            Instruction::Install { id, resource } => {
-                let handle = self.installer.install(resource, name);
-                assert!(self.cache.insert(id, handle).is_none());
+    /// The handles of the active resources, in the order of installation.
+    #[cfg(feature = "test-util")]
+    pub(crate) fn handles(&self) -> Vec<&I::UninstallHandle> {
+        let mut handles = self.cache.values().collect::<Vec<_>>();
+        handles.sort_by_key(|(order, _)| *order);
+        handles.into_iter().map(|(_, handle)| handle).collect()
+    }
+    /// Performs one batch of instructions.
+    ///
+    /// The batch either succeeds as a whole or is rolled back. The old resources are removed only
+    /// after all the new ones got installed, so they can be put back if an installation fails (or
+    /// panics, in which case the panic is propagated after the rollback).
+    pub(crate) fn interpret(
+        &mut self,
+        instructions: Vec<Instruction<R>>,
+        name: &'static str,
+    ) -> Result<(), Error> {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), Error> {
+            for instruction in instructions {
+                match instruction {
+                    Instruction::DropAll => removed.extend(self.cache.drain()),
+                    Instruction::DropSpecific(id) => {
+                        let handle = self.cache.remove(&id).expect("Dropping missing resource");
+                        removed.push((id, handle));
+                    }
+                    Instruction::Install { id, resource } => {
+                        let handle = self.installer.try_install(resource, name)?;
+                        self.installed += 1;
+                        let previous = self.cache.insert(id, (self.installed, handle));
+                        assert!(previous.is_none());
+                        added.push(id);
+                    }
+                }
             }
+            Ok(())
+        }));
+        if let Ok(Ok(())) = result {
+            // Only now we can get rid of the old ones. The later ones may depend on the earlier ones,
+            // so tear them down in reverse order of installation.
+            removed.sort_by(|(_, (a, _)), (_, (b, _))| b.cmp(a));
+            drop(removed);
+            return Ok(());
+        }
+        debug!("Rolling back installation of pipeline {}", name);
+        for id in added {
+            self.cache.remove(&id);
+        }
+        self.cache.extend(removed);
+        match result {
+            Ok(result) => result,
+            Err(panic) => panic::resume_unwind(panic),
         }
     }
 }
@@ -288,9 +339,12 @@ where
 ///   is sometimes necessary, but sometimes either the [`Fragment`] or one of the
 ///   [`Transformation`]s provides one.
 ///
+/// The [`on_error`][Pipeline::on_error] callback can be set at any point.
+///
 /// [`Resource`]: Fragment::Resource
 pub struct Pipeline<Fragment, Extractor, Driver, Transformation, SpiritType> {
     name: &'static str,
+    on_error: Option<ErrorCallback>,
     _fragment: PhantomData<Fn(Fragment)>,
     _spirit: PhantomData<Fn(SpiritType)>,
     extractor: Extractor,
@@ -298,15 +352,57 @@ pub struct Pipeline<Fragment, Extractor, Driver, Transformation, SpiritType> {
     transformation: Transformation,
 }
 
+/// A callback observing the errors of creating the resources.
+type ErrorCallback = Arc<dyn Fn(&[Error]) + Send + Sync>;
+
+impl<F, E, D, T, S> Pipeline<F, E, D, T, S> {
+    /// Sets a callback to observe the failures to create the resources.
+    ///
+    /// If creating the resources of the pipeline (by the [`Fragment`] or one of the
+    /// [`Transformation`]s) fails, the configuration is refused. The callback is called with the
+    /// errors before that happens, so the application can react to them (for example by counting
+    /// them in its metrics), without having to dig them out of the logged errors.
+    ///
+    /// Note that the errors of installing the resources are not passed to the callback, as these
+    /// happen only after the configuration has been accepted.
+    pub fn on_error<C>(self, callback: C) -> Self
+    where
+        C: Fn(&[Error]) + Send + Sync + 'static,
+    {
+        Pipeline {
+            on_error: Some(Arc::new(callback)),
+            ..self
+        }
+    }
+}
+
 impl Pipeline<(), (), (), (), ()> {
     /// Starts creating a new pipeline.
     ///
     /// This initializes a completely useless and empty pipeline. It only sets the name, but other
     /// properties (at least the [`Extractor`]) need to be set for the [`Pipeline`] to be of any
     /// practical use.
-    pub fn new(name: &'static str) -> Self {
+    ///
+    /// The name is usually a string literal, but it can also be computed at runtime (for example
+    /// to create a pipeline for each tenant). As the name is passed around as `&'static str`, a
+    /// dynamic name is interned ‒ each distinct name is kept in memory forever, so re-creating
+    /// pipelines of the same name is fine, but there shouldn't be an unbounded number of different
+    /// ones.
+    ///
+    /// ```rust
+    /// use spirit::Pipeline;
+    ///
+    /// let tenant = "acme";
+    /// let _pipeline = Pipeline::new(format!("listen-{}", tenant));
+    /// ```
+    pub fn new<N: Into<Cow<'static, str>>>(name: N) -> Self {
+        let name = match name.into() {
+            Cow::Borrowed(name) => name,
+            Cow::Owned(name) => crate::utils::intern(name),
+        };
         Self {
             name,
+            on_error: None,
             _fragment: PhantomData,
             _spirit: PhantomData,
             extractor: (),
@@ -341,6 +437,7 @@ impl Pipeline<(), (), (), (), ()> {
         trace!("Configured extractor on pipeline {}", self.name);
         Pipeline {
             name: self.name,
+            on_error: self.on_error,
             _fragment: PhantomData,
             _spirit: PhantomData,
             extractor: e,
@@ -366,6 +463,7 @@ impl Pipeline<(), (), (), (), ()> {
         trace!("Configured extractor on pipeline {}", self.name);
         Pipeline {
             name: self.name,
+            on_error: self.on_error,
             _fragment: PhantomData,
             _spirit: PhantomData,
             extractor: CfgExtractor(e),
@@ -392,6 +490,96 @@ where
         Pipeline {
             driver,
             name: self.name,
+            on_error: self.on_error,
+            _fragment: PhantomData,
+            _spirit: PhantomData,
+            extractor: self.extractor,
+            transformation: self.transformation,
+        }
+    }
+
+    /// Keeps the resources as long as a key derived from the fragment doesn't change.
+    ///
+    /// This wraps the current [`Driver`] into [`CacheBy`]. If the key of the new fragment is
+    /// the same as of the previous one, the resources stay as they are, even if other parts of the
+    /// fragment changed. Otherwise, the original driver decides as usual.
+    ///
+    /// This is useful to ignore fields that don't influence the resource (like a comment).
+    ///
+    /// The closure gets a reference to the extracted fragment. If the fragment is itself a
+    /// reference into the configuration, that makes it a reference to a reference and its type
+    /// needs to be spelled out, so the closure works with any lifetime of the configuration.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use serde::Deserialize;
+    /// use spirit::prelude::*;
+    /// use spirit::fragment::driver::CacheEq;
+    /// use spirit::fragment::Installer;
+    /// # use failure::Error;
+    ///
+    /// #[derive(Default)]
+    /// struct WorkerInstaller;
+    ///
+    /// impl<O, C> Installer<usize, O, C> for WorkerInstaller {
+    ///     type UninstallHandle = ();
+    ///     fn install(&mut self, threads: usize, _: &str) {
+    ///         println!("Starting {} threads", threads);
+    ///     }
+    /// }
+    ///
+    /// #[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+    /// struct Worker {
+    ///     threads: usize,
+    ///     #[serde(default)]
+    ///     comment: String,
+    /// }
+    ///
+    /// impl Fragment for Worker {
+    ///     type Driver = CacheEq<Worker>;
+    ///     type Installer = WorkerInstaller;
+    ///     type Seed = ();
+    ///     type Resource = usize;
+    ///     fn make_seed(&self, _: &str) -> Result<(), Error> {
+    ///         Ok(())
+    ///     }
+    ///     fn make_resource(&self, _: &mut (), _: &str) -> Result<usize, Error> {
+    ///         Ok(self.threads)
+    ///     }
+    /// }
+    ///
+    /// #[derive(Clone, Debug, Default, Deserialize)]
+    /// struct Cfg {
+    ///     #[serde(default)]
+    ///     worker: Worker,
+    /// }
+    ///
+    /// impl Cfg {
+    ///     fn worker(&self) -> &Worker {
+    ///         &self.worker
+    ///     }
+    /// }
+    ///
+    /// Spirit::<Empty, Cfg>::new()
+    ///     .with(
+    ///         Pipeline::new("worker")
+    ///             .extract_cfg(Cfg::worker)
+    ///             // Changing just the comment doesn't restart the workers
+    ///             .compare_by(|worker: &&Worker| worker.threads),
+    ///     )
+    ///     .run(|_| Ok(()));
+    /// ```
+    pub fn compare_by<K, KF>(self, key: KF) -> Pipeline<F, E, CacheBy<D, K, KF>, T, (O, C)>
+    where
+        D: Driver<F>,
+        T: Transformation<<D::SubFragment as Fragment>::Resource, F::Installer, D::SubFragment>,
+    {
+        trace!("Comparing by a key on pipeline {}", self.name);
+        Pipeline {
+            driver: CacheBy::new(self.driver, key),
+            name: self.name,
+            on_error: self.on_error,
             _fragment: PhantomData,
             _spirit: PhantomData,
             extractor: self.extractor,
@@ -425,6 +613,7 @@ where
         trace!("Adding a transformation to pipeline {}", self.name);
         Pipeline {
             name: self.name,
+            on_error: self.on_error,
             _fragment: PhantomData,
             _spirit: PhantomData,
             driver: self.driver,
@@ -450,6 +639,7 @@ where
         trace!("Adding a map transformation to pipeline {}", self.name);
         Pipeline {
             name: self.name,
+            on_error: self.on_error,
             _fragment: PhantomData,
             _spirit: PhantomData,
             driver: self.driver,
@@ -469,6 +659,7 @@ where
         trace!("Setting installer to pipeline {}", self.name);
         Pipeline {
             name: self.name,
+            on_error: self.on_error,
             _fragment: PhantomData,
             _spirit: PhantomData,
             driver: self.driver,
@@ -574,10 +765,23 @@ where
                 instructions.len(),
             );
             let mut me = me_s.lock();
-            me.driver.confirm(name);
             let name = me.name;
-            for ins in instructions {
-                me.install_cache.interpret(ins, name);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                me.install_cache.interpret(instructions, name)
+            }));
+            match result {
+                Ok(Ok(())) => me.driver.confirm(name),
+                Err(panic) => {
+                    me.driver.abort(name);
+                    panic::resume_unwind(panic);
+                }
+                Ok(Err(e)) => {
+                    // The old resources are still in place, so the driver needs to return to them
+                    // too.
+                    me.driver.abort(name);
+                    let e = e.context(format!("Failed to install resources of pipeline {}", name));
+                    crate::log_error!(multi Error, e.into());
+                }
             }
         };
         Ok(Action::new().on_abort(failure).on_success(success))
@@ -626,19 +830,133 @@ where
         };
         let compiled = Arc::new(Mutex::new(compiled));
         let name = self.name;
+        let on_error = self.on_error;
+        let failed = move |errs: Vec<Error>| {
+            if let Some(on_error) = on_error.as_ref() {
+                on_error(&errs);
+            }
+            MultiError::wrap(errs, name)
+        };
+        let failed = Arc::new(failed);
         if F::RUN_BEFORE_CONFIG && !B::STARTED {
             let compiled = Arc::clone(&compiled);
+            let failed = Arc::clone(&failed);
             let before_config = move |cfg: &B::Config, opts: &B::Opts| {
                 BoundedCompiledPipeline::run(&compiled, opts, cfg)
                     .map(|action| action.run(true))
-                    .map_err(|errs| MultiError::wrap(errs, name))
+                    .map_err(|errs| failed(errs))
             };
             builder = builder.before_config(before_config)?;
         }
         let validator = move |_old: &_, cfg: &Arc<B::Config>, opts: &B::Opts| {
-            BoundedCompiledPipeline::run(&compiled, opts, cfg)
-                .map_err(|errs| MultiError::wrap(errs, name))
+            BoundedCompiledPipeline::run(&compiled, opts, cfg).map_err(|errs| failed(errs))
         };
         builder.config_validator(validator)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use failure::err_msg;
+
+    use super::*;
+    use crate::fragment::driver::IdGen;
+
+    /// Counts the installed resources, refuses to install 0 and panics on 42.
+    #[derive(Default)]
+    struct CountingInstaller(Arc<AtomicUsize>);
+
+    struct Handle(Arc<AtomicUsize>);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    impl Installer<usize, (), ()> for CountingInstaller {
+        type UninstallHandle = Handle;
+        fn install(&mut self, resource: usize, name: &'static str) -> Handle {
+            self.try_install(resource, name).unwrap()
+        }
+        fn try_install(&mut self, resource: usize, _: &'static str) -> Result<Handle, Error> {
+            match resource {
+                0 => Err(err_msg("Refusing 0")),
+                42 => panic!("Panicking on 42"),
+                _ => {
+                    self.0.fetch_add(1, Ordering::Relaxed);
+                    Ok(Handle(Arc::clone(&self.0)))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rollback() {
+        let installer = CountingInstaller::default();
+        let count = Arc::clone(&installer.0);
+        let mut cache = InstallCache::<_, (), (), usize, _>::new(installer);
+        let mut ids = IdGen::default();
+        let first = ids.next().unwrap();
+        let second = ids.next().unwrap();
+        cache
+            .interpret(
+                vec![
+                    Instruction::Install {
+                        id: first,
+                        resource: 1,
+                    },
+                    Instruction::Install {
+                        id: second,
+                        resource: 2,
+                    },
+                ],
+                "test",
+            )
+            .unwrap();
+        assert_eq!(2, count.load(Ordering::Relaxed));
+
+        // Fails in the middle, nothing changes
+        let failing = vec![
+            Instruction::DropSpecific(first),
+            Instruction::Install {
+                id: ids.next().unwrap(),
+                resource: 3,
+            },
+            Instruction::Install {
+                id: ids.next().unwrap(),
+                resource: 0,
+            },
+        ];
+        assert!(cache.interpret(failing, "test").is_err());
+        assert_eq!(2, count.load(Ordering::Relaxed));
+        assert!(cache.cache.contains_key(&first));
+        assert!(cache.cache.contains_key(&second));
+
+        let panicking = vec![
+            Instruction::DropAll,
+            Instruction::Install {
+                id: ids.next().unwrap(),
+                resource: 42,
+            },
+        ];
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cache.interpret(panicking, "test")));
+        assert!(result.is_err());
+        assert_eq!(2, count.load(Ordering::Relaxed));
+        assert_eq!(2, cache.cache.len());
+
+        // And a successful one gets rid of the old ones
+        let replace = vec![
+            Instruction::DropAll,
+            Instruction::Install {
+                id: ids.next().unwrap(),
+                resource: 5,
+            },
+        ];
+        cache.interpret(replace, "test").unwrap();
+        assert_eq!(1, count.load(Ordering::Relaxed));
+        assert_eq!(1, cache.cache.len());
+    }
+}
@@ -0,0 +1,266 @@
+//! Helpers for testing [`Driver`]s.
+//!
+//! Writing a custom [`Driver`] means getting the caching right ‒ it should reuse as much as
+//! possible, but never keep a resource that doesn't match the new configuration. The
+//! [`DriverTester`] runs the driver the same way a [`Pipeline`][crate::Pipeline] would, but
+//! instead of installing the resources, it only records what was installed and dropped, so the
+//! test can check the driver did the minimal set of changes.
+//!
+//! Available with the `test-util` feature.
+
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+
+use failure::Error;
+use parking_lot::Mutex;
+
+use super::driver::Driver;
+use super::pipeline::{InstallCache, NopTransformation};
+use super::{Fragment, Installer};
+
+/// The resources the driver `D` produces for the fragment `F`.
+pub type DriverResource<F, D> = <<D as Driver<F>>::SubFragment as Fragment>::Resource;
+
+/// What happened to the resources during one update.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Changes<R> {
+    /// The resources that got installed, in the order of installation.
+    pub installed: Vec<R>,
+    /// The resources that got removed, in the order of removal.
+    pub dropped: Vec<R>,
+}
+
+type Record<R> = Arc<Mutex<Vec<R>>>;
+
+/// An [`Installer`] that only writes down what it installs.
+struct Recorder<R> {
+    installed: Record<R>,
+    dropped: Record<R>,
+}
+
+/// A handle of a resource installed by the [`Recorder`], writing down its removal.
+struct Recorded<R: Clone> {
+    resource: R,
+    dropped: Record<R>,
+}
+
+impl<R: Clone> Drop for Recorded<R> {
+    fn drop(&mut self) {
+        self.dropped.lock().push(self.resource.clone());
+    }
+}
+
+impl<R, O, C> Installer<R, O, C> for Recorder<R>
+where
+    R: Clone + Send + 'static,
+{
+    type UninstallHandle = Recorded<R>;
+    fn install(&mut self, resource: R, _: &'static str) -> Recorded<R> {
+        self.installed.lock().push(resource.clone());
+        Recorded {
+            resource,
+            dropped: Arc::clone(&self.dropped),
+        }
+    }
+}
+
+/// Feeds a [`Driver`] with fragments and records the resulting installations and removals.
+///
+/// The resources are passed through unchanged (no [`Transformation`][super::Transformation] is
+/// applied) and are kept in a cache the same way the [`Pipeline`][crate::Pipeline] does, so the
+/// tester also checks the driver issues valid [`Instruction`][super::driver::Instruction]s (it
+/// panics if the driver tries to drop a resource that isn't installed, for example).
+///
+/// # Examples
+///
+/// ```rust
+/// use failure::Error;
+/// use spirit::fragment::driver::CacheEq;
+/// use spirit::fragment::test_util::DriverTester;
+/// use spirit::fragment::{Fragment, Stackable};
+///
+/// #[derive(Clone, Debug, Eq, PartialEq)]
+/// struct Port(u16);
+///
+/// impl Stackable for Port {}
+///
+/// impl Fragment for Port {
+///     type Driver = CacheEq<Port>;
+///     type Installer = ();
+///     type Seed = ();
+///     type Resource = u16;
+///     fn make_seed(&self, _: &'static str) -> Result<(), Error> {
+///         Ok(())
+///     }
+///     fn make_resource(&self, _: &mut (), _: &'static str) -> Result<u16, Error> {
+///         Ok(self.0)
+///     }
+/// }
+///
+/// let mut tester = DriverTester::<Vec<Port>>::default();
+/// let changes = tester.update(&vec![Port(1), Port(2)]).unwrap();
+/// assert_eq!(vec![1, 2], changes.installed);
+/// // Only the changed one is replaced
+/// let changes = tester.update(&vec![Port(2), Port(3)]).unwrap();
+/// assert_eq!(vec![3], changes.installed);
+/// assert_eq!(vec![1], changes.dropped);
+/// assert_eq!(vec![2, 3], tester.installed());
+/// ```
+pub struct DriverTester<F, D = <F as Fragment>::Driver>
+where
+    F: Fragment,
+    D: Driver<F>,
+    DriverResource<F, D>: Clone + Send + 'static,
+{
+    driver: D,
+    cache: InstallCache<
+        Recorder<DriverResource<F, D>>,
+        (),
+        (),
+        DriverResource<F, D>,
+        Recorded<DriverResource<F, D>>,
+    >,
+    installed: Record<DriverResource<F, D>>,
+    dropped: Record<DriverResource<F, D>>,
+    _fragment: PhantomData<fn(&F)>,
+}
+
+const NAME: &str = "test";
+
+impl<F, D> DriverTester<F, D>
+where
+    F: Fragment,
+    D: Driver<F>,
+    DriverResource<F, D>: Clone + Send + 'static,
+{
+    /// Creates the tester around the driver.
+    pub fn new(driver: D) -> Self {
+        let installed = Record::default();
+        let dropped = Record::default();
+        let recorder = Recorder {
+            installed: Arc::clone(&installed),
+            dropped: Arc::clone(&dropped),
+        };
+        Self {
+            driver,
+            cache: InstallCache::new(recorder),
+            installed,
+            dropped,
+            _fragment: PhantomData,
+        }
+    }
+
+    /// Updates to a new fragment, as on a configuration reload.
+    ///
+    /// The changes are confirmed to the driver. If the driver refuses the fragment (because
+    /// creating a resource failed), it is aborted instead and the errors are returned.
+    pub fn update(&mut self, fragment: &F) -> Result<Changes<DriverResource<F, D>>, Vec<Error>> {
+        let instructions =
+            match self
+                .driver
+                .instructions::<_, ()>(fragment, &mut NopTransformation, NAME)
+            {
+                Ok(instructions) => instructions,
+                Err(errs) => {
+                    self.driver.abort(NAME);
+                    return Err(errs);
+                }
+            };
+        if let Err(e) = self.cache.interpret(instructions, NAME) {
+            self.driver.abort(NAME);
+            return Err(vec![e]);
+        }
+        self.driver.confirm(NAME);
+        Ok(Changes {
+            installed: mem::take(&mut *self.installed.lock()),
+            dropped: mem::take(&mut *self.dropped.lock()),
+        })
+    }
+
+    /// Lets the driver prepare an update to the fragment, but then aborts it.
+    ///
+    /// This is what happens when some other part of the configuration is refused. Nothing gets
+    /// installed and the driver is supposed to return to its previous state. The errors of
+    /// preparing the update are returned.
+    pub fn abort(&mut self, fragment: &F) -> Result<(), Vec<Error>> {
+        let result = self
+            .driver
+            .instructions::<_, ()>(fragment, &mut NopTransformation, NAME)
+            .map(drop);
+        self.driver.abort(NAME);
+        result
+    }
+
+    /// The currently installed resources, in the order of installation.
+    pub fn installed(&self) -> Vec<DriverResource<F, D>> {
+        self.cache
+            .handles()
+            .into_iter()
+            .map(|handle| handle.resource.clone())
+            .collect()
+    }
+
+    /// Access to the driver, to examine its state.
+    pub fn driver(&self) -> &D {
+        &self.driver
+    }
+}
+
+/// A tester of the fragment's own driver.
+impl<F> Default for DriverTester<F, F::Driver>
+where
+    F: Fragment,
+    DriverResource<F, F::Driver>: Clone + Send + 'static,
+{
+    fn default() -> Self {
+        Self::new(F::Driver::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::err_msg;
+
+    use super::*;
+    use crate::fragment::driver::CacheEq;
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Num(u8);
+
+    impl Fragment for Num {
+        type Driver = CacheEq<Num>;
+        type Installer = ();
+        type Seed = ();
+        type Resource = u8;
+        fn make_seed(&self, _: &'static str) -> Result<(), Error> {
+            Ok(())
+        }
+        fn make_resource(&self, _: &mut (), _: &'static str) -> Result<u8, Error> {
+            match self.0 {
+                0 => Err(err_msg("Refusing 0")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn records() {
+        let mut tester = DriverTester::<Num>::default();
+        let changes = tester.update(&Num(1)).unwrap();
+        assert_eq!(vec![1], changes.installed);
+        assert!(changes.dropped.is_empty());
+        // Cached, nothing happens
+        let changes = tester.update(&Num(1)).unwrap();
+        assert!(changes.installed.is_empty());
+        assert!(changes.dropped.is_empty());
+        assert!(tester.update(&Num(0)).is_err());
+        assert_eq!(vec![1], tester.installed());
+        tester.abort(&Num(2)).unwrap();
+        assert_eq!(vec![1], tester.installed());
+        let changes = tester.update(&Num(2)).unwrap();
+        assert_eq!(vec![2], changes.installed);
+        assert_eq!(vec![1], changes.dropped);
+        assert_eq!(vec![2], tester.installed());
+    }
+}
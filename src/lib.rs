@@ -377,8 +377,8 @@
 //!
 //! # Features
 //!
-//! There are several features that can tweak functionality. Currently, they are all *on* by
-//! default, but they can be opted out of. All the other spirit crates depend only on the bare
+//! There are several features that can tweak functionality. Most of them are *on* by default, but
+//! they can be opted out of. All the other spirit crates depend only on the bare
 //! minimum of features they need.
 //!
 //! * `ini`, `json`, `hjson`, `yaml`: support for given configuration formats.
@@ -386,6 +386,9 @@
 //!   by the [`spirit-cfg-helpers`] crate to add the `--help-config` command line option. It is
 //!   implemented by the [`structdoc`] crate behind the scenes. On by default. This feature flag is
 //!   actually available in all the other sub-crates too.
+//! * `test-util`: helpers for testing custom [`Driver`][fragment::driver::Driver]s, in the
+//!   `fragment::test_util` module. Off by default, usually turned on only in the
+//!   `dev-dependencies`.
 //!
 //! # Other documentation
 //!
@@ -39,6 +39,7 @@ struct Hooks<O, C> {
     config_loader: CfgLoader,
     config_mutators: Vec<Box<dyn FnMut(&mut C) + Send>>,
     config_validators: Vec<Box<dyn FnMut(&Arc<C>, &Arc<C>, &O) -> Result<Action, Error> + Send>>,
+    reload: Vec<Box<dyn FnMut(&Result<(), Error>) + Send>>,
     sigs: HashMap<libc::c_int, Vec<Box<dyn FnMut() + Send>>>,
     singletons: HashSet<TypeId>,
     terminate: Vec<Box<dyn FnMut() + Send>>,
@@ -55,6 +56,7 @@ impl<O, C> Default for Hooks<O, C> {
             config_loader: CfgBuilder::new().build_no_opts(),
             config_mutators: Vec::new(),
             config_validators: Vec::new(),
+            reload: Vec::new(),
             sigs: HashMap::new(),
             singletons: HashSet::new(),
             terminate: Vec::new(),
@@ -149,6 +151,7 @@ where
             config_mutators: Vec::new(),
             config_validators: Vec::new(),
             opts: PhantomData,
+            reload_hooks: Vec::new(),
             sig_hooks: HashMap::new(),
             singletons: HashSet::new(),
             terminate_hooks: Vec::new(),
@@ -215,6 +218,9 @@ where
     ///
     /// If any step fails, it is aborted and the old configuration is preserved.
     ///
+    /// In either case, the [`on_reload`][Extensible::on_reload] callbacks are informed about the
+    /// outcome.
+    ///
     /// # Warning
     ///
     /// The Spirit allows to run only one callback at a time (even from multiple threads), to make
@@ -222,6 +228,16 @@ where
     /// don't have to by `Sync`). That, however, means that you can't call `config_reload` or
     /// [`terminate`][Spirit::terminate] from any callback as that would lead to a deadlock.
     pub fn config_reload(&self) -> Result<(), Error> {
+        let result = self.config_reload_inner();
+        let mut hooks = self.hooks.lock();
+        debug!("Running {} reload hooks", hooks.reload.len());
+        for hook in &mut hooks.reload {
+            hook(&result);
+        }
+        result
+    }
+
+    fn config_reload_inner(&self) -> Result<(), Error> {
         let mut new = self.load_config().context("Failed to load configuration")?;
         // The lock here is across the whole processing, to avoid potential races in logic
         // processing. This makes writing the hooks correctly easier.
@@ -473,6 +489,18 @@ where
         self
     }
 
+    fn on_reload<F>(self, hook: F) -> Self
+    where
+        F: FnMut(&Result<(), Error>) + Send + 'static,
+    {
+        trace!("Adding reload hook at runtime");
+        let mut hooks = self.hooks.lock();
+        if !hooks.terminated {
+            hooks.reload.push(Box::new(hook));
+        }
+        self
+    }
+
     fn on_signal<F>(self, signal: libc::c_int, hook: F) -> Result<Self, Error>
     where
         F: FnMut() + Send + 'static,
@@ -581,6 +609,7 @@ pub struct Builder<O = Empty, C = Empty> {
     config_mutators: Vec<Box<dyn FnMut(&mut C) + Send>>,
     config_validators: Vec<Box<dyn FnMut(&Arc<C>, &Arc<C>, &O) -> Result<Action, Error> + Send>>,
     opts: PhantomData<O>,
+    reload_hooks: Vec<Box<dyn FnMut(&Result<(), Error>) + Send>>,
     sig_hooks: HashMap<libc::c_int, Vec<Box<dyn FnMut() + Send>>>,
     singletons: HashSet<TypeId>,
     terminate_hooks: Vec<Box<dyn FnMut() + Send>>,
@@ -687,6 +716,18 @@ impl<O, C> Extensible for Builder<O, C> {
         }
     }
 
+    fn on_reload<F>(self, hook: F) -> Self
+    where
+        F: FnMut(&Result<(), Error>) + Send + 'static,
+    {
+        let mut hooks = self.reload_hooks;
+        hooks.push(Box::new(hook));
+        Self {
+            reload_hooks: hooks,
+            ..self
+        }
+    }
+
     fn on_signal<F>(self, signal: libc::c_int, hook: F) -> Result<Self, Error>
     where
         F: FnMut() + Send + 'static,
@@ -888,6 +929,7 @@ where
                 config_loader: loader,
                 config_mutators: self.config_mutators,
                 config_validators: self.config_validators,
+                reload: self.reload_hooks,
                 sigs: self.sig_hooks,
                 singletons: self.singletons,
                 terminate: self.terminate_hooks,
@@ -3,12 +3,14 @@
 //! All the little things that are useful through the spirit's or user's code, and don't really fit
 //! anywhere else.
 
+use std::collections::BTreeSet;
 use std::env;
 use std::ffi::OsStr;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use failure::{Error, Fail};
@@ -379,6 +381,22 @@ pub fn serialize_opt_duration<S: Serializer>(
     }
 }
 
+/// Turns a name computed at runtime into a `&'static str`.
+///
+/// The names need to live forever, so the string is leaked. But each distinct name is leaked only
+/// once, so re-creating things with the same name doesn't leak more memory.
+pub(crate) fn intern(name: String) -> &'static str {
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    // A panic while inserting can't leave the set in an inconsistent state
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(interned) = names.get(name.as_str()) {
+        return interned;
+    }
+    let interned = &*Box::leak(name.into_boxed_str());
+    names.insert(interned);
+    interned
+}
+
 #[cfg(test)]
 mod tests {
     use std::ffi::OsString;
@@ -387,6 +405,14 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn interned() {
+        let first = intern("interned-name".to_owned());
+        let second = intern(format!("interned-{}", "name"));
+        assert_eq!("interned-name", first);
+        assert!(std::ptr::eq(first, second));
+    }
+
     #[test]
     fn abs() {
         let current = env::current_dir().unwrap();